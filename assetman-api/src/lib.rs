@@ -1,14 +1,86 @@
+pub use rust_decimal::{Decimal, RoundingStrategy};
 use serde::{Deserialize, Serialize};
-use std::fmt::{Debug, Display, Formatter};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Request {
     pub arguments: String,
+    /// If set the plugin is asked for a `Value::Series` of historic values in that range instead
+    /// of the current value. Only some plugins support this, others will answer with an error or
+    /// a single number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<TimeRange>,
+}
+
+/// Time range given as unix timestamps in seconds, both ends are inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct TimeRange {
+    pub start: i64,
+    pub end: i64,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Answer {
-    pub answer: f64,
+    pub answer: Value,
+}
+
+/// Value returned by a plugin, which variant is expected depends on the plugin's type (see
+/// `PluginType::accepts`). Numbers are exact decimals and serialized as strings, but plugins may
+/// also send JSON numbers, which are read via their shortest decimal representation.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Number(Decimal),
+    Metadata(BTreeMap<String, String>),
+    Series(Vec<DataPoint>),
+}
+
+/// One point of a time series, `timestamp` is a unix timestamp in seconds
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DataPoint {
+    pub timestamp: i64,
+    pub value: Decimal,
+}
+
+impl Value {
+    pub fn number(&self) -> Option<Decimal> {
+        match self {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn metadata(&self) -> Option<&BTreeMap<String, String>> {
+        match self {
+            Value::Metadata(metadata) => Some(metadata),
+            _ => None,
+        }
+    }
+
+    pub fn series(&self) -> Option<&[DataPoint]> {
+        match self {
+            Value::Series(series) => Some(series),
+            _ => None,
+        }
+    }
+}
+
+impl From<Decimal> for Value {
+    fn from(number: Decimal) -> Self {
+        Value::Number(number)
+    }
+}
+
+/// Converts a float to a decimal the same way JSON numbers sent by plugins are read, i.e. `0.1`
+/// becomes exactly `0.1`. Returns `None` for values that aren't finite or out of range.
+pub fn from_float(number: f64) -> Option<Decimal> {
+    if number.is_finite() {
+        Decimal::from_str(&number.to_string()).ok()
+    } else {
+        None
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -21,20 +93,55 @@ impl std::error::Error for Error {}
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        Debug::fmt(self, f)
+        write!(f, "error {}: {}", self.code, self.description)
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PluginInfo {
     pub name: String,
     pub plugin_type: PluginType,
     pub description: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
 pub enum PluginType {
+    /// Amount of an asset held, a `Value::Number`
     Holdings,
+    /// Price of one unit of an asset, a `Value::Number`
     Price,
+    /// Exchange rate between two currencies, a `Value::Number`
+    FxRate,
+    /// Outstanding amount of a debt, a `Value::Number`
+    Liabilities,
+    /// Descriptive data like ISIN or display name, a `Value::Metadata`
+    Metadata,
+    /// Historic prices of an asset, a `Value::Series`
+    PriceHistory,
+    /// Plugin can answer queries of every type
     Any,
 }
+
+impl PluginType {
+    /// Returns true if a plugin of this type may be asked for values of the `requested` type
+    pub fn provides(&self, requested: PluginType) -> bool {
+        *self == PluginType::Any || requested == PluginType::Any || *self == requested
+    }
+
+    /// Returns true if `value` has the shape expected from a query of this type
+    pub fn accepts(&self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (PluginType::Any, _)
+                | (PluginType::Metadata, Value::Metadata(_))
+                | (PluginType::PriceHistory, Value::Series(_))
+                | (
+                    PluginType::Holdings
+                        | PluginType::Price
+                        | PluginType::FxRate
+                        | PluginType::Liabilities,
+                    Value::Number(_),
+                )
+        )
+    }
+}
@@ -0,0 +1,258 @@
+//! Scriptable mock plugins for testing code that talks to assetman plugins.
+//!
+//! A `MockPlugin` speaks the same JSON protocol as a real plugin, but instead of running as a
+//! child process it is served from a thread over a pair of unix sockets. Answers are scripted per
+//! argument string, so tests can simulate well-behaved plugins as well as errors, slow answers,
+//! crashes and malformed output.
+//!
+//! ```no_run
+//! use assetman_api::{Decimal, PluginType};
+//! use assetman_mock::MockPlugin;
+//! use std::time::Duration;
+//!
+//! let (reader, writer) = MockPlugin::new("mock_p", PluginType::Price)
+//!     .answer("BTCEUR", Decimal::new(10_000, 0))
+//!     .error("XXXEUR", 3, "unknown pair")
+//!     .delay("ETHEUR", Duration::from_millis(100), Decimal::new(300, 0))
+//!     .crash("boom")
+//!     .spawn()
+//!     .unwrap();
+//! ```
+
+use assetman_api::{Answer, PluginInfo, PluginType, Request, Value};
+use serde_json::de::Deserializer;
+use serde_json::to_writer;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// What a mock plugin does when it receives a certain request
+#[derive(Debug, Clone)]
+pub enum Behavior {
+    /// Answer with the given value
+    Answer(Value),
+    /// Answer with a plugin error
+    Error { code: u64, description: String },
+    /// Wait before behaving as specified
+    Delay(Duration, Box<Behavior>),
+    /// Exit without answering, closing the connection
+    Crash,
+    /// Write the given string instead of a valid answer
+    Malformed(String),
+    /// Behave like the n-th element on the n-th request, the last one is repeated. Sequences
+    /// can't be nested.
+    Sequence(Vec<Behavior>),
+}
+
+/// Builder and runtime of a scripted plugin
+#[derive(Debug, Clone)]
+pub struct MockPlugin {
+    name: String,
+    plugin_type: PluginType,
+    handshake: Option<Behavior>,
+    behaviors: HashMap<String, Behavior>,
+    fallback: Behavior,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockPlugin {
+    /// Creates a plugin that answers every request with error code 1 until scripted otherwise
+    pub fn new(name: &str, plugin_type: PluginType) -> MockPlugin {
+        MockPlugin {
+            name: name.to_string(),
+            plugin_type,
+            handshake: None,
+            behaviors: HashMap::new(),
+            fallback: Behavior::Error {
+                code: 1,
+                description: "mock plugin: unexpected arguments".to_string(),
+            },
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Sets the behavior for requests with the given arguments
+    pub fn behave(mut self, arguments: &str, behavior: Behavior) -> MockPlugin {
+        self.behaviors.insert(arguments.to_string(), behavior);
+        self
+    }
+
+    /// Sets the behavior for requests that weren't scripted explicitly
+    pub fn otherwise(mut self, behavior: Behavior) -> MockPlugin {
+        self.fallback = behavior;
+        self
+    }
+
+    pub fn answer(self, arguments: &str, value: impl Into<Value>) -> MockPlugin {
+        self.behave(arguments, Behavior::Answer(value.into()))
+    }
+
+    pub fn error(self, arguments: &str, code: u64, description: &str) -> MockPlugin {
+        self.behave(
+            arguments,
+            Behavior::Error {
+                code,
+                description: description.to_string(),
+            },
+        )
+    }
+
+    pub fn delay(self, arguments: &str, delay: Duration, value: impl Into<Value>) -> MockPlugin {
+        self.behave(
+            arguments,
+            Behavior::Delay(delay, Box::new(Behavior::Answer(value.into()))),
+        )
+    }
+
+    pub fn crash(self, arguments: &str) -> MockPlugin {
+        self.behave(arguments, Behavior::Crash)
+    }
+
+    pub fn malformed(self, arguments: &str, garbage: &str) -> MockPlugin {
+        self.behave(arguments, Behavior::Malformed(garbage.to_string()))
+    }
+
+    /// Answers the given values one after another on repeated requests
+    pub fn answers<V: Into<Value>>(
+        self,
+        arguments: &str,
+        values: impl IntoIterator<Item = V>,
+    ) -> MockPlugin {
+        let sequence = values
+            .into_iter()
+            .map(|value| Behavior::Answer(value.into()))
+            .collect();
+        self.behave(arguments, Behavior::Sequence(sequence))
+    }
+
+    /// Changes how the plugin info is sent on startup, e.g. `Behavior::Crash` or
+    /// `Behavior::Malformed` to test handshake failures. Answer and error behaviors send the
+    /// regular info.
+    pub fn handshake(mut self, behavior: Behavior) -> MockPlugin {
+        self.handshake = Some(behavior);
+        self
+    }
+
+    /// Returns a handle to the log of all requests this plugin (and its clones) received
+    pub fn requests(&self) -> Arc<Mutex<Vec<Request>>> {
+        self.requests.clone()
+    }
+
+    /// Runs the plugin on a background thread and returns the host's ends of the connection as
+    /// `(plugin stdout, plugin stdin)`
+    pub fn spawn(self) -> std::io::Result<(UnixStream, UnixStream)> {
+        let (host, plugin) = UnixStream::pair()?;
+        let plugin_out = plugin.try_clone()?;
+
+        thread::spawn(move || {
+            // I/O errors just mean the host went away
+            let _ = self.serve(plugin, plugin_out);
+        });
+
+        Ok((host.try_clone()?, host))
+    }
+
+    /// Speaks the plugin protocol on the given streams till the input is closed or a crash is
+    /// scripted
+    pub fn serve(&self, input: impl Read, mut output: impl Write) -> std::io::Result<()> {
+        if !self.send_info(self.handshake.as_ref(), &mut output)? {
+            return Ok(());
+        }
+        output.flush()?;
+
+        let mut counts = HashMap::<String, usize>::new();
+        for req in Deserializer::from_reader(input).into_iter::<Request>() {
+            let req = match req {
+                Ok(req) => req,
+                Err(e) => {
+                    let resp: Result<Answer, assetman_api::Error> = Err(assetman_api::Error {
+                        code: 1,
+                        description: format!("Input parsing error: {:?}", e),
+                    });
+                    to_writer(&mut output, &resp)?;
+                    output.flush()?;
+                    continue;
+                }
+            };
+
+            let count = counts.entry(req.arguments.clone()).or_insert(0);
+            let mut behavior = self
+                .behaviors
+                .get(&req.arguments)
+                .unwrap_or(&self.fallback)
+                .clone();
+            if let Behavior::Sequence(sequence) = behavior {
+                behavior = sequence
+                    .get(*count)
+                    .or_else(|| sequence.last())
+                    .cloned()
+                    .unwrap_or(Behavior::Crash);
+            }
+            *count += 1;
+            self.requests.lock().unwrap().push(req);
+
+            if !respond(&behavior, &mut output)? {
+                return Ok(());
+            }
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the plugin info as modified by the handshake behavior, returns false on crash
+    fn send_info(
+        &self,
+        behavior: Option<&Behavior>,
+        output: &mut impl Write,
+    ) -> std::io::Result<bool> {
+        match behavior {
+            Some(Behavior::Delay(delay, behavior)) => {
+                thread::sleep(*delay);
+                self.send_info(Some(behavior), output)
+            }
+            Some(Behavior::Crash) | Some(Behavior::Sequence(_)) => Ok(false),
+            Some(Behavior::Malformed(garbage)) => {
+                output.write_all(garbage.as_bytes())?;
+                Ok(true)
+            }
+            None | Some(Behavior::Answer(_)) | Some(Behavior::Error { .. }) => {
+                let info = PluginInfo {
+                    name: self.name.clone(),
+                    plugin_type: self.plugin_type,
+                    description: "mock plugin".to_string(),
+                };
+                to_writer(&mut *output, &info)?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Writes the answer for `behavior`, returns false if the plugin should crash
+fn respond(behavior: &Behavior, output: &mut impl Write) -> std::io::Result<bool> {
+    let resp: Result<Answer, assetman_api::Error> = match behavior {
+        Behavior::Answer(value) => Ok(Answer {
+            answer: value.clone(),
+        }),
+        Behavior::Error { code, description } => Err(assetman_api::Error {
+            code: *code,
+            description: description.clone(),
+        }),
+        Behavior::Delay(delay, behavior) => {
+            thread::sleep(*delay);
+            return respond(behavior, output);
+        }
+        Behavior::Crash | Behavior::Sequence(_) => return Ok(false),
+        Behavior::Malformed(garbage) => {
+            output.write_all(garbage.as_bytes())?;
+            return Ok(true);
+        }
+    };
+
+    to_writer(output, &resp)?;
+    Ok(true)
+}
@@ -0,0 +1,7 @@
+CREATE TABLE updates_old (
+  id INTEGER PRIMARY KEY,
+  timestamp INTEGER DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+INSERT INTO updates_old (id, timestamp) SELECT id, timestamp FROM updates;
+DROP TABLE updates;
+ALTER TABLE updates_old RENAME TO updates;
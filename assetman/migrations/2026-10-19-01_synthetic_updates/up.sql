@@ -0,0 +1 @@
+ALTER TABLE updates ADD COLUMN synthetic BOOLEAN DEFAULT 0 NOT NULL;
@@ -0,0 +1,19 @@
+CREATE TABLE holdings_real (
+  update_id INTEGER REFERENCES updates (id) NOT NULL,
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  amount REAL NOT NULL,
+  PRIMARY KEY (asset_id, update_id)
+);
+INSERT INTO holdings_real SELECT update_id, asset_id, CAST(amount AS REAL) FROM holdings;
+DROP TABLE holdings;
+ALTER TABLE holdings_real RENAME TO holdings;
+
+CREATE TABLE prices_real (
+  update_id INTEGER REFERENCES updates (id) NOT NULL,
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  price REAL NOT NULL,
+  PRIMARY KEY (asset_id, update_id)
+);
+INSERT INTO prices_real SELECT update_id, asset_id, CAST(price AS REAL) FROM prices;
+DROP TABLE prices;
+ALTER TABLE prices_real RENAME TO prices;
@@ -0,0 +1,20 @@
+-- SQLite can't change column types, so the tables are rebuilt with amounts stored as text
+CREATE TABLE holdings_decimal (
+  update_id INTEGER REFERENCES updates (id) NOT NULL,
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  amount TEXT NOT NULL,
+  PRIMARY KEY (asset_id, update_id)
+);
+INSERT INTO holdings_decimal SELECT update_id, asset_id, CAST(amount AS TEXT) FROM holdings;
+DROP TABLE holdings;
+ALTER TABLE holdings_decimal RENAME TO holdings;
+
+CREATE TABLE prices_decimal (
+  update_id INTEGER REFERENCES updates (id) NOT NULL,
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  price TEXT NOT NULL,
+  PRIMARY KEY (asset_id, update_id)
+);
+INSERT INTO prices_decimal SELECT update_id, asset_id, CAST(price AS TEXT) FROM prices;
+DROP TABLE prices;
+ALTER TABLE prices_decimal RENAME TO prices;
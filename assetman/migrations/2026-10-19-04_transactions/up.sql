@@ -0,0 +1,9 @@
+CREATE TABLE transactions (
+  id INTEGER PRIMARY KEY,
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  kind TEXT NOT NULL,
+  date DATE NOT NULL,
+  amount TEXT NOT NULL,
+  price TEXT NOT NULL,
+  currency TEXT NOT NULL
+);
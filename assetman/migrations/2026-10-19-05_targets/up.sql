@@ -0,0 +1,7 @@
+CREATE TABLE targets (
+  kind TEXT NOT NULL,
+  name TEXT NOT NULL,
+  weight TEXT NOT NULL,
+  tolerance TEXT NOT NULL,
+  PRIMARY KEY (kind, name)
+);
@@ -0,0 +1 @@
+ALTER TABLE assets ADD COLUMN liability BOOLEAN DEFAULT 0 NOT NULL;
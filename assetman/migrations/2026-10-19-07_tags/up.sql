@@ -0,0 +1,6 @@
+CREATE TABLE tags (
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  key TEXT NOT NULL,
+  value TEXT NOT NULL,
+  PRIMARY KEY (asset_id, key)
+);
@@ -0,0 +1,6 @@
+CREATE TABLE ownership (
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  portfolio TEXT NOT NULL,
+  share TEXT NOT NULL,
+  PRIMARY KEY (asset_id, portfolio)
+);
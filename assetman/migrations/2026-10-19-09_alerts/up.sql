@@ -0,0 +1,14 @@
+CREATE TABLE alert_rules (
+  id INTEGER PRIMARY KEY,
+  kind TEXT NOT NULL,
+  subject TEXT,
+  threshold TEXT
+);
+
+-- the rule is stored as text, so the history survives removing the rule
+CREATE TABLE alerts (
+  id INTEGER PRIMARY KEY,
+  update_id INTEGER REFERENCES updates (id) NOT NULL,
+  rule TEXT NOT NULL,
+  message TEXT NOT NULL
+);
@@ -0,0 +1,12 @@
+CREATE TABLE assets_old (
+  id INTEGER PRIMARY KEY,
+  name TEXT NOT NULL,
+  price_query TEXT NOT NULL,
+  holdings_query TEXT NOT NULL,
+  category TEXT DEFAULT 'default',
+  liability BOOLEAN DEFAULT 0 NOT NULL
+);
+INSERT INTO assets_old (id, name, price_query, holdings_query, category, liability)
+  SELECT id, name, price_query, holdings_query, category, liability FROM assets;
+DROP TABLE assets;
+ALTER TABLE assets_old RENAME TO assets;
@@ -0,0 +1 @@
+ALTER TABLE assets ADD COLUMN archived BOOLEAN DEFAULT 0 NOT NULL;
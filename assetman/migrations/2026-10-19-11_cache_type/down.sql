@@ -0,0 +1,8 @@
+DROP TABLE plugin_cache;
+CREATE TABLE plugin_cache (
+  plugin TEXT NOT NULL,
+  arguments TEXT NOT NULL,
+  value TEXT NOT NULL,
+  fetched_at TIMESTAMP NOT NULL,
+  PRIMARY KEY (plugin, arguments)
+);
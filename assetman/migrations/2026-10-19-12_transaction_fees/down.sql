@@ -0,0 +1,13 @@
+CREATE TABLE transactions_old (
+  id INTEGER PRIMARY KEY,
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  kind TEXT NOT NULL,
+  date DATE NOT NULL,
+  amount TEXT NOT NULL,
+  price TEXT NOT NULL,
+  currency TEXT NOT NULL
+);
+INSERT INTO transactions_old (id, asset_id, kind, date, amount, price, currency)
+  SELECT id, asset_id, kind, date, amount, price, currency FROM transactions;
+DROP TABLE transactions;
+ALTER TABLE transactions_old RENAME TO transactions;
@@ -0,0 +1,11 @@
+DROP TABLE alerts;
+DROP TABLE alert_rules;
+DROP TABLE ownership;
+DROP TABLE tags;
+DROP TABLE targets;
+DROP TABLE transactions;
+DROP TABLE plugin_cache;
+DROP TABLE prices;
+DROP TABLE holdings;
+DROP TABLE updates;
+DROP TABLE assets;
@@ -0,0 +1,88 @@
+-- the same schema as the SQLite migrations up to this version, new migrations have to be added
+-- to both directories. Decimals are stored as text like in SQLite, so they are read back exactly
+-- as written.
+CREATE TABLE assets (
+  id SERIAL PRIMARY KEY,
+  name TEXT NOT NULL,
+  price_query TEXT NOT NULL,
+  holdings_query TEXT NOT NULL,
+  category TEXT NOT NULL DEFAULT 'default',
+  liability BOOLEAN NOT NULL DEFAULT FALSE,
+  archived BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+-- timestamps are stored in UTC with a resolution of seconds like in SQLite
+CREATE TABLE updates (
+  id SERIAL PRIMARY KEY,
+  timestamp TIMESTAMP NOT NULL DEFAULT date_trunc('second', now() AT TIME ZONE 'utc'),
+  synthetic BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE holdings (
+  update_id INTEGER REFERENCES updates (id) NOT NULL,
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  amount TEXT NOT NULL,
+  PRIMARY KEY (asset_id, update_id)
+);
+
+CREATE TABLE prices (
+  update_id INTEGER REFERENCES updates (id) NOT NULL,
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  price TEXT NOT NULL,
+  PRIMARY KEY (asset_id, update_id)
+);
+
+CREATE TABLE plugin_cache (
+  plugin TEXT NOT NULL,
+  arguments TEXT NOT NULL,
+  value TEXT NOT NULL,
+  fetched_at TIMESTAMP NOT NULL,
+  PRIMARY KEY (plugin, arguments)
+);
+
+CREATE TABLE transactions (
+  id SERIAL PRIMARY KEY,
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  kind TEXT NOT NULL,
+  date DATE NOT NULL,
+  amount TEXT NOT NULL,
+  price TEXT NOT NULL,
+  currency TEXT NOT NULL
+);
+
+CREATE TABLE targets (
+  kind TEXT NOT NULL,
+  name TEXT NOT NULL,
+  weight TEXT NOT NULL,
+  tolerance TEXT NOT NULL,
+  PRIMARY KEY (kind, name)
+);
+
+CREATE TABLE tags (
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  key TEXT NOT NULL,
+  value TEXT NOT NULL,
+  PRIMARY KEY (asset_id, key)
+);
+
+CREATE TABLE ownership (
+  asset_id INTEGER REFERENCES assets (id) NOT NULL,
+  portfolio TEXT NOT NULL,
+  share TEXT NOT NULL,
+  PRIMARY KEY (asset_id, portfolio)
+);
+
+CREATE TABLE alert_rules (
+  id SERIAL PRIMARY KEY,
+  kind TEXT NOT NULL,
+  subject TEXT,
+  threshold TEXT
+);
+
+-- the rule is stored as text, so the history survives removing the rule
+CREATE TABLE alerts (
+  id SERIAL PRIMARY KEY,
+  update_id INTEGER REFERENCES updates (id) NOT NULL,
+  rule TEXT NOT NULL,
+  message TEXT NOT NULL
+);
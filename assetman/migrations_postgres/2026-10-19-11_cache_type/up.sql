@@ -0,0 +1,11 @@
+-- cached answers are only valid for the type they were requested as, plugins of type Any may
+-- answer the same arguments differently per type
+DROP TABLE plugin_cache;
+CREATE TABLE plugin_cache (
+  plugin TEXT NOT NULL,
+  plugin_type TEXT NOT NULL,
+  arguments TEXT NOT NULL,
+  value TEXT NOT NULL,
+  fetched_at TIMESTAMP NOT NULL,
+  PRIMARY KEY (plugin, plugin_type, arguments)
+);
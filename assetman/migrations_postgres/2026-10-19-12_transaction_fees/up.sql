@@ -0,0 +1 @@
+ALTER TABLE transactions ADD COLUMN fee TEXT DEFAULT '0' NOT NULL;
@@ -0,0 +1,399 @@
+use crate::allocation::{Drift, TargetKind};
+use crate::db::LastInsertId;
+use crate::decimal::DbDecimal;
+use crate::schema::{alert_rules, alerts};
+use crate::{schema, Assets, AssetsSnapshot, Error};
+use assetman_api::Decimal;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// What an alert rule watches. Thresholds are fractions, e.g. `0.05` for 5%.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// Net worth dropped by more than the threshold since the previous update
+    TotalDrop(Decimal),
+    /// Price of an asset rose or fell by more than the threshold since the previous update
+    PriceMove { asset: String, threshold: Decimal },
+    /// Price of an asset rose above a level it wasn't above at the previous update
+    PriceAbove { asset: String, level: Decimal },
+    /// Price of an asset fell below a level it wasn't below at the previous update
+    PriceBelow { asset: String, level: Decimal },
+    /// A category or asset drifted out of the tolerance band around its target weight
+    OutOfBand(TargetKind),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertRule {
+    pub id: i32,
+    pub condition: Condition,
+}
+
+/// A rule that matched after a fetch
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub id: i32,
+    /// Time of the update that triggered the alert
+    pub time: NaiveDateTime,
+    /// Description of the rule at the time it matched
+    pub rule: String,
+    pub message: String,
+}
+
+#[derive(Debug, Queryable)]
+struct AlertRuleRow {
+    id: i32,
+    kind: String,
+    subject: Option<String>,
+    threshold: Option<DbDecimal>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "alert_rules"]
+struct InsertAlertRule {
+    kind: &'static str,
+    subject: Option<String>,
+    threshold: Option<DbDecimal>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "alerts"]
+struct InsertAlert<'a> {
+    update_id: i32,
+    rule: String,
+    message: &'a str,
+}
+
+impl Assets {
+    /// Adds a rule that is checked after every fetch, returns its id
+    pub fn add_alert_rule(&mut self, condition: &Condition) -> Result<i32, Error> {
+        match condition {
+            Condition::TotalDrop(threshold) | Condition::PriceMove { threshold, .. }
+                if threshold.is_sign_negative() || threshold.is_zero() =>
+            {
+                return Err(Error::InvalidAlertRule(
+                    "the threshold has to be positive".to_string(),
+                ));
+            }
+            Condition::PriceMove { asset, .. }
+            | Condition::PriceAbove { asset, .. }
+            | Condition::PriceBelow { asset, .. } => {
+                self.asset_id(asset)?;
+            }
+            _ => {}
+        }
+
+        let (kind, subject, threshold) = condition.to_row();
+        with_db!(self.db()?, |db| db.transaction(|| {
+            diesel::insert_into(schema::alert_rules::table)
+                .values(InsertAlertRule {
+                    kind,
+                    subject,
+                    threshold: threshold.map(DbDecimal),
+                })
+                .execute(db)?;
+            Ok(db.last_insert_id("alert_rules")?)
+        }))
+    }
+
+    pub fn remove_alert_rule(&mut self, id: i32) -> Result<(), Error> {
+        let deleted = with_db!(self.db()?, |db| diesel::delete(
+            schema::alert_rules::table.find(id)
+        )
+        .execute(db))?;
+        if deleted == 0 {
+            return Err(Error::AlertRuleNotFound(id));
+        }
+        Ok(())
+    }
+
+    pub fn list_alert_rules(&self) -> Result<Vec<AlertRule>, Error> {
+        with_db!(self.db()?, |db| schema::alert_rules::table
+            .order_by(schema::alert_rules::id)
+            .load::<AlertRuleRow>(db))?
+        .into_iter()
+        .map(|row| {
+            Ok(AlertRule {
+                id: row.id,
+                condition: Condition::from_row(
+                    &row.kind,
+                    row.subject,
+                    row.threshold.map(Decimal::from),
+                )
+                .map_err(Error::InvalidAlertRule)?,
+            })
+        })
+        .collect()
+    }
+
+    /// Lists recorded alerts, newest first
+    pub fn list_alerts(&self, limit: Option<i64>) -> Result<Vec<Alert>, Error> {
+        let rows = with_db!(self.db()?, |db| {
+            let mut query = schema::alerts::table
+                .inner_join(schema::updates::table)
+                .select((
+                    schema::alerts::id,
+                    schema::updates::timestamp,
+                    schema::alerts::rule,
+                    schema::alerts::message,
+                ))
+                .order_by(schema::alerts::id.desc())
+                .into_boxed();
+            if let Some(limit) = limit {
+                query = query.limit(limit);
+            }
+            query.load::<(i32, NaiveDateTime, String, String)>(db)
+        })?;
+        Ok(rows
+            .into_iter()
+            .map(|(id, time, rule, message)| Alert {
+                id,
+                time,
+                rule,
+                message,
+            })
+            .collect())
+    }
+
+    /// Evaluates all rules against the latest and the previous regular update of the selected
+    /// portfolio, records the matching alerts and returns them. Checking the same update again
+    /// replaces its alerts.
+    pub fn check_alerts(&mut self) -> Result<Vec<Alert>, Error> {
+        let rules = self.list_alert_rules()?;
+        let updates = self.regular_updates(2)?;
+        let latest = match updates.first() {
+            Some(latest) => *latest,
+            None => return Ok(Vec::new()),
+        };
+        let current = self.in_portfolio(self.snapshot_at(latest)?)?;
+        let previous = match updates.get(1) {
+            Some(previous) => Some(self.in_portfolio(self.snapshot_at(*previous)?)?),
+            None => None,
+        };
+
+        let mut matches = Vec::new();
+        for rule in &rules {
+            let messages = match &rule.condition {
+                Condition::OutOfBand(kind) => {
+                    let was_out_of_band = match &previous {
+                        Some(previous) => self
+                            .drift_of(previous.assets.clone(), *kind)?
+                            .into_iter()
+                            .filter(Drift::out_of_band)
+                            .map(|drift| drift.name)
+                            .collect(),
+                        None => Vec::new(),
+                    };
+                    self.drift_of(current.assets.clone(), *kind)?
+                        .into_iter()
+                        .filter(|drift| {
+                            drift.out_of_band() && !was_out_of_band.contains(&drift.name)
+                        })
+                        .map(|drift| out_of_band_message(&drift))
+                        .collect()
+                }
+                condition => condition.evaluate(previous.as_ref(), &current),
+            };
+            matches.extend(messages.into_iter().map(|message| (rule, message)));
+        }
+
+        with_db!(self.db()?, |db| db.transaction::<_, Error, _>(|| {
+            diesel::delete(schema::alerts::table.filter(schema::alerts::update_id.eq(latest.0)))
+                .execute(db)?;
+            for (rule, message) in &matches {
+                diesel::insert_into(schema::alerts::table)
+                    .values(InsertAlert {
+                        update_id: latest.0,
+                        rule: rule.condition.to_string(),
+                        message,
+                    })
+                    .execute(db)?;
+            }
+            Ok(())
+        }))?;
+
+        let mut alerts = self.list_alerts(Some(matches.len() as i64))?;
+        alerts.reverse();
+        Ok(alerts)
+    }
+}
+
+impl Condition {
+    /// Messages for every way the condition matches, except for `OutOfBand` which needs the
+    /// targets and is evaluated by `Assets::check_alerts`
+    fn evaluate(&self, previous: Option<&AssetsSnapshot>, current: &AssetsSnapshot) -> Vec<String> {
+        let prices = |snapshot: &AssetsSnapshot| {
+            snapshot
+                .assets
+                .iter()
+                .map(|asset| (asset.name.clone(), asset.price))
+                .collect::<HashMap<_, _>>()
+        };
+        let current_prices = prices(current);
+        let previous_prices = previous.map(prices).unwrap_or_default();
+
+        let message = match self {
+            Condition::TotalDrop(threshold) => {
+                let before = match previous {
+                    Some(previous) => previous.net_worth(),
+                    None => return Vec::new(),
+                };
+                let after = current.net_worth();
+                match change(before, after) {
+                    Some(change) if -change > *threshold => format!(
+                        "net worth dropped by {}% from {} to {}",
+                        percent(-change),
+                        before.normalize(),
+                        after.normalize()
+                    ),
+                    _ => return Vec::new(),
+                }
+            }
+            Condition::PriceMove { asset, threshold } => {
+                let (before, after) = match (previous_prices.get(asset), current_prices.get(asset))
+                {
+                    (Some(before), Some(after)) => (*before, *after),
+                    _ => return Vec::new(),
+                };
+                match change(before, after) {
+                    Some(change) if change.abs() > *threshold => format!(
+                        "price of {} {} by {}% from {} to {}",
+                        asset,
+                        if change.is_sign_negative() {
+                            "fell"
+                        } else {
+                            "rose"
+                        },
+                        percent(change.abs()),
+                        before.normalize(),
+                        after.normalize()
+                    ),
+                    _ => return Vec::new(),
+                }
+            }
+            Condition::PriceAbove { asset, level } => match current_prices.get(asset) {
+                Some(price)
+                    if price > level
+                        && !matches!(previous_prices.get(asset), Some(before) if before > level) =>
+                {
+                    format!(
+                        "price of {} rose above {} to {}",
+                        asset,
+                        level.normalize(),
+                        price.normalize()
+                    )
+                }
+                _ => return Vec::new(),
+            },
+            Condition::PriceBelow { asset, level } => match current_prices.get(asset) {
+                Some(price)
+                    if price < level
+                        && !matches!(previous_prices.get(asset), Some(before) if before < level) =>
+                {
+                    format!(
+                        "price of {} fell below {} to {}",
+                        asset,
+                        level.normalize(),
+                        price.normalize()
+                    )
+                }
+                _ => return Vec::new(),
+            },
+            Condition::OutOfBand(_) => return Vec::new(),
+        };
+        vec![message]
+    }
+
+    fn to_row(&self) -> (&'static str, Option<String>, Option<Decimal>) {
+        match self {
+            Condition::TotalDrop(threshold) => ("total-drop", None, Some(*threshold)),
+            Condition::PriceMove { asset, threshold } => {
+                ("price-move", Some(asset.clone()), Some(*threshold))
+            }
+            Condition::PriceAbove { asset, level } => {
+                ("price-above", Some(asset.clone()), Some(*level))
+            }
+            Condition::PriceBelow { asset, level } => {
+                ("price-below", Some(asset.clone()), Some(*level))
+            }
+            Condition::OutOfBand(kind) => ("out-of-band", Some(kind.to_string()), None),
+        }
+    }
+
+    pub(crate) fn from_row(
+        kind: &str,
+        subject: Option<String>,
+        threshold: Option<Decimal>,
+    ) -> Result<Condition, String> {
+        let missing = || format!("alert rule '{}' is incomplete", kind);
+        Ok(match kind {
+            "total-drop" => Condition::TotalDrop(threshold.ok_or_else(missing)?),
+            "price-move" => Condition::PriceMove {
+                asset: subject.ok_or_else(missing)?,
+                threshold: threshold.ok_or_else(missing)?,
+            },
+            "price-above" => Condition::PriceAbove {
+                asset: subject.ok_or_else(missing)?,
+                level: threshold.ok_or_else(missing)?,
+            },
+            "price-below" => Condition::PriceBelow {
+                asset: subject.ok_or_else(missing)?,
+                level: threshold.ok_or_else(missing)?,
+            },
+            "out-of-band" => Condition::OutOfBand(subject.ok_or_else(missing)?.parse()?),
+            _ => return Err(format!("unknown alert rule '{}'", kind)),
+        })
+    }
+}
+
+/// Relative change, `None` if there was nothing before
+fn change(before: Decimal, after: Decimal) -> Option<Decimal> {
+    if before.is_zero() {
+        None
+    } else {
+        Some((after - before) / before.abs())
+    }
+}
+
+fn percent(fraction: Decimal) -> Decimal {
+    (fraction * Decimal::new(100, 0)).round_dp(1)
+}
+
+fn out_of_band_message(drift: &Drift) -> String {
+    let target = drift
+        .target
+        .as_ref()
+        .expect("only drifts with target can be out of band");
+    format!(
+        "{} '{}' is at {}% instead of {}% ±{}%",
+        drift.kind,
+        drift.name,
+        percent(drift.weight),
+        percent(target.weight),
+        percent(target.tolerance)
+    )
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::TotalDrop(threshold) => {
+                write!(f, "net worth drops by more than {}%", percent(*threshold))
+            }
+            Condition::PriceMove { asset, threshold } => write!(
+                f,
+                "price of {} moves by more than {}%",
+                asset,
+                percent(*threshold)
+            ),
+            Condition::PriceAbove { asset, level } => {
+                write!(f, "price of {} rises above {}", asset, level)
+            }
+            Condition::PriceBelow { asset, level } => {
+                write!(f, "price of {} falls below {}", asset, level)
+            }
+            Condition::OutOfBand(kind) => write!(f, "a {} drifts out of its target band", kind),
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use crate::decimal::DbDecimal;
+use crate::schema::targets;
+use crate::{schema, Asset, Assets, Error};
+use assetman_api::Decimal;
+use diesel::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Whether a target weight applies to a category or a single asset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TargetKind {
+    Category,
+    Asset,
+}
+
+/// Share of the whole portfolio a category or asset should have. Weights and tolerances are
+/// fractions, e.g. `0.6` for 60%.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub kind: TargetKind,
+    pub name: String,
+    pub weight: Decimal,
+    /// How far the actual weight may drift from the target weight in either direction
+    pub tolerance: Decimal,
+}
+
+/// Actual weight of a category or asset compared to its target
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drift {
+    pub kind: TargetKind,
+    pub name: String,
+    pub value: Decimal,
+    pub weight: Decimal,
+    /// `None` if there is no target for this category or asset
+    pub target: Option<Target>,
+}
+
+/// Amount to buy (positive) or sell (negative) of a category or asset, in the currency prices are
+/// quoted in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trade {
+    pub name: String,
+    pub amount: Decimal,
+}
+
+#[derive(Debug, Queryable)]
+struct TargetRow {
+    kind: String,
+    name: String,
+    #[diesel(deserialize_as = "DbDecimal")]
+    weight: Decimal,
+    #[diesel(deserialize_as = "DbDecimal")]
+    tolerance: Decimal,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "targets"]
+struct InsertTarget<'a> {
+    kind: String,
+    name: &'a str,
+    weight: DbDecimal,
+    tolerance: DbDecimal,
+}
+
+impl Drift {
+    /// Actual minus target weight, `None` without target
+    pub fn drift(&self) -> Option<Decimal> {
+        self.target
+            .as_ref()
+            .map(|target| self.weight - target.weight)
+    }
+
+    /// True if the actual weight is outside the tolerance band around the target weight
+    pub fn out_of_band(&self) -> bool {
+        match (&self.target, self.drift()) {
+            (Some(target), Some(drift)) => drift.abs() > target.tolerance,
+            _ => false,
+        }
+    }
+}
+
+impl Assets {
+    /// Sets the target weight of a category or asset, replacing an existing one. The target
+    /// weights of each kind may add up to at most 100%.
+    pub fn set_target(&mut self, target: &Target) -> Result<(), Error> {
+        if target.weight.is_sign_negative() || target.weight > Decimal::ONE {
+            return Err(Error::InvalidTarget(
+                "the weight has to be between 0% and 100%".to_string(),
+            ));
+        }
+        if target.tolerance.is_sign_negative() {
+            return Err(Error::InvalidTarget(
+                "the tolerance can't be negative".to_string(),
+            ));
+        }
+        let known = match target.kind {
+            TargetKind::Category => with_db!(self.db()?, |db| schema::assets::table
+                .filter(schema::assets::category.eq(&target.name))
+                .count()
+                .get_result::<i64>(db))?,
+            TargetKind::Asset => with_db!(self.db()?, |db| schema::assets::table
+                .filter(schema::assets::name.eq(&target.name))
+                .count()
+                .get_result::<i64>(db))?,
+        };
+        if known == 0 {
+            return Err(match target.kind {
+                TargetKind::Category => {
+                    Error::InvalidTarget(format!("no asset has the category '{}'", target.name))
+                }
+                TargetKind::Asset => Error::AssetNotFound(target.name.clone()),
+            });
+        }
+
+        let others: Decimal = self
+            .list_targets()?
+            .into_iter()
+            .filter(|other| other.kind == target.kind && other.name != target.name)
+            .map(|other| other.weight)
+            .sum();
+        if others + target.weight > Decimal::ONE {
+            return Err(Error::InvalidTarget(format!(
+                "the {} targets would add up to more than 100%",
+                target.kind
+            )));
+        }
+
+        let kind = target.kind.to_string();
+        with_db!(self.db()?, |db| db.transaction(|| {
+            diesel::delete(schema::targets::table.find((&kind, &target.name))).execute(db)?;
+            diesel::insert_into(schema::targets::table)
+                .values(InsertTarget {
+                    kind: kind.clone(),
+                    name: &target.name,
+                    weight: DbDecimal(target.weight),
+                    tolerance: DbDecimal(target.tolerance),
+                })
+                .execute(db)
+        }))?;
+        Ok(())
+    }
+
+    pub fn remove_target(&mut self, kind: TargetKind, name: &str) -> Result<(), Error> {
+        let deleted = with_db!(self.db()?, |db| diesel::delete(
+            schema::targets::table.find((kind.to_string(), name))
+        )
+        .execute(db))?;
+        if deleted == 0 {
+            return Err(Error::InvalidTarget(format!(
+                "there is no target for the {} '{}'",
+                kind, name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Lists all targets, categories first
+    pub fn list_targets(&self) -> Result<Vec<Target>, Error> {
+        let mut targets = with_db!(self.db()?, |db| schema::targets::table
+            .load::<TargetRow>(db))?
+        .into_iter()
+        .map(|row| {
+            Ok(Target {
+                kind: row.kind.parse().map_err(Error::InvalidTarget)?,
+                name: row.name,
+                weight: row.weight,
+                tolerance: row.tolerance,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+        targets.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+        Ok(targets)
+    }
+
+    /// Compares the weights of the latest update to the targets of the given kind. Lists every
+    /// category or asset that has either a value or a target. Weights are relative to gross
+    /// assets, liabilities aren't part of the allocation.
+    pub fn drift(&self, kind: TargetKind) -> Result<Vec<Drift>, Error> {
+        self.drift_of(self.list_assets()?.assets, kind)
+    }
+
+    /// See `drift`, but for the given assets instead of the latest update
+    pub(crate) fn drift_of(
+        &self,
+        assets: Vec<Asset>,
+        kind: TargetKind,
+    ) -> Result<Vec<Drift>, Error> {
+        let assets = assets
+            .into_iter()
+            .filter(|asset| !asset.liability)
+            .collect::<Vec<_>>();
+        let total: Decimal = assets.iter().map(Asset::value).sum();
+
+        let mut values = BTreeMap::<String, Decimal>::new();
+        for asset in &assets {
+            let name = match kind {
+                TargetKind::Category => &asset.category,
+                TargetKind::Asset => &asset.name,
+            };
+            *values.entry(name.clone()).or_insert(Decimal::ZERO) += asset.value();
+        }
+        let mut targets = self
+            .list_targets()?
+            .into_iter()
+            .filter(|target| target.kind == kind)
+            .map(|target| (target.name.clone(), target))
+            .collect::<BTreeMap<_, _>>();
+        for name in targets.keys() {
+            values.entry(name.clone()).or_insert(Decimal::ZERO);
+        }
+
+        Ok(values
+            .into_iter()
+            .map(|(name, value)| Drift {
+                kind,
+                weight: if total.is_zero() {
+                    Decimal::ZERO
+                } else {
+                    value / total
+                },
+                target: targets.remove(&name),
+                name,
+                value,
+            })
+            .collect())
+    }
+
+    /// Proposes trades that bring the categories or assets with targets back to their target
+    /// weights after adding `cash` to the portfolio. With `cash_only` nothing is sold and the cash
+    /// is split among the underweight positions in proportion to how far they are below target.
+    pub fn rebalance(
+        &self,
+        kind: TargetKind,
+        cash: Decimal,
+        cash_only: bool,
+    ) -> Result<Vec<Trade>, Error> {
+        Ok(rebalance(&self.drift(kind)?, cash, cash_only))
+    }
+}
+
+/// See `Assets::rebalance`, positions without target aren't traded
+pub fn rebalance(drift: &[Drift], cash: Decimal, cash_only: bool) -> Vec<Trade> {
+    let total = drift.iter().map(|drift| drift.value).sum::<Decimal>() + cash;
+    let gaps = drift
+        .iter()
+        .filter_map(|drift| {
+            let target = drift.target.as_ref()?;
+            Some((drift.name.clone(), target.weight * total - drift.value))
+        })
+        .collect::<Vec<_>>();
+
+    if !cash_only {
+        return gaps
+            .into_iter()
+            .filter(|(_, amount)| !amount.is_zero())
+            .map(|(name, amount)| Trade { name, amount })
+            .collect();
+    }
+
+    let missing: Decimal = gaps
+        .iter()
+        .map(|(_, gap)| *gap)
+        .filter(|gap| gap.is_sign_positive())
+        .sum();
+    if missing.is_zero() {
+        return Vec::new();
+    }
+    gaps.into_iter()
+        .filter(|(_, gap)| gap.is_sign_positive() && !gap.is_zero())
+        .map(|(name, gap)| Trade {
+            name,
+            amount: if missing <= cash {
+                gap
+            } else {
+                cash * gap / missing
+            },
+        })
+        .collect()
+}
+
+impl Display for TargetKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetKind::Category => write!(f, "category"),
+            TargetKind::Asset => write!(f, "asset"),
+        }
+    }
+}
+
+impl FromStr for TargetKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "category" => Ok(TargetKind::Category),
+            "asset" => Ok(TargetKind::Asset),
+            _ => Err(format!("unknown target kind '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocation::{rebalance, Drift, Target, TargetKind};
+    use assetman_api::Decimal;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn drift(name: &str, value: &str, total: &str, target: Option<&str>) -> Drift {
+        Drift {
+            kind: TargetKind::Category,
+            name: name.to_string(),
+            value: dec(value),
+            weight: dec(value) / dec(total),
+            target: target.map(|weight| Target {
+                kind: TargetKind::Category,
+                name: name.to_string(),
+                weight: dec(weight),
+                tolerance: dec("0.05"),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_rebalance() {
+        let drift = vec![
+            drift("bonds", "200", "1000", Some("0.3")),
+            drift("cash", "100", "1000", None),
+            drift("stocks", "700", "1000", Some("0.6")),
+        ];
+        assert!(drift[0].out_of_band());
+        assert!(!drift[1].out_of_band());
+        assert!(drift[2].out_of_band());
+
+        let trades = rebalance(&drift, dec("0"), false);
+        assert_eq!(trades.len(), 2);
+        assert_eq!(
+            (trades[0].name.as_str(), trades[0].amount),
+            ("bonds", dec("100"))
+        );
+        assert_eq!(
+            (trades[1].name.as_str(), trades[1].amount),
+            ("stocks", dec("-100"))
+        );
+
+        // enough new cash to fill all gaps, what's left is for the position without target
+        let trades = rebalance(&drift, dec("500"), true);
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].amount, dec("250"));
+        assert_eq!(trades[1].amount, dec("200"));
+
+        // not enough to fill the gap, nothing is sold
+        let trades = rebalance(&drift, dec("90"), true);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(
+            (trades[0].name.as_str(), trades[0].amount),
+            ("bonds", dec("90"))
+        );
+    }
+}
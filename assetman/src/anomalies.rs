@@ -0,0 +1,156 @@
+use crate::db::DbConnection;
+use crate::store::Store;
+use crate::{schema, Error};
+use assetman_api::Decimal;
+use diesel::prelude::*;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// Limits beyond which fetched values are considered suspicious
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnomalyCfg {
+    /// Largest plausible relative price change between two updates, e.g. `0.5` for 50%
+    pub max_price_change: Decimal,
+}
+
+/// A fetched value that doesn't fit the previous update
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub asset: String,
+    pub kind: AnomalyKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnomalyKind {
+    /// Holdings dropped to zero, e.g. because of a broken export or a wrong descriptor
+    HoldingsVanished { before: Decimal },
+    /// Holdings of an asset with transactions changed without a transaction since the previous
+    /// update
+    UnexplainedHoldingsChange { before: Decimal, after: Decimal },
+    /// Price changed by more than `AnomalyCfg::max_price_change`
+    PriceJump { before: Decimal, after: Decimal },
+}
+
+impl Default for AnomalyCfg {
+    fn default() -> Self {
+        AnomalyCfg {
+            max_price_change: Decimal::new(5, 1),
+        }
+    }
+}
+
+/// Compares the values of `update_id` to the latest regular update before it. Holdings changes
+/// are only checked against transactions if there is a database to keep them.
+pub(crate) fn detect(
+    store: &dyn Store,
+    db_client: Option<&DbConnection>,
+    update_id: i32,
+    cfg: &AnomalyCfg,
+) -> Result<Vec<Anomaly>, Error> {
+    let previous = store
+        .updates()?
+        .into_iter()
+        .rev()
+        .find(|update| !update.synthetic && update.id != update_id);
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return Ok(Vec::new()),
+    };
+
+    let names = store
+        .assets()?
+        .into_iter()
+        .map(|asset| (asset.id, asset.name))
+        .collect::<HashMap<_, _>>();
+    let before = store
+        .values(Some(previous.id))?
+        .into_iter()
+        .map(|value| (value.asset_id, value))
+        .collect::<HashMap<_, _>>();
+    let (with_transactions, recent_transactions) = match db_client {
+        Some(db_client) => (
+            with_db!(db_client, |db| schema::transactions::table
+                .select(schema::transactions::asset_id)
+                .distinct()
+                .load::<i32>(db))?,
+            with_db!(db_client, |db| schema::transactions::table
+                .select(schema::transactions::asset_id)
+                .filter(schema::transactions::date.ge(previous.time.date()))
+                .distinct()
+                .load::<i32>(db))?,
+        ),
+        None => (Vec::new(), Vec::new()),
+    };
+    let mut values = store
+        .values(Some(update_id))?
+        .into_iter()
+        .filter_map(|value| Some((names.get(&value.asset_id)?.clone(), value)))
+        .collect::<Vec<_>>();
+    values.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut anomalies = Vec::new();
+    for (name, after) in values {
+        let before = match before.get(&after.asset_id) {
+            Some(before) => before,
+            None => continue,
+        };
+        let mut flag = |kind| {
+            anomalies.push(Anomaly {
+                asset: name.clone(),
+                kind,
+            })
+        };
+
+        if after.holdings.is_zero() && !before.holdings.is_zero() {
+            flag(AnomalyKind::HoldingsVanished {
+                before: before.holdings,
+            });
+        } else if after.holdings != before.holdings
+            && with_transactions.contains(&after.asset_id)
+            && !recent_transactions.contains(&after.asset_id)
+        {
+            flag(AnomalyKind::UnexplainedHoldingsChange {
+                before: before.holdings,
+                after: after.holdings,
+            });
+        }
+
+        if !before.price.is_zero()
+            && ((after.price - before.price) / before.price).abs() > cfg.max_price_change
+        {
+            flag(AnomalyKind::PriceJump {
+                before: before.price,
+                after: after.price,
+            });
+        }
+    }
+
+    Ok(anomalies)
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            AnomalyKind::HoldingsVanished { before } => write!(
+                f,
+                "holdings of '{}' dropped from {} to zero",
+                self.asset,
+                before.normalize()
+            ),
+            AnomalyKind::UnexplainedHoldingsChange { before, after } => write!(
+                f,
+                "holdings of '{}' changed from {} to {} without a recorded transaction",
+                self.asset,
+                before.normalize(),
+                after.normalize()
+            ),
+            AnomalyKind::PriceJump { before, after } => write!(
+                f,
+                "price of '{}' jumped from {} to {}",
+                self.asset,
+                before.normalize(),
+                after.normalize()
+            ),
+        }
+    }
+}
@@ -0,0 +1,901 @@
+use crate::alerts::Condition;
+use crate::allocation::TargetKind;
+use crate::db::{DbConnection, LastInsertId};
+use crate::decimal::DbDecimal;
+use crate::maintenance::{Defect, Problem, Row};
+use crate::store::{NewAsset, StoredAsset};
+use crate::transactions::TransactionKind;
+use crate::{schema, Assets, Error, InsertUpdate};
+use assetman_api::Decimal;
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Version of the bundle format, raised whenever records change incompatibly
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Everything stored in the database except cached plugin answers, independent of the database
+/// schema. Assets are referred to by name, updates by an id that is only meaningful within the
+/// bundle. Decimals are kept as strings so that no precision is lost.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bundle {
+    pub version: u32,
+    pub exported_at: NaiveDateTime,
+    pub assets: Vec<AssetRecord>,
+    pub tags: Vec<TagRecord>,
+    pub ownership: Vec<OwnershipRecord>,
+    pub updates: Vec<UpdateRecord>,
+    pub prices: Vec<ValueRecord>,
+    pub holdings: Vec<ValueRecord>,
+    pub transactions: Vec<TransactionRecord>,
+    pub targets: Vec<TargetRecord>,
+    pub alert_rules: Vec<AlertRuleRecord>,
+    pub alerts: Vec<AlertRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetRecord {
+    pub name: String,
+    pub category: String,
+    pub price_query: String,
+    pub holdings_query: String,
+    pub liability: bool,
+    pub archived: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagRecord {
+    pub asset: String,
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OwnershipRecord {
+    pub asset: String,
+    pub portfolio: String,
+    pub share: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UpdateRecord {
+    pub id: i32,
+    pub time: NaiveDateTime,
+    pub synthetic: bool,
+}
+
+/// Price or holdings of an asset at an update
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValueRecord {
+    pub update: i32,
+    pub asset: String,
+    pub value: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    pub asset: String,
+    pub kind: String,
+    pub date: NaiveDate,
+    pub amount: Decimal,
+    pub price: Decimal,
+    /// Missing in bundles exported before fees were recorded
+    #[serde(default)]
+    pub fee: Decimal,
+    pub currency: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TargetRecord {
+    pub kind: String,
+    pub name: String,
+    pub weight: Decimal,
+    pub tolerance: Decimal,
+}
+
+/// Alert rule as stored, thresholds are fractions like in `Condition`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertRuleRecord {
+    pub kind: String,
+    pub subject: Option<String>,
+    pub threshold: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertRecord {
+    pub update: i32,
+    pub rule: String,
+    pub message: String,
+}
+
+/// What to do if a record of the bundle differs from the one in the database, e.g. an asset
+/// with different queries or a different price at the same update
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Fail without importing anything
+    Abort,
+    /// Keep the record in the database
+    Keep,
+    /// Replace the record in the database with the one from the bundle
+    Replace,
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportSummary {
+    /// Records that weren't in the database yet
+    pub added: usize,
+    /// Records that were already in the database as they are
+    pub unchanged: usize,
+    /// Descriptions of records that differed, kept or replaced depending on `OnConflict`
+    pub conflicts: Vec<String>,
+}
+
+/// Header of a CSV bundle, the records are in one file per table next to it
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvHeader {
+    version: u32,
+    exported_at: NaiveDateTime,
+}
+
+impl Assets {
+    /// Dumps the whole database regardless of the selected portfolio
+    pub fn export(&self) -> Result<Bundle, Error> {
+        with_db!(self.db()?, |db| db.transaction(|| {
+            let assets = schema::assets::table
+                .order_by(schema::assets::name)
+                .load::<StoredAsset>(db)?;
+            let names = assets
+                .iter()
+                .map(|asset| (asset.id, asset.name.clone()))
+                .collect::<HashMap<_, _>>();
+            // SQLite doesn't enforce references, so rows may refer to assets deleted by hand
+            let name = |asset_id: i32, row: Row| {
+                names.get(&asset_id).cloned().ok_or_else(|| {
+                    Error::BrokenReference(Problem {
+                        row,
+                        defect: Defect::MissingAsset(asset_id),
+                    })
+                })
+            };
+
+            let values = |values: Vec<(i32, i32, DbDecimal)>, is_price: bool| {
+                values
+                    .into_iter()
+                    .map(|(update_id, asset_id, value)| {
+                        let row = if is_price {
+                            Row::Price {
+                                update_id,
+                                asset_id,
+                            }
+                        } else {
+                            Row::Holdings {
+                                update_id,
+                                asset_id,
+                            }
+                        };
+                        Ok(ValueRecord {
+                            update: update_id,
+                            asset: name(asset_id, row)?,
+                            value: value.0,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, Error>>()
+            };
+            let prices = values(
+                schema::prices::table
+                    .order_by((schema::prices::update_id, schema::prices::asset_id))
+                    .load(db)?,
+                true,
+            )?;
+            let holdings = values(
+                schema::holdings::table
+                    .order_by((schema::holdings::update_id, schema::holdings::asset_id))
+                    .load(db)?,
+                false,
+            )?;
+
+            Ok(Bundle {
+                version: BUNDLE_VERSION,
+                exported_at: chrono::Utc::now().naive_utc(),
+                tags: schema::tags::table
+                    .order_by((schema::tags::asset_id, schema::tags::key))
+                    .load::<(i32, String, String)>(db)?
+                    .into_iter()
+                    .map(|(asset_id, key, value)| {
+                        Ok(TagRecord {
+                            asset: name(
+                                asset_id,
+                                Row::Tag {
+                                    asset_id,
+                                    key: key.clone(),
+                                },
+                            )?,
+                            key,
+                            value,
+                        })
+                    })
+                    .collect::<Result<_, Error>>()?,
+                ownership: schema::ownership::table
+                    .order_by((schema::ownership::asset_id, schema::ownership::portfolio))
+                    .load::<(i32, String, DbDecimal)>(db)?
+                    .into_iter()
+                    .map(|(asset_id, portfolio, share)| {
+                        Ok(OwnershipRecord {
+                            asset: name(
+                                asset_id,
+                                Row::Ownership {
+                                    asset_id,
+                                    portfolio: portfolio.clone(),
+                                },
+                            )?,
+                            portfolio,
+                            share: share.0,
+                        })
+                    })
+                    .collect::<Result<_, Error>>()?,
+                updates: schema::updates::table
+                    .order_by(schema::updates::id)
+                    .load::<(i32, NaiveDateTime, bool)>(db)?
+                    .into_iter()
+                    .map(|(id, time, synthetic)| UpdateRecord {
+                        id,
+                        time,
+                        synthetic,
+                    })
+                    .collect(),
+                prices,
+                holdings,
+                transactions: schema::transactions::table
+                    .select((
+                        schema::transactions::id,
+                        schema::transactions::asset_id,
+                        schema::transactions::kind,
+                        schema::transactions::date,
+                        schema::transactions::amount,
+                        schema::transactions::price,
+                        schema::transactions::fee,
+                        schema::transactions::currency,
+                    ))
+                    .order_by(schema::transactions::id)
+                    .load::<(
+                        i32,
+                        i32,
+                        String,
+                        NaiveDate,
+                        DbDecimal,
+                        DbDecimal,
+                        DbDecimal,
+                        String,
+                    )>(db)?
+                    .into_iter()
+                    .map(|(id, asset_id, kind, date, amount, price, fee, currency)| {
+                        Ok(TransactionRecord {
+                            asset: name(asset_id, Row::Transaction(id))?,
+                            kind,
+                            date,
+                            amount: amount.0,
+                            price: price.0,
+                            fee: fee.0,
+                            currency,
+                        })
+                    })
+                    .collect::<Result<_, Error>>()?,
+                targets: schema::targets::table
+                    .order_by((schema::targets::kind, schema::targets::name))
+                    .load::<(String, String, DbDecimal, DbDecimal)>(db)?
+                    .into_iter()
+                    .map(|(kind, name, weight, tolerance)| TargetRecord {
+                        kind,
+                        name,
+                        weight: weight.0,
+                        tolerance: tolerance.0,
+                    })
+                    .collect(),
+                alert_rules: schema::alert_rules::table
+                    .select((
+                        schema::alert_rules::kind,
+                        schema::alert_rules::subject,
+                        schema::alert_rules::threshold,
+                    ))
+                    .order_by(schema::alert_rules::id)
+                    .load::<(String, Option<String>, Option<DbDecimal>)>(db)?
+                    .into_iter()
+                    .map(|(kind, subject, threshold)| AlertRuleRecord {
+                        kind,
+                        subject,
+                        threshold: threshold.map(Decimal::from),
+                    })
+                    .collect(),
+                alerts: schema::alerts::table
+                    .select((
+                        schema::alerts::update_id,
+                        schema::alerts::rule,
+                        schema::alerts::message,
+                    ))
+                    .order_by(schema::alerts::id)
+                    .load::<(i32, String, String)>(db)?
+                    .into_iter()
+                    .map(|(update, rule, message)| AlertRecord {
+                        update,
+                        rule,
+                        message,
+                    })
+                    .collect(),
+                assets: assets
+                    .into_iter()
+                    .map(|asset| AssetRecord {
+                        name: asset.name,
+                        category: asset.category,
+                        price_query: asset.price_query,
+                        holdings_query: asset.holdings_query,
+                        liability: asset.liability,
+                        archived: asset.archived,
+                    })
+                    .collect(),
+            })
+        }))
+    }
+
+    /// Merges a bundle into the database. Updates are matched one-to-one by time and whether they
+    /// are synthetic, transactions, alert rules and alerts are only skipped if an identical one
+    /// existed before the import, so duplicates within the bundle are kept. Either the whole
+    /// bundle is imported or nothing.
+    pub fn import(
+        &mut self,
+        bundle: &Bundle,
+        on_conflict: OnConflict,
+    ) -> Result<ImportSummary, Error> {
+        if bundle.version != BUNDLE_VERSION {
+            return Err(Error::InvalidBundle(format!(
+                "version {} isn't supported, expected version {}",
+                bundle.version, BUNDLE_VERSION
+            )));
+        }
+
+        let db_client = self.db()?;
+        let mut summary = ImportSummary::default();
+        with_db!(db_client, |db| db.transaction(|| {
+            for record in &bundle.assets {
+                import_asset(db_client, record, on_conflict, &mut summary)?;
+            }
+            let asset_ids = schema::assets::table
+                .select((schema::assets::name, schema::assets::id))
+                .load::<(String, i32)>(db)?
+                .into_iter()
+                .collect::<HashMap<_, _>>();
+            let asset_id = |name: &str| {
+                asset_ids.get(name).copied().ok_or_else(|| {
+                    Error::InvalidBundle(format!("refers to unknown asset '{}'", name))
+                })
+            };
+
+            for tag in &bundle.tags {
+                let asset_id = asset_id(&tag.asset)?;
+                let existing = schema::tags::table
+                    .find((asset_id, &tag.key))
+                    .select(schema::tags::value)
+                    .first::<String>(db)
+                    .optional()?;
+                let description = || format!("tag {} of '{}'", tag.key, tag.asset);
+                if summary.merge(
+                    existing.map(|value| value == tag.value),
+                    on_conflict,
+                    description,
+                )? {
+                    diesel::delete(schema::tags::table.find((asset_id, &tag.key))).execute(db)?;
+                    diesel::insert_into(schema::tags::table)
+                        .values((
+                            schema::tags::asset_id.eq(asset_id),
+                            schema::tags::key.eq(&tag.key),
+                            schema::tags::value.eq(&tag.value),
+                        ))
+                        .execute(db)?;
+                }
+            }
+
+            for ownership in &bundle.ownership {
+                let asset_id = asset_id(&ownership.asset)?;
+                let existing = schema::ownership::table
+                    .find((asset_id, &ownership.portfolio))
+                    .select(schema::ownership::share)
+                    .first::<DbDecimal>(db)
+                    .optional()?;
+                let description = || {
+                    format!(
+                        "share of '{}' owned by {}",
+                        ownership.asset, ownership.portfolio
+                    )
+                };
+                if summary.merge(
+                    existing.map(|share| share.0 == ownership.share),
+                    on_conflict,
+                    description,
+                )? {
+                    diesel::delete(schema::ownership::table.find((asset_id, &ownership.portfolio)))
+                        .execute(db)?;
+                    diesel::insert_into(schema::ownership::table)
+                        .values((
+                            schema::ownership::asset_id.eq(asset_id),
+                            schema::ownership::portfolio.eq(&ownership.portfolio),
+                            schema::ownership::share.eq(DbDecimal(ownership.share)),
+                        ))
+                        .execute(db)?;
+                }
+            }
+            let mut shares = HashMap::<_, Decimal>::new();
+            for (asset, share) in schema::ownership::table
+                .inner_join(schema::assets::table)
+                .select((schema::assets::name, schema::ownership::share))
+                .load::<(String, DbDecimal)>(db)?
+            {
+                *shares.entry(asset).or_default() += share.0;
+            }
+            if let Some((asset, _)) = shares.iter().find(|(_, sum)| **sum > Decimal::ONE) {
+                return Err(Error::InvalidOwnership(format!(
+                    "the shares of '{}' would add up to more than 100%",
+                    asset
+                )));
+            }
+
+            // every existing update is matched at most once, so that updates sharing a
+            // timestamp stay separate, earlier ones are matched first
+            let mut existing_updates = HashMap::<_, Vec<i32>>::new();
+            for (id, time, synthetic) in schema::updates::table
+                .order_by(schema::updates::id.desc())
+                .load::<(i32, NaiveDateTime, bool)>(db)?
+            {
+                existing_updates
+                    .entry((time, synthetic))
+                    .or_default()
+                    .push(id);
+            }
+            let mut update_ids = HashMap::new();
+            for update in &bundle.updates {
+                let existing = existing_updates
+                    .get_mut(&(update.time, update.synthetic))
+                    .and_then(Vec::pop);
+                let id = match existing {
+                    Some(id) => {
+                        summary.unchanged += 1;
+                        id
+                    }
+                    None => {
+                        diesel::insert_into(schema::updates::table)
+                            .values(InsertUpdate {
+                                timestamp: update.time,
+                                synthetic: update.synthetic,
+                            })
+                            .execute(db)?;
+                        summary.added += 1;
+                        db.last_insert_id("updates")?
+                    }
+                };
+                if update_ids.insert(update.id, (id, update.time)).is_some() {
+                    return Err(Error::InvalidBundle(format!(
+                        "update id {} is used more than once",
+                        update.id
+                    )));
+                }
+            }
+            let update = |id: i32| {
+                update_ids
+                    .get(&id)
+                    .copied()
+                    .ok_or_else(|| Error::InvalidBundle(format!("refers to unknown update {}", id)))
+            };
+
+            for (record, is_price) in bundle
+                .prices
+                .iter()
+                .map(|record| (record, true))
+                .chain(bundle.holdings.iter().map(|record| (record, false)))
+            {
+                let (update_id, time) = update(record.update)?;
+                let asset_id = asset_id(&record.asset)?;
+                let existing = if is_price {
+                    schema::prices::table
+                        .find((update_id, asset_id))
+                        .select(schema::prices::price)
+                        .first::<DbDecimal>(db)
+                        .optional()?
+                } else {
+                    schema::holdings::table
+                        .find((update_id, asset_id))
+                        .select(schema::holdings::amount)
+                        .first::<DbDecimal>(db)
+                        .optional()?
+                };
+                let description = || {
+                    format!(
+                        "{} of '{}' at {}",
+                        if is_price { "price" } else { "holdings" },
+                        record.asset,
+                        time
+                    )
+                };
+                if !summary.merge(
+                    existing.map(|value| value.0 == record.value),
+                    on_conflict,
+                    description,
+                )? {
+                    continue;
+                }
+                if is_price {
+                    diesel::delete(schema::prices::table.find((update_id, asset_id)))
+                        .execute(db)?;
+                    diesel::insert_into(schema::prices::table)
+                        .values((
+                            schema::prices::update_id.eq(update_id),
+                            schema::prices::asset_id.eq(asset_id),
+                            schema::prices::price.eq(DbDecimal(record.value)),
+                        ))
+                        .execute(db)?;
+                } else {
+                    diesel::delete(schema::holdings::table.find((update_id, asset_id)))
+                        .execute(db)?;
+                    diesel::insert_into(schema::holdings::table)
+                        .values((
+                            schema::holdings::update_id.eq(update_id),
+                            schema::holdings::asset_id.eq(asset_id),
+                            schema::holdings::amount.eq(DbDecimal(record.value)),
+                        ))
+                        .execute(db)?;
+                }
+            }
+
+            // each existing transaction matches one identical transaction of the bundle
+            let mut existing_txs = schema::transactions::table
+                .select((
+                    schema::transactions::asset_id,
+                    schema::transactions::kind,
+                    schema::transactions::date,
+                    schema::transactions::amount,
+                    schema::transactions::price,
+                    schema::transactions::fee,
+                    schema::transactions::currency,
+                ))
+                .load::<(
+                    i32,
+                    String,
+                    NaiveDate,
+                    DbDecimal,
+                    DbDecimal,
+                    DbDecimal,
+                    String,
+                )>(db)?;
+            for tx in &bundle.transactions {
+                let asset_id = asset_id(&tx.asset)?;
+                TransactionKind::from_str(&tx.kind).map_err(Error::InvalidBundle)?;
+                let existing = existing_txs.iter().position(
+                    |(existing_asset, kind, date, amount, price, fee, currency)| {
+                        *existing_asset == asset_id
+                            && *kind == tx.kind
+                            && *date == tx.date
+                            && amount.0 == tx.amount
+                            && price.0 == tx.price
+                            && fee.0 == tx.fee
+                            && *currency == tx.currency
+                    },
+                );
+                if let Some(idx) = existing {
+                    existing_txs.swap_remove(idx);
+                    summary.unchanged += 1;
+                    continue;
+                }
+                diesel::insert_into(schema::transactions::table)
+                    .values((
+                        schema::transactions::asset_id.eq(asset_id),
+                        schema::transactions::kind.eq(&tx.kind),
+                        schema::transactions::date.eq(tx.date),
+                        schema::transactions::amount.eq(DbDecimal(tx.amount)),
+                        schema::transactions::price.eq(DbDecimal(tx.price)),
+                        schema::transactions::fee.eq(DbDecimal(tx.fee)),
+                        schema::transactions::currency.eq(&tx.currency),
+                    ))
+                    .execute(db)?;
+                summary.added += 1;
+            }
+
+            for target in &bundle.targets {
+                TargetKind::from_str(&target.kind).map_err(Error::InvalidBundle)?;
+                let existing = schema::targets::table
+                    .find((&target.kind, &target.name))
+                    .select((schema::targets::weight, schema::targets::tolerance))
+                    .first::<(DbDecimal, DbDecimal)>(db)
+                    .optional()?;
+                let description = || format!("target of {} '{}'", target.kind, target.name);
+                if summary.merge(
+                    existing.map(|(weight, tolerance)| {
+                        weight.0 == target.weight && tolerance.0 == target.tolerance
+                    }),
+                    on_conflict,
+                    description,
+                )? {
+                    diesel::delete(schema::targets::table.find((&target.kind, &target.name)))
+                        .execute(db)?;
+                    diesel::insert_into(schema::targets::table)
+                        .values((
+                            schema::targets::kind.eq(&target.kind),
+                            schema::targets::name.eq(&target.name),
+                            schema::targets::weight.eq(DbDecimal(target.weight)),
+                            schema::targets::tolerance.eq(DbDecimal(target.tolerance)),
+                        ))
+                        .execute(db)?;
+                }
+            }
+
+            let existing_rules = schema::alert_rules::table
+                .select((
+                    schema::alert_rules::kind,
+                    schema::alert_rules::subject,
+                    schema::alert_rules::threshold,
+                ))
+                .load::<(String, Option<String>, Option<DbDecimal>)>(db)?;
+            for rule in &bundle.alert_rules {
+                Condition::from_row(&rule.kind, rule.subject.clone(), rule.threshold)
+                    .map_err(Error::InvalidBundle)?;
+                let exists = existing_rules.iter().any(|(kind, subject, threshold)| {
+                    *kind == rule.kind
+                        && *subject == rule.subject
+                        && threshold.map(Decimal::from) == rule.threshold
+                });
+                if exists {
+                    summary.unchanged += 1;
+                    continue;
+                }
+                diesel::insert_into(schema::alert_rules::table)
+                    .values((
+                        schema::alert_rules::kind.eq(&rule.kind),
+                        schema::alert_rules::subject.eq(&rule.subject),
+                        schema::alert_rules::threshold.eq(rule.threshold.map(DbDecimal)),
+                    ))
+                    .execute(db)?;
+                summary.added += 1;
+            }
+
+            let mut existing_alerts = schema::alerts::table
+                .select((
+                    schema::alerts::update_id,
+                    schema::alerts::rule,
+                    schema::alerts::message,
+                ))
+                .load::<(i32, String, String)>(db)?;
+            for alert in &bundle.alerts {
+                let (update_id, _) = update(alert.update)?;
+                let existing = existing_alerts.iter().position(|(update, rule, message)| {
+                    *update == update_id && *rule == alert.rule && *message == alert.message
+                });
+                if let Some(idx) = existing {
+                    existing_alerts.swap_remove(idx);
+                    summary.unchanged += 1;
+                    continue;
+                }
+                diesel::insert_into(schema::alerts::table)
+                    .values((
+                        schema::alerts::update_id.eq(update_id),
+                        schema::alerts::rule.eq(&alert.rule),
+                        schema::alerts::message.eq(&alert.message),
+                    ))
+                    .execute(db)?;
+                summary.added += 1;
+            }
+
+            Ok(())
+        }))?;
+        Ok(summary)
+    }
+}
+
+fn import_asset(
+    db_client: &DbConnection,
+    record: &AssetRecord,
+    on_conflict: OnConflict,
+    summary: &mut ImportSummary,
+) -> Result<(), Error> {
+    if record.name.trim().is_empty() {
+        return Err(Error::InvalidBundle(
+            "asset names can't be empty".to_string(),
+        ));
+    }
+    let existing = with_db!(db_client, |db| schema::assets::table
+        .filter(schema::assets::name.eq(&record.name))
+        .first::<StoredAsset>(db)
+        .optional())?;
+    let existing = match existing {
+        Some(existing) => existing,
+        None => {
+            with_db!(db_client, |db| diesel::insert_into(schema::assets::table)
+                .values(NewAsset {
+                    name: &record.name,
+                    price_query: &record.price_query,
+                    holdings_query: &record.holdings_query,
+                    category: &record.category,
+                    liability: record.liability,
+                    archived: record.archived,
+                })
+                .execute(db))?;
+            summary.added += 1;
+            return Ok(());
+        }
+    };
+
+    let same = existing.category == record.category
+        && existing.price_query == record.price_query
+        && existing.holdings_query == record.holdings_query
+        && existing.liability == record.liability
+        && existing.archived == record.archived;
+    let description = || format!("asset '{}'", record.name);
+    if summary.merge(Some(same), on_conflict, description)? {
+        with_db!(db_client, |db| diesel::update(
+            schema::assets::table.find(existing.id)
+        )
+        .set((
+            schema::assets::category.eq(&record.category),
+            schema::assets::price_query.eq(&record.price_query),
+            schema::assets::holdings_query.eq(&record.holdings_query),
+            schema::assets::liability.eq(record.liability),
+            schema::assets::archived.eq(record.archived),
+        ))
+        .execute(db))?;
+    }
+    Ok(())
+}
+
+impl ImportSummary {
+    /// Counts a record given whether it exists and is the same as in the bundle, returns whether
+    /// it has to be written
+    fn merge(
+        &mut self,
+        same: Option<bool>,
+        on_conflict: OnConflict,
+        description: impl FnOnce() -> String,
+    ) -> Result<bool, Error> {
+        match (same, on_conflict) {
+            (None, _) => {
+                self.added += 1;
+                Ok(true)
+            }
+            (Some(true), _) => {
+                self.unchanged += 1;
+                Ok(false)
+            }
+            (Some(false), OnConflict::Abort) => Err(Error::ImportConflict(description())),
+            (Some(false), on_conflict) => {
+                self.conflicts.push(description());
+                Ok(on_conflict == OnConflict::Replace)
+            }
+        }
+    }
+}
+
+impl Bundle {
+    pub fn write_json(&self, out: impl Write) -> std::io::Result<()> {
+        Ok(serde_json::to_writer_pretty(out, self)?)
+    }
+
+    pub fn read_json(input: impl Read) -> Result<Bundle, Error> {
+        serde_json::from_reader(input).map_err(|e| Error::InvalidBundle(e.to_string()))
+    }
+
+    /// Writes the bundle as `bundle.csv` with the version and one CSV file per table into `dir`,
+    /// creating it if necessary
+    pub fn write_csv(&self, dir: &Path) -> Result<(), Error> {
+        std::fs::create_dir_all(dir).map_err(|e| Error::Io(dir.to_path_buf(), e))?;
+        let header = CsvHeader {
+            version: self.version,
+            exported_at: self.exported_at,
+        };
+        write_csv_file(dir, "bundle", &[header])?;
+        write_csv_file(dir, "assets", &self.assets)?;
+        write_csv_file(dir, "tags", &self.tags)?;
+        write_csv_file(dir, "ownership", &self.ownership)?;
+        write_csv_file(dir, "updates", &self.updates)?;
+        write_csv_file(dir, "prices", &self.prices)?;
+        write_csv_file(dir, "holdings", &self.holdings)?;
+        write_csv_file(dir, "transactions", &self.transactions)?;
+        write_csv_file(dir, "targets", &self.targets)?;
+        write_csv_file(dir, "alert_rules", &self.alert_rules)?;
+        write_csv_file(dir, "alerts", &self.alerts)
+    }
+
+    /// Reads a bundle written by `write_csv`
+    pub fn read_csv(dir: &Path) -> Result<Bundle, Error> {
+        let header = read_csv_file::<CsvHeader>(dir, "bundle")?
+            .pop()
+            .ok_or_else(|| Error::InvalidBundle("bundle.csv is empty".to_string()))?;
+        Ok(Bundle {
+            version: header.version,
+            exported_at: header.exported_at,
+            assets: read_csv_file(dir, "assets")?,
+            tags: read_csv_file(dir, "tags")?,
+            ownership: read_csv_file(dir, "ownership")?,
+            updates: read_csv_file(dir, "updates")?,
+            prices: read_csv_file(dir, "prices")?,
+            holdings: read_csv_file(dir, "holdings")?,
+            transactions: read_csv_file(dir, "transactions")?,
+            targets: read_csv_file(dir, "targets")?,
+            alert_rules: read_csv_file(dir, "alert_rules")?,
+            alerts: read_csv_file(dir, "alerts")?,
+        })
+    }
+}
+
+fn write_csv_file<T: Serialize>(dir: &Path, table: &str, records: &[T]) -> Result<(), Error> {
+    let path = dir.join(format!("{}.csv", table));
+    let file = File::create(&path).map_err(|e| Error::Io(path.clone(), e))?;
+    let mut writer = csv::Writer::from_writer(file);
+    for record in records {
+        writer
+            .serialize(record)
+            .map_err(|e| Error::InvalidBundle(format!("could not write {}: {}", table, e)))?;
+    }
+    writer.flush().map_err(|e| Error::Io(path, e))
+}
+
+fn read_csv_file<T: DeserializeOwned>(dir: &Path, table: &str) -> Result<Vec<T>, Error> {
+    let path = dir.join(format!("{}.csv", table));
+    let file = File::open(&path).map_err(|e| Error::Io(path.clone(), e))?;
+    csv::Reader::from_reader(file)
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .map_err(|e| Error::InvalidBundle(format!("{}: {}", path.display(), e)))
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "abort" => Ok(OnConflict::Abort),
+            "keep" => Ok(OnConflict::Keep),
+            "replace" => Ok(OnConflict::Replace),
+            _ => Err(format!("unknown conflict handling '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::maintenance::{Defect, Problem, Row};
+    use crate::{schema, Assets, Error, Plugins};
+    use diesel::prelude::*;
+
+    #[test]
+    fn test_export_broken_reference() {
+        let assets = Assets::with_plugins(":memory:", Plugins::default()).unwrap();
+        with_db!(assets.db().unwrap(), |db| db.transaction(|| {
+            diesel::insert_into(schema::updates::table)
+                .values(schema::updates::id.eq(1))
+                .execute(db)?;
+            diesel::insert_into(schema::prices::table)
+                .values((
+                    schema::prices::update_id.eq(1),
+                    schema::prices::asset_id.eq(7),
+                    schema::prices::price.eq("1800"),
+                ))
+                .execute(db)
+        }))
+        .unwrap();
+
+        match assets.export() {
+            Err(Error::BrokenReference(problem)) => assert_eq!(
+                problem,
+                Problem {
+                    row: Row::Price {
+                        update_id: 1,
+                        asset_id: 7
+                    },
+                    defect: Defect::MissingAsset(7),
+                }
+            ),
+            res => panic!("expected broken reference, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::db::DbConnection;
+use crate::plugins::{parse_query, Plugins};
+use crate::schema::plugin_cache;
+use crate::{schema, Error};
+use assetman_api::{Decimal, PluginType, Value};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use log::info;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How long answers of plugins stay valid in the cache, a TTL of zero disables caching
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheCfg {
+    pub default_ttl: Duration,
+    pub plugin_ttl: HashMap<String, Duration>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "plugin_cache"]
+struct InsertCacheEntry<'a> {
+    plugin: &'a str,
+    plugin_type: &'a str,
+    arguments: &'a str,
+    value: String,
+    fetched_at: NaiveDateTime,
+}
+
+impl CacheCfg {
+    pub fn ttl(&self, plugin: &str) -> Duration {
+        self.plugin_ttl
+            .get(plugin)
+            .cloned()
+            .unwrap_or(self.default_ttl)
+    }
+}
+
+impl Default for CacheCfg {
+    fn default() -> Self {
+        CacheCfg {
+            default_ttl: Duration::from_secs(300),
+            plugin_ttl: HashMap::new(),
+        }
+    }
+}
+
+/// Parses a comma separated list of TTLs in seconds, either `plugin=seconds` for a certain plugin
+/// or just `seconds` to change the default, e.g. `600,bitstamp=60,csv_scan=0`
+impl FromStr for CacheCfg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cfg = CacheCfg::default();
+        let parse_secs = |secs: &str| {
+            secs.trim()
+                .parse()
+                .map(Duration::from_secs)
+                .map_err(|_| format!("'{}' is not a number of seconds", secs))
+        };
+
+        for entry in s.split(',').filter(|entry| !entry.trim().is_empty()) {
+            match entry.find('=') {
+                Some(idx) => {
+                    let plugin = entry[..idx].trim().to_string();
+                    cfg.plugin_ttl
+                        .insert(plugin, parse_secs(&entry[idx + 1..])?);
+                }
+                None => cfg.default_ttl = parse_secs(entry)?,
+            }
+        }
+
+        Ok(cfg)
+    }
+}
+
+/// Runs a query, answering it from the cache if there is a recent enough answer. Plugin errors are
+/// returned as `Error::PluginError`. Passing no config or no database bypasses the cache completely.
+pub(crate) fn query(
+    db_client: Option<&DbConnection>,
+    plugins: &mut Plugins,
+    cfg: Option<&CacheCfg>,
+    query: &str,
+    expected_type: PluginType,
+) -> Result<Value, Error> {
+    let (plugin, arguments) = parse_query(query)?;
+    // cached answers must not bypass the type check
+    plugins.check_type(plugin, expected_type)?;
+    let (db_client, ttl) = match (db_client, cfg.map(|cfg| cfg.ttl(plugin))) {
+        (Some(db_client), Some(ttl)) if ttl > Duration::from_secs(0) => (db_client, ttl),
+        _ => return Ok(plugins.query_plugin(plugin, arguments, expected_type)?),
+    };
+
+    // answers are cached per requested type since plugins of type `Any` may answer differently
+    let plugin_type = format!("{:?}", expected_type);
+    let now = chrono::Utc::now().naive_utc();
+    let cached = with_db!(db_client, |db| schema::plugin_cache::table
+        .select((
+            schema::plugin_cache::value,
+            schema::plugin_cache::fetched_at,
+        ))
+        .filter(
+            schema::plugin_cache::plugin
+                .eq(plugin)
+                .and(schema::plugin_cache::plugin_type.eq(&plugin_type))
+                .and(schema::plugin_cache::arguments.eq(arguments)),
+        )
+        .first::<(String, NaiveDateTime)>(db)
+        .optional())?;
+
+    if let Some((value, fetched_at)) = cached {
+        let age = now.signed_duration_since(fetched_at).to_std().ok();
+        // entries that can't be parsed anymore or have the wrong shape are simply replaced
+        let value = serde_json::from_str::<Value>(&value)
+            .ok()
+            .filter(|value| expected_type.accepts(value));
+        if let (Some(age), Some(value)) = (age, value) {
+            if age <= ttl {
+                info!("Answering {} from cache", query);
+                return Ok(value);
+            }
+        }
+    }
+
+    let value = plugins.query_plugin(plugin, arguments, expected_type)?;
+    let entry = InsertCacheEntry {
+        plugin,
+        plugin_type: &plugin_type,
+        arguments,
+        value: serde_json::to_string(&value).expect("values can always be serialized"),
+        fetched_at: now,
+    };
+    with_db!(db_client, |db| db.transaction(|| {
+        diesel::delete(schema::plugin_cache::table.find((plugin, &plugin_type, arguments)))
+            .execute(db)?;
+        diesel::insert_into(schema::plugin_cache::table)
+            .values(&entry)
+            .execute(db)
+    }))?;
+
+    Ok(value)
+}
+
+/// Like `query`, but the answer has to be a plain number
+pub(crate) fn query_number(
+    db_client: Option<&DbConnection>,
+    plugins: &mut Plugins,
+    cfg: Option<&CacheCfg>,
+    query_str: &str,
+    expected_type: PluginType,
+) -> Result<Decimal, Error> {
+    let value = query(db_client, plugins, cfg, query_str, expected_type)?;
+    value.number().ok_or_else(|| {
+        let (plugin, _) = parse_query(query_str).expect("query was parsed before");
+        Error::PluginError(crate::PluginError::WrongAnswerType(plugin.to_string()))
+    })
+}
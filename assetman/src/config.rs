@@ -0,0 +1,396 @@
+use crate::anomalies::AnomalyCfg;
+use crate::cache::CacheCfg;
+use crate::notifiers::{self, NotifierCfg};
+use crate::plugins::PluginCfg;
+use crate::retention::RetentionPolicy;
+use crate::sync::AssetDecl;
+use crate::transactions::TaxYearStart;
+use crate::{AssetsCfg, Error};
+use assetman_api::Decimal;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Preferences for how reports are shown if no options say otherwise
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayCfg {
+    /// Sort lists by value instead of by name
+    pub sort_by_value: bool,
+    /// Keys to group lists by, see `Assets::group_assets`
+    pub group_by: Vec<String>,
+}
+
+/// Contents of the config file, every key is optional so that it can be set via the
+/// environment instead
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    database: Option<String>,
+    plugins: Option<Vec<PluginEntry>>,
+    base_currency: Option<String>,
+    cache: Option<CacheEntry>,
+    notify: Option<Vec<String>>,
+    max_price_change: Option<Decimal>,
+    retention: Option<String>,
+    tax_year_start: Option<String>,
+    display: DisplayCfg,
+    groups: BTreeMap<String, Vec<String>>,
+    assets: Option<Vec<AssetDecl>>,
+}
+
+/// A plugin is either just its path or a table with its settings
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PluginEntry {
+    Path(PathBuf),
+    Table(PluginTable),
+}
+
+/// Untagged enum variants can't deny unknown fields themselves, so the table is its own struct
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PluginTable {
+    path: PathBuf,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    /// Seconds to wait for an answer
+    timeout: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CacheEntry {
+    ttl: Option<u64>,
+    plugins: HashMap<String, u64>,
+}
+
+/// Location of the config file if none is given, usually `~/.config/assetman/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    dirs_next::config_dir().map(|dir| dir.join("assetman").join("config.toml"))
+}
+
+impl AssetsCfg {
+    /// Reads the config file at `path`, or at `default_path` if it exists, and overrides its
+    /// keys with the `AM_*` environment variables that are set
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let path = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => default_path().filter(|path| path.exists()),
+        };
+        let content = match &path {
+            Some(path) => read_config(path)?,
+            None => String::new(),
+        };
+        AssetsCfg::from_sources(&content, |var| dotenv::var(var).ok()).map_err(|e| {
+            match (e, &path) {
+                (Error::InvalidConfig("config file", reason), Some(path)) => {
+                    Error::InvalidConfig("config file", format!("{}: {}", path.display(), reason))
+                }
+                (e, _) => e,
+            }
+        })
+    }
+
+    /// Reads the config from the environment only
+    pub fn from_env() -> Result<Self, Error> {
+        AssetsCfg::from_sources("", |var| dotenv::var(var).ok())
+    }
+
+    /// Parses a config file without looking at the environment
+    pub fn from_toml(toml: &str) -> Result<Self, Error> {
+        AssetsCfg::from_sources(toml, |_| None)
+    }
+
+    /// Asset names of a named group from the `[groups]` section
+    pub fn group(&self, name: &str) -> Result<&[String], Error> {
+        self.groups
+            .get(name)
+            .map(Vec::as_slice)
+            .ok_or_else(|| Error::GroupNotFound(name.to_string()))
+    }
+
+    fn from_sources(toml: &str, var: impl Fn(&str) -> Option<String>) -> Result<Self, Error> {
+        let file = toml::from_str::<ConfigFile>(toml)
+            .map_err(|e| Error::InvalidConfig("config file", e.to_string()))?;
+
+        let db_path = var("AM_DATABASE")
+            .or(file.database)
+            .ok_or(Error::MissingConfig("database"))?;
+
+        let configured = file
+            .plugins
+            .map(|plugins| plugins.into_iter().map(PluginCfg::from).collect::<Vec<_>>());
+        let plugins = match (var("AM_PLUGINS"), configured) {
+            // keep the settings of plugins that are also in the config file
+            (Some(paths), configured) => {
+                let configured = configured.unwrap_or_default();
+                paths
+                    .split(':')
+                    .map(|path| {
+                        configured
+                            .iter()
+                            .find(|plugin| plugin.path == Path::new(path))
+                            .cloned()
+                            .unwrap_or_else(|| PluginCfg::from(PathBuf::from(path)))
+                    })
+                    .collect()
+            }
+            (None, Some(configured)) => configured,
+            (None, None) => return Err(Error::MissingConfig("plugins")),
+        };
+
+        let cache = match (var("AM_CACHE_TTL"), file.cache) {
+            (Some(ttl), _) => ttl
+                .parse()
+                .map_err(|e| Error::InvalidConfig("AM_CACHE_TTL", e))?,
+            (None, Some(cache)) => {
+                let default = CacheCfg::default();
+                CacheCfg {
+                    default_ttl: cache
+                        .ttl
+                        .map(Duration::from_secs)
+                        .unwrap_or(default.default_ttl),
+                    plugin_ttl: cache
+                        .plugins
+                        .into_iter()
+                        .map(|(plugin, secs)| (plugin, Duration::from_secs(secs)))
+                        .collect(),
+                }
+            }
+            (None, None) => CacheCfg::default(),
+        };
+
+        let notifiers = match (var("AM_NOTIFY"), file.notify) {
+            (Some(notifiers), _) => notifiers::parse_notifiers(&notifiers)
+                .map_err(|e| Error::InvalidConfig("AM_NOTIFY", e))?,
+            (None, Some(notifiers)) => notifiers
+                .iter()
+                .map(|notifier| notifier.parse::<NotifierCfg>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| Error::InvalidConfig("notify", e))?,
+            (None, None) => vec![NotifierCfg::Stdout],
+        };
+
+        let max_price_change = match var("AM_MAX_PRICE_CHANGE") {
+            Some(percent) => Some(
+                percent
+                    .trim()
+                    .parse::<Decimal>()
+                    .map_err(|e| Error::InvalidConfig("AM_MAX_PRICE_CHANGE", e.to_string()))?,
+            ),
+            None => file.max_price_change,
+        };
+        let anomalies = match max_price_change {
+            Some(percent) => AnomalyCfg {
+                max_price_change: percent / Decimal::new(100, 0),
+            },
+            None => AnomalyCfg::default(),
+        };
+
+        let retention = match var("AM_RETENTION") {
+            Some(retention) => Some(
+                retention
+                    .parse::<RetentionPolicy>()
+                    .map_err(|e| Error::InvalidConfig("AM_RETENTION", e))?,
+            ),
+            None => file
+                .retention
+                .map(|retention| retention.parse::<RetentionPolicy>())
+                .transpose()
+                .map_err(|e| Error::InvalidConfig("retention", e))?,
+        };
+
+        let tax_year_start = match var("AM_TAX_YEAR_START") {
+            Some(start) => start
+                .parse::<TaxYearStart>()
+                .map_err(|e| Error::InvalidConfig("AM_TAX_YEAR_START", e))?,
+            None => file
+                .tax_year_start
+                .map(|start| start.parse::<TaxYearStart>())
+                .transpose()
+                .map_err(|e| Error::InvalidConfig("tax_year_start", e))?
+                .unwrap_or_default(),
+        };
+
+        Ok(AssetsCfg {
+            db_path,
+            plugins,
+            cache,
+            notifiers,
+            anomalies,
+            retention,
+            base_currency: var("AM_BASE_CURRENCY").or(file.base_currency),
+            tax_year_start,
+            display: file.display,
+            groups: file.groups,
+            assets: file.assets,
+        })
+    }
+}
+
+fn read_config(path: &Path) -> Result<String, Error> {
+    std::fs::read_to_string(path).map_err(|e| {
+        Error::InvalidConfig(
+            "config file",
+            format!("could not read {}: {}", path.display(), e),
+        )
+    })
+}
+
+impl From<PluginEntry> for PluginCfg {
+    fn from(entry: PluginEntry) -> Self {
+        match entry {
+            PluginEntry::Path(path) => PluginCfg::from(path),
+            PluginEntry::Table(PluginTable { path, env, timeout }) => PluginCfg {
+                path,
+                env,
+                timeout: timeout.map(Duration::from_secs),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::notifiers::NotifierCfg;
+    use crate::retention::{Resolution, RetentionPolicy};
+    use crate::transactions::TaxYearStart;
+    use crate::{AssetsCfg, Error};
+    use assetman_api::Decimal;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    const CONFIG: &str = r#"
+        database = "/data/assets.db"
+        base_currency = "EUR"
+        notify = ["stdout", "command:/bin/hook"]
+        max_price_change = 25
+        retention = "30=all,365=daily,weekly"
+        tax_year_start = "04-06"
+
+        [cache]
+        ttl = 600
+        plugins = { bitstamp = 60 }
+
+        [[plugins]]
+        path = "assetman-static"
+
+        [[plugins]]
+        path = "assetman-bitcoin-holdings"
+        timeout = 30
+        env = { AM_ELECTRUM_SERVER = "ssl://electrum.example.org:50002" }
+
+        [display]
+        sort_by_value = true
+        group_by = ["category", "owner"]
+
+        [groups]
+        crypto = ["Bitcoin", "Ether"]
+
+        [[assets]]
+        name = "Bitcoin"
+        category = "crypto"
+        price = "bitstamp(BTCEUR)"
+        holdings = "static(0.5)"
+        tags = { owner = "alice" }
+    "#;
+
+    #[test]
+    fn test_config_file() {
+        let cfg = AssetsCfg::from_toml(CONFIG).unwrap();
+        assert_eq!(cfg.db_path, "/data/assets.db");
+        assert_eq!(cfg.base_currency.as_deref(), Some("EUR"));
+        assert_eq!(
+            cfg.notifiers,
+            vec![
+                NotifierCfg::Stdout,
+                NotifierCfg::Command(PathBuf::from("/bin/hook"))
+            ]
+        );
+        assert_eq!(cfg.anomalies.max_price_change, Decimal::new(25, 2));
+        assert_eq!(cfg.cache.ttl("bitstamp"), Duration::from_secs(60));
+        assert_eq!(cfg.cache.ttl("static"), Duration::from_secs(600));
+        assert_eq!(cfg.retention, Some(RetentionPolicy::default()));
+        assert_eq!(cfg.tax_year_start, TaxYearStart { month: 4, day: 6 });
+
+        assert_eq!(cfg.plugins.len(), 2);
+        assert_eq!(cfg.plugins[0].path, PathBuf::from("assetman-static"));
+        assert_eq!(cfg.plugins[0].timeout, None);
+        assert_eq!(cfg.plugins[1].timeout, Some(Duration::from_secs(30)));
+        assert_eq!(
+            cfg.plugins[1].env["AM_ELECTRUM_SERVER"],
+            "ssl://electrum.example.org:50002"
+        );
+
+        assert!(cfg.display.sort_by_value);
+        assert_eq!(cfg.display.group_by, vec!["category", "owner"]);
+        assert_eq!(cfg.group("crypto").unwrap(), ["Bitcoin", "Ether"]);
+        let declared = cfg.assets.as_ref().unwrap();
+        assert_eq!(declared[0].price_query, "bitstamp(BTCEUR)");
+        assert!(!declared[0].liability);
+        assert_eq!(declared[0].tags["owner"], "alice");
+        match cfg.group("stocks") {
+            Err(Error::GroupNotFound(group)) => assert_eq!(group, "stocks"),
+            res => panic!("expected unknown group, got {:?}", res),
+        }
+
+        // plugins can also be given as plain paths
+        let cfg = AssetsCfg::from_toml(
+            "database = \"assets.db\"\nplugins = [\"assetman-static\", \"assetman-csv-scan\"]",
+        )
+        .unwrap();
+        assert_eq!(cfg.plugins[1].path, PathBuf::from("assetman-csv-scan"));
+        assert_eq!(cfg.assets, None);
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let env = vec![
+            ("AM_DATABASE", "/tmp/other.db"),
+            (
+                "AM_PLUGINS",
+                "assetman-bitcoin-holdings:assetman-metal-price",
+            ),
+            ("AM_MAX_PRICE_CHANGE", "80"),
+            ("AM_RETENTION", "monthly"),
+            ("AM_TAX_YEAR_START", "07-01"),
+        ]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+        let cfg =
+            AssetsCfg::from_sources(CONFIG, |var| env.get(var).map(|value| value.to_string()))
+                .unwrap();
+
+        assert_eq!(cfg.db_path, "/tmp/other.db");
+        assert_eq!(cfg.anomalies.max_price_change, Decimal::new(8, 1));
+        assert_eq!(cfg.retention.unwrap().older, Resolution::Monthly);
+        assert_eq!(cfg.tax_year_start.to_string(), "07-01");
+        // untouched keys still come from the file
+        assert_eq!(cfg.base_currency.as_deref(), Some("EUR"));
+        assert_eq!(cfg.cache.ttl("bitstamp"), Duration::from_secs(60));
+        // plugins listed in the environment keep their settings from the file
+        assert_eq!(cfg.plugins.len(), 2);
+        assert_eq!(cfg.plugins[0].timeout, Some(Duration::from_secs(30)));
+        assert_eq!(cfg.plugins[1].path, PathBuf::from("assetman-metal-price"));
+        assert!(cfg.plugins[1].env.is_empty());
+
+        match AssetsCfg::from_toml("plugins = [\"assetman-static\"]") {
+            Err(Error::MissingConfig("database")) => {}
+            res => panic!("expected missing database, got {:?}", res),
+        }
+        match AssetsCfg::from_toml("database = \"assets.db\"\nplugin = []") {
+            Err(Error::InvalidConfig("config file", _)) => {}
+            res => panic!("expected invalid config, got {:?}", res),
+        }
+        // misspelled plugin settings must not be ignored silently
+        match AssetsCfg::from_toml(
+            "database = \"assets.db\"\n[[plugins]]\npath = \"assetman-static\"\ntimout = 30",
+        ) {
+            Err(Error::InvalidConfig("config file", _)) => {}
+            res => panic!("expected invalid config, got {:?}", res),
+        }
+    }
+}
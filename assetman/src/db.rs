@@ -0,0 +1,396 @@
+use crate::decimal::DbDecimal;
+use crate::store::{NewAsset, Store, StoredAsset, StoredValue, Update};
+use crate::{schema, Error, InsertHoldings, InsertPrices, InsertUpdate};
+use chrono::NaiveDateTime;
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::Integer;
+use diesel::sqlite::SqliteConnection;
+
+#[cfg(feature = "postgres")]
+use diesel::pg::PgConnection;
+
+mod sqlite_migrations {
+    embed_migrations!("migrations");
+    pub(super) use self::embedded_migrations::run;
+}
+
+/// SQLite can't change column types and has no sequences, so PostgreSQL starts with the current
+/// schema. New migrations have to be added to both directories, a test checks that both end up
+/// with the same tables.
+#[cfg(feature = "postgres")]
+mod postgres_migrations {
+    embed_migrations!("migrations_postgres");
+    pub(super) use self::embedded_migrations::run;
+}
+
+/// Connection to one of the supported databases. Queries are written once and run against
+/// whichever database is connected with `with_db!`.
+pub(crate) enum DbConnection {
+    Sqlite(SqliteConnection),
+    #[cfg(feature = "postgres")]
+    Postgres(PgConnection),
+}
+
+/// Runs `$body` with `$conn` bound to the concrete connection, so the body is compiled once per
+/// database
+macro_rules! with_db {
+    ($db:expr, |$conn:ident| $body:expr) => {
+        match $db {
+            $crate::db::DbConnection::Sqlite($conn) => $body,
+            #[cfg(feature = "postgres")]
+            $crate::db::DbConnection::Postgres($conn) => $body,
+        }
+    };
+}
+
+impl DbConnection {
+    /// Connects to a PostgreSQL database if `url` starts with `postgres://` or `postgresql://`,
+    /// otherwise opens or creates the SQLite database at that path
+    pub(crate) fn establish(url: &str) -> Result<DbConnection, Error> {
+        if !is_postgres_url(url) {
+            return Ok(DbConnection::Sqlite(SqliteConnection::establish(url)?));
+        }
+
+        #[cfg(feature = "postgres")]
+        return Ok(DbConnection::Postgres(PgConnection::establish(url)?));
+        #[cfg(not(feature = "postgres"))]
+        Err(Error::InvalidConfig(
+            "database",
+            "PostgreSQL support isn't compiled in, build with `--features postgres`".to_string(),
+        ))
+    }
+
+    pub(crate) fn run_migrations(&self) -> Result<(), Error> {
+        match self {
+            DbConnection::Sqlite(conn) => sqlite_migrations::run(conn)?,
+            #[cfg(feature = "postgres")]
+            DbConnection::Postgres(conn) => postgres_migrations::run(conn)?,
+        }
+        Ok(())
+    }
+}
+
+fn is_postgres_url(url: &str) -> bool {
+    url.starts_with("postgres://") || url.starts_with("postgresql://")
+}
+
+/// Id of the row this connection inserted last into a table with an `id` column. Unlike
+/// looking up the highest id this isn't confused by other clients inserting at the same time.
+pub(crate) trait LastInsertId {
+    fn last_insert_id(&self, table: &str) -> QueryResult<i32>;
+}
+
+impl LastInsertId for SqliteConnection {
+    fn last_insert_id(&self, _table: &str) -> QueryResult<i32> {
+        diesel::select(sql::<Integer>("last_insert_rowid()")).get_result(self)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl LastInsertId for PgConnection {
+    fn last_insert_id(&self, table: &str) -> QueryResult<i32> {
+        diesel::select(sql::<Integer>(&format!(
+            "CAST(currval(pg_get_serial_sequence('{}', 'id')) AS INTEGER)",
+            table
+        )))
+        .get_result(self)
+    }
+}
+
+impl DbConnection {
+    fn load_values(
+        &self,
+        update_id: Option<i32>,
+        asset_id: Option<i32>,
+    ) -> Result<Vec<StoredValue>, Error> {
+        Ok(with_db!(self, |db| {
+            let mut query = schema::prices::table
+                .inner_join(
+                    schema::holdings::table.on(schema::holdings::update_id
+                        .eq(schema::prices::update_id)
+                        .and(schema::holdings::asset_id.eq(schema::prices::asset_id))),
+                )
+                .select((
+                    schema::prices::update_id,
+                    schema::prices::asset_id,
+                    schema::prices::price,
+                    schema::holdings::amount,
+                ))
+                .order_by((schema::prices::update_id, schema::prices::asset_id))
+                .into_boxed();
+            if let Some(update_id) = update_id {
+                query = query.filter(schema::prices::update_id.eq(update_id));
+            }
+            if let Some(asset_id) = asset_id {
+                query = query.filter(schema::prices::asset_id.eq(asset_id));
+            }
+            query.load::<StoredValue>(db)
+        })?)
+    }
+}
+
+impl Store for DbConnection {
+    fn assets(&self) -> Result<Vec<StoredAsset>, Error> {
+        Ok(with_db!(self, |db| schema::assets::table
+            .order_by(schema::assets::name)
+            .load::<StoredAsset>(db))?)
+    }
+
+    fn asset(&self, name: &str) -> Result<Option<StoredAsset>, Error> {
+        Ok(with_db!(self, |db| schema::assets::table
+            .filter(schema::assets::name.eq(name))
+            .first::<StoredAsset>(db)
+            .optional())?)
+    }
+
+    fn add_asset(&self, asset: &NewAsset) -> Result<i32, Error> {
+        Ok(with_db!(self, |db| db.transaction(|| {
+            diesel::insert_into(schema::assets::table)
+                .values(asset)
+                .execute(db)?;
+            db.last_insert_id("assets")
+        }))?)
+    }
+
+    fn update_asset(&self, asset: &StoredAsset) -> Result<(), Error> {
+        let updated = with_db!(self, |db| diesel::update(
+            schema::assets::table.find(asset.id)
+        )
+        .set((
+            schema::assets::name.eq(&asset.name),
+            schema::assets::price_query.eq(&asset.price_query),
+            schema::assets::holdings_query.eq(&asset.holdings_query),
+            schema::assets::category.eq(&asset.category),
+            schema::assets::liability.eq(asset.liability),
+            schema::assets::archived.eq(asset.archived),
+        ))
+        .execute(db))?;
+        if updated == 0 {
+            return Err(Error::AssetNotFound(asset.name.clone()));
+        }
+        Ok(())
+    }
+
+    fn add_update(&self, time: Option<NaiveDateTime>, synthetic: bool) -> Result<i32, Error> {
+        Ok(with_db!(self, |db| db.transaction(|| {
+            match time {
+                Some(timestamp) => diesel::insert_into(schema::updates::table)
+                    .values(InsertUpdate {
+                        timestamp,
+                        synthetic,
+                    })
+                    .execute(db)?,
+                // the database knows the current time in its own resolution
+                None => diesel::insert_into(schema::updates::table)
+                    .values(schema::updates::synthetic.eq(synthetic))
+                    .execute(db)?,
+            };
+            db.last_insert_id("updates")
+        }))?)
+    }
+
+    fn updates(&self) -> Result<Vec<Update>, Error> {
+        Ok(with_db!(self, |db| schema::updates::table
+            .order_by((schema::updates::timestamp, schema::updates::id))
+            .load::<Update>(db))?)
+    }
+
+    fn add_value(&self, value: &StoredValue) -> Result<(), Error> {
+        with_db!(self, |db| db.transaction(|| {
+            diesel::insert_into(schema::prices::table)
+                .values(InsertPrices {
+                    update_id: value.update_id,
+                    asset_id: value.asset_id,
+                    price: DbDecimal(value.price),
+                })
+                .execute(db)?;
+            diesel::insert_into(schema::holdings::table)
+                .values(InsertHoldings {
+                    update_id: value.update_id,
+                    asset_id: value.asset_id,
+                    amount: DbDecimal(value.holdings),
+                })
+                .execute(db)
+        }))?;
+        Ok(())
+    }
+
+    fn values(&self, update_id: Option<i32>) -> Result<Vec<StoredValue>, Error> {
+        self.load_values(update_id, None)
+    }
+
+    fn asset_values(&self, asset_id: i32) -> Result<Vec<StoredValue>, Error> {
+        self.load_values(None, Some(asset_id))
+    }
+
+    fn atomically(&self, f: &mut dyn FnMut() -> Result<(), Error>) -> Result<(), Error> {
+        with_db!(self, |db| db.transaction(f))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sqlite_migrations;
+    use diesel::sql_types::{Integer, Text};
+    use diesel::sqlite::SqliteConnection;
+    use diesel::{Connection, RunQueryDsl};
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    /// Declared type, whether the column can't be null and whether it's part of the primary key
+    type Columns = BTreeMap<String, (String, bool, bool)>;
+    type Schema = BTreeMap<String, Columns>;
+
+    /// Differences SQLite can't catch up with because it can't alter columns
+    const KNOWN_DIFFERENCES: &[(&str, &str)] = &[
+        // created as INTEGER by the first migration, diesel reads it as timestamp either way
+        ("updates", "timestamp"),
+        // nullable since the first migration, every insert sets it
+        ("assets", "category"),
+    ];
+
+    #[derive(QueryableByName)]
+    struct Table {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    #[derive(QueryableByName)]
+    struct Column {
+        #[sql_type = "Text"]
+        name: String,
+        #[sql_type = "Text"]
+        #[column_name = "type"]
+        column_type: String,
+        #[sql_type = "Integer"]
+        notnull: i32,
+        #[sql_type = "Integer"]
+        pk: i32,
+    }
+
+    fn sqlite_schema() -> Schema {
+        let db = SqliteConnection::establish(":memory:").unwrap();
+        sqlite_migrations::run(&db).unwrap();
+        diesel::sql_query(
+            "SELECT name FROM sqlite_master WHERE type = 'table' \
+             AND name NOT LIKE 'sqlite_%' AND name NOT LIKE '__diesel%'",
+        )
+        .load::<Table>(&db)
+        .unwrap()
+        .into_iter()
+        .map(|table| {
+            let columns = diesel::sql_query(format!("PRAGMA table_info({})", table.name))
+                .load::<Column>(&db)
+                .unwrap()
+                .into_iter()
+                .map(|column| {
+                    let pk = column.pk > 0;
+                    let column_type = column.column_type.to_uppercase();
+                    (column.name, (column_type, column.notnull == 1 || pk, pk))
+                })
+                .collect();
+            (table.name, columns)
+        })
+        .collect()
+    }
+
+    /// Replays the PostgreSQL migrations, which only create, drop and add columns to tables
+    fn postgres_schema() -> Schema {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations_postgres");
+        let mut migrations = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path().join("up.sql"))
+            .collect::<Vec<_>>();
+        migrations.sort();
+
+        let mut schema = Schema::new();
+        for migration in migrations {
+            let sql = std::fs::read_to_string(migration).unwrap();
+            let sql = sql
+                .lines()
+                .map(|line| line.split("--").next().unwrap())
+                .collect::<Vec<_>>()
+                .join("\n");
+            for statement in sql.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+                let words = statement.split_whitespace().collect::<Vec<_>>();
+                match &words[..] {
+                    ["CREATE", "TABLE", name, ..] => {
+                        let body = &statement[statement.find('(').unwrap() + 1..];
+                        let body = &body[..body.rfind(')').unwrap()];
+                        schema.insert(name.to_string(), create_table(body));
+                    }
+                    ["DROP", "TABLE", name] => {
+                        schema.remove(*name).unwrap();
+                    }
+                    ["ALTER", "TABLE", name, "ADD", "COLUMN", ..] => {
+                        let definition = statement.split_once("ADD COLUMN").unwrap().1;
+                        let (column, definition) = column(definition);
+                        schema.get_mut(*name).unwrap().insert(column, definition);
+                    }
+                    _ => panic!("can't compare statement: {}", statement),
+                }
+            }
+        }
+        schema
+    }
+
+    fn create_table(body: &str) -> Columns {
+        // split at commas outside of parentheses
+        let mut definitions = vec![String::new()];
+        let mut depth = 0;
+        for c in body.chars() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    definitions.push(String::new());
+                    continue;
+                }
+                _ => {}
+            }
+            definitions.last_mut().unwrap().push(c);
+        }
+
+        let mut columns = Columns::new();
+        for definition in &definitions {
+            let definition = definition.trim();
+            if let Some(key) = definition.strip_prefix("PRIMARY KEY") {
+                for name in key.trim().trim_matches(|c| c == '(' || c == ')').split(',') {
+                    let column = columns.get_mut(name.trim()).unwrap();
+                    column.1 = true;
+                    column.2 = true;
+                }
+            } else {
+                let (name, column) = column(definition);
+                columns.insert(name, column);
+            }
+        }
+        columns
+    }
+
+    fn column(definition: &str) -> (String, (String, bool, bool)) {
+        let mut words = definition.split_whitespace();
+        let name = words.next().unwrap().to_string();
+        let column_type = match words.next().unwrap() {
+            "SERIAL" => "INTEGER".to_string(),
+            other => other.to_string(),
+        };
+        let pk = definition.contains("PRIMARY KEY");
+        (
+            name,
+            (column_type, pk || definition.contains("NOT NULL"), pk),
+        )
+    }
+
+    #[test]
+    fn test_postgres_schema_matches_sqlite() {
+        let mut sqlite = sqlite_schema();
+        let mut postgres = postgres_schema();
+        for (table, column) in KNOWN_DIFFERENCES {
+            sqlite.get_mut(*table).unwrap().remove(*column).unwrap();
+            postgres.get_mut(*table).unwrap().remove(*column).unwrap();
+        }
+        assert_eq!(postgres, sqlite);
+    }
+}
@@ -0,0 +1,48 @@
+use assetman_api::Decimal;
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Text;
+use std::io::Write;
+
+/// Decimal stored as text, so that no precision is lost in the database
+#[derive(Debug, Clone, Copy, PartialEq, AsExpression, FromSqlRow)]
+#[sql_type = "Text"]
+pub struct DbDecimal(pub Decimal);
+
+impl<DB: Backend> FromSql<Text, DB> for DbDecimal
+where
+    String: FromSql<Text, DB>,
+{
+    fn from_sql(value: Option<&DB::RawValue>) -> deserialize::Result<Self> {
+        let text = <String as FromSql<Text, DB>>::from_sql(value)?;
+        let decimal =
+            DbDecimal::parse(&text).ok_or_else(|| format!("'{}' isn't a decimal", text))?;
+        Ok(DbDecimal(decimal))
+    }
+}
+
+impl DbDecimal {
+    /// Parses a stored decimal, `None` if it isn't a finite number like `NaN`
+    pub(crate) fn parse(text: &str) -> Option<Decimal> {
+        // values migrated from floating point columns may use scientific notation
+        text.parse()
+            .or_else(|_| Decimal::from_scientific(text))
+            .ok()
+    }
+}
+
+impl<DB: Backend> ToSql<Text, DB> for DbDecimal
+where
+    String: ToSql<Text, DB>,
+{
+    fn to_sql<W: Write>(&self, out: &mut Output<W, DB>) -> serialize::Result {
+        <String as ToSql<Text, DB>>::to_sql(&self.0.normalize().to_string(), out)
+    }
+}
+
+impl From<DbDecimal> for Decimal {
+    fn from(decimal: DbDecimal) -> Self {
+        decimal.0
+    }
+}
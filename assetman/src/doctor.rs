@@ -0,0 +1,160 @@
+use crate::plugins::{PluginCfg, PluginError, Plugins};
+use crate::{Assets, Error};
+use assetman_api::PluginType::{self, Price};
+use assetman_api::{Decimal, PluginInfo};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Outcome of starting a single plugin
+#[derive(Debug)]
+pub struct PluginCheck {
+    pub path: PathBuf,
+    pub duration: Duration,
+    pub result: Result<PluginInfo, PluginError>,
+    /// Problem of a plugin that started successfully but can't be used as configured
+    pub warning: Option<PluginWarning>,
+}
+
+/// Plugin setups that work but most likely aren't intended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PluginWarning {
+    /// The plugin has the same name as the plugin at the given path, which was loaded earlier
+    /// and is replaced by it
+    Replaces(PathBuf),
+    /// Queries can't refer to the plugin since its name is empty or contains '('
+    UnusableName,
+}
+
+/// Outcome of running a single stored query
+#[derive(Debug)]
+pub struct QueryCheck {
+    pub asset: String,
+    pub query: String,
+    pub query_type: PluginType,
+    pub duration: Duration,
+    pub result: Result<Decimal, PluginError>,
+}
+
+/// Starts every plugin individually, so that one broken plugin doesn't prevent checking the
+/// others. Returns the registry of all plugins that started successfully.
+pub fn check_plugins<'a>(cfgs: impl Iterator<Item = &'a PluginCfg>) -> (Plugins, Vec<PluginCheck>) {
+    let mut plugins = Plugins::default();
+    let mut loaded = HashMap::new();
+    let checks = cfgs
+        .map(|cfg| {
+            let start = Instant::now();
+            let result = plugins.load_with(cfg).cloned();
+            let duration = start.elapsed();
+            let warning = match &result {
+                Ok(info) => plugin_warning(&mut loaded, info, &cfg.path),
+                Err(_) => None,
+            };
+            PluginCheck {
+                path: cfg.path.clone(),
+                duration,
+                result,
+                warning,
+            }
+        })
+        .collect();
+
+    (plugins, checks)
+}
+
+/// Checks the name of a plugin loaded from `path`, `loaded` maps the names of all previously
+/// loaded plugins to their paths
+fn plugin_warning(
+    loaded: &mut HashMap<String, PathBuf>,
+    info: &PluginInfo,
+    path: &Path,
+) -> Option<PluginWarning> {
+    if info.name.is_empty() || info.name.contains('(') {
+        return Some(PluginWarning::UnusableName);
+    }
+    loaded
+        .insert(info.name.clone(), path.to_path_buf())
+        .map(PluginWarning::Replaces)
+}
+
+impl Display for PluginWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginWarning::Replaces(path) => {
+                write!(
+                    f,
+                    "replaces the plugin of the same name at {}",
+                    path.display()
+                )
+            }
+            PluginWarning::UnusableName => {
+                write!(f, "name can't be used in queries")
+            }
+        }
+    }
+}
+
+impl Assets {
+    /// Runs the price and holdings query of every asset that isn't archived and reports each
+    /// result individually instead of aborting on the first error like `fetch_data` does
+    pub fn check_queries(&mut self) -> Result<Vec<QueryCheck>, Error> {
+        let mut assets = self.store().assets()?;
+        assets.retain(|asset| !asset.archived);
+
+        let mut checks = Vec::with_capacity(assets.len() * 2);
+        for asset in assets {
+            let holdings_type = asset.holdings_type();
+            for (query, query_type) in [
+                (asset.price_query, Price),
+                (asset.holdings_query, holdings_type),
+            ] {
+                let start = Instant::now();
+                let result = self.plugins.query_number(&query, query_type);
+                checks.push(QueryCheck {
+                    asset: asset.name.clone(),
+                    query,
+                    query_type,
+                    duration: start.elapsed(),
+                    result,
+                });
+            }
+        }
+
+        Ok(checks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str) -> PluginInfo {
+        PluginInfo {
+            name: name.to_string(),
+            plugin_type: Price,
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_plugin_warning() {
+        let mut loaded = HashMap::new();
+        let first = PathBuf::from("/plugins/first");
+        let second = PathBuf::from("/plugins/second");
+
+        assert_eq!(plugin_warning(&mut loaded, &info("price"), &first), None);
+        assert_eq!(
+            plugin_warning(&mut loaded, &info("price"), &second),
+            Some(PluginWarning::Replaces(first))
+        );
+        assert_eq!(
+            plugin_warning(&mut loaded, &info(""), &second),
+            Some(PluginWarning::UnusableName)
+        );
+        assert_eq!(
+            plugin_warning(&mut loaded, &info("a(b"), &second),
+            Some(PluginWarning::UnusableName)
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use crate::store::{NewAsset, StoredValue};
+use crate::{Assets, Error};
+use assetman_api::Decimal;
+use chrono::NaiveDate;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Read;
+
+/// Query of assets created by a history import, it doesn't refer to any plugin. Such assets are
+/// archived until they are declared with real queries and synced.
+pub const PLACEHOLDER_QUERY: &str = "placeholder()";
+
+/// Which spreadsheet columns hold what, by their header. Columns that aren't in the header are
+/// ignored except for `date` and `asset`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnMapping {
+    pub date: String,
+    pub asset: String,
+    pub holdings: String,
+    pub price: String,
+    /// Holdings times price, used to derive whichever of both is missing
+    pub value: String,
+    /// Category of assets that don't exist yet, `default_category` if missing
+    pub category: String,
+    /// `chrono` format of the date column, e.g. `%d.%m.%Y`
+    pub date_format: String,
+    pub delimiter: u8,
+    /// Numbers use `,` as decimal separator and `.` to group thousands
+    pub decimal_comma: bool,
+    pub default_category: String,
+}
+
+/// Outcome of a history import
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistoryImport {
+    /// Columns found in the header, in the order of `ColumnMapping`
+    pub columns: Vec<String>,
+    /// Number of updates created, one per date that didn't have one yet
+    pub updates: usize,
+    /// Number of rows stored as price and holdings of an asset
+    pub rows: usize,
+    /// Assets created with placeholder queries
+    pub new_assets: Vec<String>,
+    pub skipped: Vec<SkippedRow>,
+}
+
+/// A row that couldn't be mapped and wasn't imported
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedRow {
+    /// Line in the file, starting at 1 for the header
+    pub line: u64,
+    pub reason: String,
+}
+
+/// Price and holdings of one row
+struct Row {
+    date: NaiveDate,
+    asset: String,
+    category: Option<String>,
+    price: Decimal,
+    holdings: Decimal,
+}
+
+/// Column indices of the mapped columns
+struct Columns {
+    date: usize,
+    asset: usize,
+    holdings: Option<usize>,
+    price: Option<usize>,
+    value: Option<usize>,
+    category: Option<usize>,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        ColumnMapping {
+            date: "date".to_string(),
+            asset: "asset".to_string(),
+            holdings: "holdings".to_string(),
+            price: "price".to_string(),
+            value: "value".to_string(),
+            category: "category".to_string(),
+            date_format: "%Y-%m-%d".to_string(),
+            delimiter: b',',
+            decimal_comma: false,
+            default_category: "imported".to_string(),
+        }
+    }
+}
+
+impl Assets {
+    /// Imports a spreadsheet with one row per asset and date as regular updates at midnight of
+    /// each date. Rows with holdings and price are taken as they are, rows with a value and one
+    /// of both get the other one derived and rows with only a value are stored with a price of
+    /// 1. Assets that don't exist yet are created archived with placeholder queries.
+    ///
+    /// Rows that can't be mapped or whose asset already has values at that date are skipped and
+    /// reported, everything else is imported at once.
+    pub fn import_history(
+        &mut self,
+        input: impl Read,
+        mapping: &ColumnMapping,
+    ) -> Result<HistoryImport, Error> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(mapping.delimiter)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(input);
+        let header = reader
+            .headers()
+            .map_err(|e| Error::InvalidSpreadsheet(e.to_string()))?
+            .clone();
+        let find = |name: &str| header.iter().position(|column| column == name);
+        let columns = Columns {
+            date: find(&mapping.date).ok_or_else(|| missing_column(&mapping.date))?,
+            asset: find(&mapping.asset).ok_or_else(|| missing_column(&mapping.asset))?,
+            holdings: find(&mapping.holdings),
+            price: find(&mapping.price),
+            value: find(&mapping.value),
+            category: find(&mapping.category),
+        };
+        if columns.price.is_none() && columns.value.is_none() {
+            return Err(Error::InvalidSpreadsheet(format!(
+                "neither a '{}' nor a '{}' column",
+                mapping.price, mapping.value
+            )));
+        }
+
+        let mut summary = HistoryImport {
+            columns: [
+                Some(columns.date),
+                Some(columns.asset),
+                columns.holdings,
+                columns.price,
+                columns.value,
+                columns.category,
+            ]
+            .iter()
+            .flatten()
+            .map(|index| header[*index].to_string())
+            .collect(),
+            ..HistoryImport::default()
+        };
+        let mut rows = BTreeMap::<NaiveDate, Vec<(u64, Row)>>::new();
+        for record in reader.records() {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    let line = e.position().map(|position| position.line()).unwrap_or(0);
+                    summary.skipped.push(SkippedRow {
+                        line,
+                        reason: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            let line = record
+                .position()
+                .map(|position| position.line())
+                .unwrap_or(0);
+            match parse_row(&record, &columns, mapping) {
+                Ok(row) => rows.entry(row.date).or_default().push((line, row)),
+                Err(reason) => summary.skipped.push(SkippedRow { line, reason }),
+            }
+        }
+
+        let store = self.store();
+        store.atomically(&mut || {
+            let mut asset_ids = store
+                .assets()?
+                .into_iter()
+                .map(|asset| (asset.name, asset.id))
+                .collect::<HashMap<_, _>>();
+            let existing_updates = store
+                .updates()?
+                .into_iter()
+                .filter(|update| !update.synthetic)
+                .map(|update| (update.time, update.id))
+                .collect::<HashMap<_, _>>();
+
+            for (date, rows) in &rows {
+                let timestamp = date.and_hms(0, 0, 0);
+                let update_id = match existing_updates.get(&timestamp) {
+                    Some(id) => *id,
+                    None => {
+                        summary.updates += 1;
+                        store.add_update(Some(timestamp), false)?
+                    }
+                };
+                let mut existing_values = store
+                    .values(Some(update_id))?
+                    .into_iter()
+                    .map(|value| value.asset_id)
+                    .collect::<HashSet<_>>();
+
+                for (line, row) in rows {
+                    let asset_id = match asset_ids.get(&row.asset) {
+                        Some(id) => *id,
+                        None => {
+                            let category =
+                                row.category.as_deref().unwrap_or(&mapping.default_category);
+                            let id = store.add_asset(&NewAsset {
+                                name: &row.asset,
+                                price_query: PLACEHOLDER_QUERY,
+                                holdings_query: PLACEHOLDER_QUERY,
+                                category,
+                                liability: false,
+                                archived: true,
+                            })?;
+                            asset_ids.insert(row.asset.clone(), id);
+                            summary.new_assets.push(row.asset.clone());
+                            id
+                        }
+                    };
+
+                    if !existing_values.insert(asset_id) {
+                        summary.skipped.push(SkippedRow {
+                            line: *line,
+                            reason: format!("'{}' already has values at {}", row.asset, date),
+                        });
+                        continue;
+                    }
+                    store.add_value(&StoredValue {
+                        update_id,
+                        asset_id,
+                        price: row.price,
+                        holdings: row.holdings,
+                    })?;
+                    summary.rows += 1;
+                }
+            }
+            Ok(())
+        })?;
+
+        summary.skipped.sort_by_key(|skipped| skipped.line);
+        Ok(summary)
+    }
+}
+
+fn missing_column(name: &str) -> Error {
+    Error::InvalidSpreadsheet(format!("no column named '{}'", name))
+}
+
+fn parse_row(
+    record: &csv::StringRecord,
+    columns: &Columns,
+    mapping: &ColumnMapping,
+) -> Result<Row, String> {
+    let cell = |index: Option<usize>| {
+        index
+            .and_then(|index| record.get(index))
+            .filter(|cell| !cell.is_empty())
+    };
+    let number = |index: Option<usize>| {
+        cell(index)
+            .map(|cell| parse_number(cell, mapping.decimal_comma))
+            .transpose()
+    };
+
+    let date = cell(Some(columns.date)).ok_or("the date is missing")?;
+    let date = NaiveDate::parse_from_str(date, &mapping.date_format)
+        .map_err(|_| format!("'{}' isn't a date like {}", date, mapping.date_format))?;
+    let asset = cell(Some(columns.asset)).ok_or("the asset is missing")?;
+
+    // a huge value divided by a tiny price or holdings doesn't fit into a decimal
+    let divide = |value: Decimal, by: Decimal| {
+        value
+            .checked_div(by)
+            .ok_or_else(|| format!("{} divided by {} is out of range", value, by))
+    };
+
+    let (price, holdings) = match (
+        number(columns.price)?,
+        number(columns.holdings)?,
+        number(columns.value)?,
+    ) {
+        (Some(price), Some(holdings), _) => (price, holdings),
+        (Some(price), None, Some(value)) if !price.is_zero() => (price, divide(value, price)?),
+        (None, Some(holdings), Some(value)) if !holdings.is_zero() => {
+            (divide(value, holdings)?, holdings)
+        }
+        (None, None, Some(value)) => (Decimal::ONE, value),
+        (_, _, Some(_)) => return Err("can't derive holdings or price from zero".to_string()),
+        (_, _, None) => return Err("needs holdings and price or a value".to_string()),
+    };
+
+    Ok(Row {
+        date,
+        asset: asset.to_string(),
+        category: cell(columns.category).map(str::to_string),
+        price,
+        holdings,
+    })
+}
+
+/// Parses numbers as spreadsheets print them, e.g. `1,234.50`, `1'234.50` or `1.234,50`
+fn parse_number(cell: &str, decimal_comma: bool) -> Result<Decimal, String> {
+    let (separator, point) = if decimal_comma {
+        ('.', ',')
+    } else {
+        (',', '.')
+    };
+    let number = cell
+        .chars()
+        .filter(|c| *c != separator && *c != '\'' && !c.is_whitespace())
+        .map(|c| if c == point { '.' } else { c })
+        .collect::<String>();
+    number
+        .parse()
+        .map_err(|_| format!("'{}' isn't a number", cell))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_number;
+    use assetman_api::Decimal;
+
+    #[test]
+    fn test_parse_number() {
+        let dec = |value: &str| value.parse::<Decimal>().unwrap();
+        assert_eq!(parse_number("1,234.50", false).unwrap(), dec("1234.50"));
+        assert_eq!(parse_number("1'234.5", false).unwrap(), dec("1234.5"));
+        assert_eq!(parse_number("-0.001", false).unwrap(), dec("-0.001"));
+        assert_eq!(parse_number("1.234,50", true).unwrap(), dec("1234.50"));
+        assert_eq!(parse_number("1 234,5", true).unwrap(), dec("1234.5"));
+        assert!(parse_number("12 EUR", false).is_err());
+    }
+}
@@ -6,28 +6,96 @@ extern crate diesel;
 extern crate diesel_migrations;
 extern crate serde_json;
 
-use diesel::prelude::*;
-
-use crate::plugins::{PluginError, Plugins};
-use assetman_api::PluginType::{Holdings, Price};
+use crate::anomalies::{Anomaly, AnomalyCfg};
+pub use crate::cache::CacheCfg;
+use crate::config::DisplayCfg;
+use crate::db::DbConnection;
+use crate::decimal::DbDecimal;
+use crate::notifiers::{Notifier, NotifierCfg};
+pub use crate::plugins::{PluginCfg, PluginError, Plugins};
+use crate::retention::RetentionPolicy;
+use crate::store::{NewAsset, Store, StoredAsset, StoredValue};
+use crate::sync::AssetDecl;
+use crate::transactions::TaxYearStart;
+use assetman_api::PluginType::{Holdings, Liabilities, Price};
+use assetman_api::{Decimal, PluginType, TimeRange, Value};
 use chrono::NaiveDateTime;
+use log::error;
 use schema::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
 
-mod plugins;
+// first, so that the other modules can use `with_db!`
+#[macro_use]
+mod db;
+
+pub mod alerts;
+pub mod allocation;
+pub mod anomalies;
+pub mod bundle;
+mod cache;
+pub mod config;
+mod decimal;
+pub mod doctor;
+pub mod history;
+pub mod maintenance;
+pub mod notifiers;
+pub mod plugins;
+pub mod pnl;
+pub mod portfolios;
+pub mod retention;
+pub mod returns;
 mod schema;
-
-embed_migrations!();
+pub mod store;
+pub mod sync;
+pub mod tags;
+pub mod transactions;
 
 pub struct Assets {
-    db_client: diesel::sqlite::SqliteConnection,
+    storage: Storage,
     plugins: Plugins,
+    cache: Option<CacheCfg>,
+    /// Selected portfolio, `None` for the combined view
+    portfolio: Option<String>,
+    notifiers: Vec<Box<dyn Notifier>>,
+    /// `None` disables anomaly detection
+    anomalies: Option<AnomalyCfg>,
+    accept_anomalies: bool,
+    /// Compacts the history after every fetch if set
+    retention: Option<RetentionPolicy>,
+    /// Currency prices are quoted in, transactions have to match it
+    base_currency: Option<String>,
+    tax_year_start: TaxYearStart,
 }
 
+/// Where assets and their values are kept
+enum Storage {
+    Database(DbConnection),
+    /// A store of the library user, everything beyond assets and their values needs a database
+    Custom(Box<dyn Store>),
+}
+
+/// Configuration read from the config file and the environment, see `AssetsCfg::load`
 #[derive(Debug)]
 pub struct AssetsCfg {
     pub db_path: String,
-    pub plugins: Vec<PathBuf>,
+    pub plugins: Vec<PluginCfg>,
+    pub cache: CacheCfg,
+    pub notifiers: Vec<NotifierCfg>,
+    pub anomalies: AnomalyCfg,
+    /// Which updates to keep after every fetch, `None` keeps all of them
+    pub retention: Option<RetentionPolicy>,
+    /// Currency all prices are quoted in, reports comparing transactions to prices require them
+    /// to be in it
+    pub base_currency: Option<String>,
+    /// First day of the tax year realized gains are reported by
+    pub tax_year_start: TaxYearStart,
+    pub display: DisplayCfg,
+    /// Named groups of assets by asset name
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// Declared assets, see `Assets::plan_sync`. `None` if the config doesn't declare any.
+    pub assets: Option<Vec<AssetDecl>>,
 }
 
 #[derive(Debug)]
@@ -36,31 +104,21 @@ pub struct AssetsSnapshot {
     pub assets: Vec<Asset>,
 }
 
-#[derive(Debug, Queryable)]
+#[derive(Debug, Clone)]
 pub struct Asset {
     pub id: i32,
     pub name: String,
-    pub price: f64,
-    pub holdings: f64,
+    pub price: Decimal,
+    pub holdings: Decimal,
     pub category: String,
+    pub liability: bool,
 }
 
 #[derive(Debug, Insertable)]
-#[table_name = "assets"]
-struct InsertAsset<'a> {
-    name: &'a str,
-    price_query: &'a str,
-    holdings_query: &'a str,
-    category: &'a str,
-}
-
-#[derive(Debug, Queryable)]
-struct QueryAsset {
-    id: i32,
-    name: String,
-    price_query: String,
-    holdings_query: String,
-    category: String,
+#[table_name = "updates"]
+struct InsertUpdate {
+    timestamp: NaiveDateTime,
+    synthetic: bool,
 }
 
 #[derive(Debug, Insertable)]
@@ -68,7 +126,7 @@ struct QueryAsset {
 struct InsertHoldings {
     update_id: i32,
     asset_id: i32,
-    amount: f64,
+    amount: DbDecimal,
 }
 
 #[derive(Debug, Insertable)]
@@ -76,58 +134,248 @@ struct InsertHoldings {
 struct InsertPrices {
     update_id: i32,
     asset_id: i32,
-    price: f64,
+    price: DbDecimal,
+}
+
+impl Asset {
+    /// Value of the holdings, negative for liabilities no matter the sign of their holdings
+    pub fn value(&self) -> Decimal {
+        let value = self.price * self.holdings;
+        if self.liability {
+            -value.abs()
+        } else {
+            value
+        }
+    }
+}
+
+impl AssetsSnapshot {
+    /// Sum of all assets that aren't liabilities
+    pub fn gross_assets(&self) -> Decimal {
+        self.assets
+            .iter()
+            .filter(|asset| !asset.liability)
+            .map(Asset::value)
+            .sum()
+    }
+
+    /// Sum of all liabilities as a negative number
+    pub fn liabilities(&self) -> Decimal {
+        self.assets
+            .iter()
+            .filter(|asset| asset.liability)
+            .map(Asset::value)
+            .sum()
+    }
+
+    pub fn net_worth(&self) -> Decimal {
+        self.gross_assets() + self.liabilities()
+    }
+}
+
+impl StoredAsset {
+    /// Liabilities may be queried from plugins that only provide liabilities
+    pub(crate) fn holdings_type(&self) -> PluginType {
+        holdings_type(self.liability)
+    }
+}
+
+fn holdings_type(liability: bool) -> PluginType {
+    if liability {
+        Liabilities
+    } else {
+        Holdings
+    }
+}
+
+impl Storage {
+    fn store(&self) -> &dyn Store {
+        match self {
+            Storage::Database(db_client) => db_client,
+            Storage::Custom(store) => store.as_ref(),
+        }
+    }
+
+    fn db(&self) -> Option<&DbConnection> {
+        match self {
+            Storage::Database(db_client) => Some(db_client),
+            Storage::Custom(_) => None,
+        }
+    }
 }
 
 impl Assets {
     pub fn new(cfg: AssetsCfg) -> Result<Assets, Error> {
-        let assets = Assets {
-            db_client: diesel::SqliteConnection::establish(&cfg.db_path)?,
-            plugins: Plugins::from_paths(cfg.plugins.iter())?,
-        };
+        let mut assets =
+            Assets::with_plugins(&cfg.db_path, Plugins::from_cfgs(cfg.plugins.iter())?)?;
+        assets.set_cache(Some(cfg.cache));
+        assets.set_anomaly_cfg(Some(cfg.anomalies));
+        assets.set_retention(cfg.retention);
+        assets.set_base_currency(cfg.base_currency);
+        assets.set_tax_year_start(cfg.tax_year_start);
+        for notifier in &cfg.notifiers {
+            assets.add_notifier(notifier.build());
+        }
+        Ok(assets)
+    }
 
-        assets.run_migrations()?;
+    /// Opens the database at `db_path` using already loaded plugins and the default cache config
+    pub fn with_plugins(db_path: &str, plugins: Plugins) -> Result<Assets, Error> {
+        let db_client = DbConnection::establish(db_path)?;
+        db_client.run_migrations()?;
+        Ok(Assets::with_storage(Storage::Database(db_client), plugins))
+    }
 
-        Ok(assets)
+    /// Keeps assets, updates, prices and holdings in `store` instead of a database. Everything
+    /// else, like transactions, tags, targets, portfolios, alerts and the plugin cache, is kept in
+    /// the database only and fails with `Error::NoDatabase`.
+    pub fn with_store(store: impl Store + 'static, plugins: Plugins) -> Assets {
+        Assets::with_storage(Storage::Custom(Box::new(store)), plugins)
     }
 
-    fn run_migrations(&self) -> Result<(), Error> {
-        embedded_migrations::run(&self.db_client)?;
-        Ok(())
+    fn with_storage(storage: Storage, plugins: Plugins) -> Assets {
+        Assets {
+            storage,
+            plugins,
+            cache: Some(CacheCfg::default()),
+            portfolio: None,
+            notifiers: Vec::new(),
+            anomalies: Some(AnomalyCfg::default()),
+            accept_anomalies: false,
+            retention: None,
+            base_currency: None,
+            tax_year_start: TaxYearStart::default(),
+        }
+    }
+
+    pub(crate) fn store(&self) -> &dyn Store {
+        self.storage.store()
+    }
+
+    /// The database, needed for everything beyond assets and their values
+    pub(crate) fn db(&self) -> Result<&DbConnection, Error> {
+        self.storage.db().ok_or(Error::NoDatabase)
+    }
+
+    /// Changes how long plugin answers are cached, `None` bypasses the cache
+    pub fn set_cache(&mut self, cache: Option<CacheCfg>) {
+        self.cache = cache;
+    }
+
+    /// Changes when fetched values are considered suspicious, `None` disables the checks
+    pub fn set_anomaly_cfg(&mut self, anomalies: Option<AnomalyCfg>) {
+        self.anomalies = anomalies;
+    }
+
+    /// Stores fetched values even if they look suspicious
+    pub fn accept_anomalies(&mut self, accept: bool) {
+        self.accept_anomalies = accept;
+    }
+
+    /// Adds a notifier that is told about every alert raised after a fetch
+    pub fn add_notifier(&mut self, notifier: Box<dyn Notifier>) {
+        self.notifiers.push(notifier);
+    }
+
+    /// Runs a single query, e.g. to test it before adding an asset
+    pub fn query(&mut self, query: &str, expected_type: PluginType) -> Result<Value, Error> {
+        cache::query(
+            self.storage.db(),
+            &mut self.plugins,
+            self.cache.as_ref(),
+            query,
+            expected_type,
+        )
     }
 
+    /// Latest regular update of the selected portfolio, assets without holdings are left out
     pub fn list_assets(&self) -> Result<AssetsSnapshot, Error> {
-        let (last_snapshot, time) = schema::updates::table
-            .select((schema::updates::id, schema::updates::timestamp))
-            .order(schema::updates::timestamp.desc())
-            .limit(1)
-            .get_result::<(i32, String)>(&self.db_client)?;
-
-        let assets = schema::assets::table
-            .inner_join(schema::holdings::table)
-            .inner_join(schema::prices::table)
-            .select((
-                schema::assets::id,
-                schema::assets::name,
-                schema::prices::price,
-                schema::holdings::amount,
-                schema::assets::category,
-            ))
-            .filter(
-                schema::prices::update_id
-                    .eq(last_snapshot)
-                    .and(schema::holdings::update_id.eq(last_snapshot))
-                    .and(schema::holdings::amount.ne(0f64)),
-            )
-            .order_by(schema::assets::name)
-            .load::<Asset>(&self.db_client)?;
-
-        Ok(AssetsSnapshot {
-            time: NaiveDateTime::parse_from_str(&time, "%Y-%m-%d %H:%M:%S").unwrap(),
-            assets,
+        self.in_portfolio(self.combined_snapshot()?)
+    }
+
+    /// Regular updates of the selected portfolio between `since` and `until` inclusively, oldest
+    /// first
+    pub fn history(
+        &self,
+        since: Option<NaiveDateTime>,
+        until: Option<NaiveDateTime>,
+    ) -> Result<Vec<AssetsSnapshot>, Error> {
+        self.store()
+            .updates()?
+            .into_iter()
+            .filter(|update| !update.synthetic)
+            .filter(|update| !matches!(since, Some(since) if update.time < since))
+            .filter(|update| !matches!(until, Some(until) if update.time > until))
+            .map(|update| self.in_portfolio(self.snapshot_at((update.id, update.time))?))
+            .collect()
+    }
+
+    /// Latest regular update of all assets regardless of the selected portfolio
+    fn combined_snapshot(&self) -> Result<AssetsSnapshot, Error> {
+        let latest = self
+            .regular_updates(1)?
+            .into_iter()
+            .next()
+            .ok_or(Error::NoData)?;
+        self.snapshot_at(latest)
+    }
+
+    /// Restricts a snapshot of all assets to the selected portfolio
+    pub(crate) fn in_portfolio(&self, snapshot: AssetsSnapshot) -> Result<AssetsSnapshot, Error> {
+        Ok(match self.owned_shares()? {
+            Some(shares) => snapshot.owned(&shares),
+            None => snapshot,
         })
     }
 
+    /// Ids and times of the latest `count` regular updates, newest first
+    pub(crate) fn regular_updates(&self, count: i64) -> Result<Vec<(i32, NaiveDateTime)>, Error> {
+        // several updates can share a timestamp, which only has a resolution of seconds, so the
+        // order by id matters too
+        Ok(self
+            .store()
+            .updates()?
+            .into_iter()
+            .rev()
+            .filter(|update| !update.synthetic)
+            .take(count as usize)
+            .map(|update| (update.id, update.time))
+            .collect())
+    }
+
+    /// All assets with holdings at the given update
+    pub(crate) fn snapshot_at(
+        &self,
+        (update_id, time): (i32, NaiveDateTime),
+    ) -> Result<AssetsSnapshot, Error> {
+        let stored = self
+            .store()
+            .assets()?
+            .into_iter()
+            .map(|asset| (asset.id, asset))
+            .collect::<HashMap<_, _>>();
+        let mut assets = self
+            .store()
+            .values(Some(update_id))?
+            .into_iter()
+            .filter(|value| !value.holdings.is_zero())
+            .filter_map(|value| {
+                let asset = stored.get(&value.asset_id)?;
+                Some(Asset {
+                    id: asset.id,
+                    name: asset.name.clone(),
+                    price: value.price,
+                    holdings: value.holdings,
+                    category: asset.category.clone(),
+                    liability: asset.liability,
+                })
+            })
+            .collect::<Vec<_>>();
+        assets.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(AssetsSnapshot { time, assets })
+    }
+
     pub fn add_asset(
         &mut self,
         name: &str,
@@ -135,86 +383,357 @@ impl Assets {
         price_query: &str,
         holdings_query: &str,
     ) -> Result<(), Error> {
-        let _ = self.plugins.query(price_query, Price)?;
-        let _ = self.plugins.query(holdings_query, Holdings)?;
-
-        diesel::insert_into(schema::assets::table)
-            .values(InsertAsset {
-                name,
-                price_query,
-                holdings_query,
-                category,
-            })
-            .execute(&self.db_client)?;
+        self.insert_asset(name, category, price_query, holdings_query, false)
+    }
+
+    /// Adds a liability like a mortgage or a loan, its holdings count negative towards net worth
+    pub fn add_liability(
+        &mut self,
+        name: &str,
+        category: &str,
+        price_query: &str,
+        holdings_query: &str,
+    ) -> Result<(), Error> {
+        self.insert_asset(name, category, price_query, holdings_query, true)
+    }
+
+    /// Flags an existing asset as liability or not. Fails if the holdings query doesn't answer
+    /// the other kind of query.
+    pub fn set_liability(&mut self, name: &str, liability: bool) -> Result<(), Error> {
+        let mut asset = self
+            .store()
+            .asset(name)?
+            .ok_or_else(|| Error::AssetNotFound(name.to_string()))?;
+        self.check_query(name, &asset.holdings_query, holdings_type(liability))?;
+        asset.liability = liability;
+        self.store().update_asset(&asset)
+    }
+
+    fn insert_asset(
+        &mut self,
+        name: &str,
+        category: &str,
+        price_query: &str,
+        holdings_query: &str,
+        liability: bool,
+    ) -> Result<(), Error> {
+        let Assets {
+            storage,
+            plugins,
+            cache,
+            ..
+        } = self;
+        cache::query_number(storage.db(), plugins, cache.as_ref(), price_query, Price)
+            .map_err(|e| e.in_query(name, price_query))?;
+        cache::query_number(
+            storage.db(),
+            plugins,
+            cache.as_ref(),
+            holdings_query,
+            holdings_type(liability),
+        )
+        .map_err(|e| e.in_query(name, holdings_query))?;
+
+        self.store().add_asset(&NewAsset {
+            name,
+            price_query,
+            holdings_query,
+            category,
+            liability,
+            archived: false,
+        })?;
 
         Ok(())
     }
 
-    pub fn fetch_data(&mut self) -> Result<(), Error> {
-        let Assets { db_client, plugins } = self;
-
-        db_client.transaction(|| {
-            let assets = schema::assets::table.load::<QueryAsset>(db_client)?;
-
-            // create update entry
-            diesel::insert_into(schema::updates::table)
-                .default_values()
-                .execute(db_client)?;
-            let update_id = schema::updates::table
-                .select(schema::updates::id)
-                .order(schema::updates::id.desc())
-                .limit(1)
-                .get_result(db_client)?;
-
-            for asset in assets {
-                let price = plugins.query(&asset.price_query, Price)?;
-                let holdings = plugins.query(&asset.holdings_query, Holdings)?;
-
-                diesel::insert_into(schema::prices::table)
-                    .values(InsertPrices {
-                        update_id,
-                        asset_id: asset.id,
-                        price,
-                    })
-                    .execute(db_client)?;
-
-                diesel::insert_into(schema::holdings::table)
-                    .values(InsertHoldings {
-                        update_id,
-                        asset_id: asset.id,
-                        amount: holdings,
-                    })
-                    .execute(db_client)?;
+    /// Fetches prices and holdings of all assets as a new update, then checks the alert rules and
+    /// passes matching alerts to the notifiers. Failing notifiers are logged but don't fail the
+    /// fetch, the alerts are recorded either way. Finally the history is compacted if a retention
+    /// policy is set, see `set_retention`.
+    ///
+    /// If the new values look suspicious compared to the previous update nothing is stored and
+    /// `Error::Anomalies` is returned, unless anomalies are accepted. Accepted anomalies are
+    /// returned.
+    pub fn fetch_data(&mut self) -> Result<Vec<Anomaly>, Error> {
+        let Assets {
+            storage,
+            plugins,
+            cache,
+            anomalies: anomaly_cfg,
+            accept_anomalies,
+            ..
+        } = self;
+        let (store, db_client) = (storage.store(), storage.db());
+
+        let mut anomalies = Vec::new();
+        store.atomically(&mut || {
+            let assets = store.assets()?;
+            let update_id = store.add_update(None, false)?;
+
+            for asset in assets.iter().filter(|asset| !asset.archived) {
+                let price = cache::query_number(
+                    db_client,
+                    plugins,
+                    cache.as_ref(),
+                    &asset.price_query,
+                    Price,
+                )
+                .map_err(|e| e.in_query(&asset.name, &asset.price_query))?;
+                let holdings = cache::query_number(
+                    db_client,
+                    plugins,
+                    cache.as_ref(),
+                    &asset.holdings_query,
+                    asset.holdings_type(),
+                )
+                .map_err(|e| e.in_query(&asset.name, &asset.holdings_query))?;
+
+                store.add_value(&StoredValue {
+                    update_id,
+                    asset_id: asset.id,
+                    price,
+                    holdings,
+                })?;
             }
 
+            anomalies = match anomaly_cfg {
+                Some(cfg) => anomalies::detect(store, db_client, update_id, cfg)?,
+                None => Vec::new(),
+            };
+            if !anomalies.is_empty() && !*accept_anomalies {
+                return Err(Error::Anomalies(anomalies.clone()));
+            }
             Ok(())
-        })
+        })?;
+
+        // without a database there can't be any alert rules
+        if self.storage.db().is_some() {
+            for alert in self.check_alerts()? {
+                for notifier in &self.notifiers {
+                    if let Err(e) = notifier.notify(&alert) {
+                        error!("Could not send alert '{}': {}", alert.message, e);
+                    }
+                }
+            }
+            // compacting last keeps the previous update around for the alert rules
+            if let Some(policy) = self.retention.clone() {
+                self.compact(&policy, false)?;
+            }
+        }
+        Ok(anomalies)
     }
-}
 
-impl AssetsCfg {
-    pub fn from_env() -> Result<Self, &'static str> {
-        let database = dotenv::var("AM_DATABASE").map_err(|_| "AM_DATABASE not set!")?;
-        let plugins = dotenv::var("AM_PLUGINS")
-            .map_err(|_| "AM_PLUGINS not set!")?
-            .split(":")
-            .map(PathBuf::from)
-            .collect::<Vec<_>>();
+    /// Fetches historic prices of an asset starting at `since` up to its first regular update and
+    /// stores them as synthetic updates. If no `holdings` amount is given the current holdings are
+    /// assumed for the whole period. Points at times that already have synthetic values of the
+    /// asset are skipped, so running it again only adds what is missing. Returns the number of
+    /// inserted data points.
+    pub fn backfill(
+        &mut self,
+        name: &str,
+        since: NaiveDateTime,
+        holdings: Option<Decimal>,
+    ) -> Result<usize, Error> {
+        let asset = self
+            .store()
+            .asset(name)?
+            .ok_or_else(|| Error::AssetNotFound(name.to_string()))?;
+
+        let with_values = self
+            .store()
+            .asset_values(asset.id)?
+            .into_iter()
+            .map(|value| value.update_id)
+            .collect::<HashSet<_>>();
+        let updates = self.store().updates()?;
+        let first_update = updates
+            .iter()
+            .find(|update| !update.synthetic && with_values.contains(&update.id));
+        let backfilled = updates
+            .iter()
+            .filter(|update| update.synthetic && with_values.contains(&update.id))
+            .map(|update| update.time)
+            .collect::<HashSet<_>>();
+        let until = first_update
+            .map(|update| update.time)
+            .unwrap_or_else(|| chrono::Utc::now().naive_utc());
+
+        let range = TimeRange {
+            start: since.timestamp(),
+            end: until.timestamp(),
+        };
+        let series = self
+            .plugins
+            .query_history(&asset.price_query, range)
+            .map_err(|e| Error::query(name, &asset.price_query, e))?;
+        let amount = match holdings {
+            Some(amount) => amount,
+            None => self
+                .plugins
+                .query_number(&asset.holdings_query, asset.holdings_type())
+                .map_err(|e| Error::query(name, &asset.holdings_query, e))?,
+        };
 
-        Ok(AssetsCfg {
-            db_path: database,
-            plugins,
-        })
+        let store = self.store();
+        let mut inserted = 0;
+        store.atomically(&mut || {
+            for point in &series {
+                if point.timestamp < range.start || point.timestamp >= range.end {
+                    continue;
+                }
+
+                let time = NaiveDateTime::from_timestamp(point.timestamp, 0);
+                if backfilled.contains(&time) {
+                    continue;
+                }
+                let update_id = store.add_update(Some(time), true)?;
+                store.add_value(&StoredValue {
+                    update_id,
+                    asset_id: asset.id,
+                    price: point.value,
+                    holdings: amount,
+                })?;
+                inserted += 1;
+            }
+            Ok(())
+        })?;
+
+        Ok(inserted)
     }
 }
 
 #[derive(Debug)]
 pub enum Error {
-    AssetNotFound,
+    AssetNotFound(String),
+    TransactionNotFound(i32),
+    /// A transaction is invalid by itself or contradicts earlier ones
+    InvalidTransaction(String),
+    /// A target weight is invalid by itself or together with the other targets
+    InvalidTarget(String),
+    /// A tag key or value is invalid or the tag to remove doesn't exist
+    InvalidTag(String),
+    PortfolioNotFound(String),
+    /// No asset group of that name is configured
+    GroupNotFound(String),
+    AlertRuleNotFound(i32),
+    /// An alert rule is invalid or refers to an unknown asset
+    InvalidAlertRule(String),
+    /// A share of an asset is invalid by itself or together with the shares of other portfolios
+    InvalidOwnership(String),
+    /// No update was fetched yet
+    NoData,
+    /// A bundle to import couldn't be parsed, is of an unsupported version or refers to assets or
+    /// updates it doesn't contain
+    InvalidBundle(String),
+    /// A spreadsheet to import lacks required columns or can't be read
+    InvalidSpreadsheet(String),
+    /// A record to import differs from the one in the database
+    ImportConflict(String),
+    /// A file couldn't be read or written
+    Io(PathBuf, std::io::Error),
+    /// A required configuration key is neither set in the config file nor in the environment
+    MissingConfig(&'static str),
+    /// The config file or a configuration variable couldn't be parsed
+    InvalidConfig(&'static str, String),
     DatabaseConnectionError(diesel::ConnectionError),
     DatabaseError(diesel::result::Error),
     DatabaseMigrationError(diesel_migrations::RunMigrationsError),
+    /// The operation needs a database, but assets are kept in a store given by `Assets::with_store`
+    NoDatabase,
+    /// A store given by `Assets::with_store` failed
+    StoreError(String),
+    /// A row refers to an asset or update that doesn't exist, which `check_database` finds too
+    BrokenReference(maintenance::Problem),
     PluginError(PluginError),
+    /// Fetched values look suspicious, so the update wasn't stored
+    Anomalies(Vec<Anomaly>),
+    /// A query belonging to a certain asset failed
+    QueryError {
+        asset: String,
+        query: String,
+        error: PluginError,
+    },
+}
+
+impl Error {
+    fn query(asset: &str, query: &str, error: PluginError) -> Error {
+        Error::QueryError {
+            asset: asset.to_string(),
+            query: query.to_string(),
+            error,
+        }
+    }
+
+    /// Attaches the asset and query a plugin error occurred in
+    fn in_query(self, asset: &str, query: &str) -> Error {
+        match self {
+            Error::PluginError(error) => Error::query(asset, query, error),
+            e => e,
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::DatabaseConnectionError(e) => Some(e),
+            Error::DatabaseError(e) => Some(e),
+            Error::DatabaseMigrationError(e) => Some(e),
+            Error::PluginError(e) => Some(e),
+            Error::QueryError { error, .. } => Some(error),
+            Error::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::AssetNotFound(name) => write!(f, "no asset named '{}'", name),
+            Error::TransactionNotFound(id) => write!(f, "no transaction with id {}", id),
+            Error::InvalidTransaction(reason) => write!(f, "invalid transaction: {}", reason),
+            Error::InvalidTarget(reason) => write!(f, "invalid target: {}", reason),
+            Error::InvalidTag(reason) => write!(f, "invalid tag: {}", reason),
+            Error::PortfolioNotFound(name) => write!(f, "no portfolio named '{}'", name),
+            Error::GroupNotFound(name) => write!(f, "no asset group named '{}'", name),
+            Error::AlertRuleNotFound(id) => write!(f, "no alert rule with id {}", id),
+            Error::InvalidAlertRule(reason) => write!(f, "invalid alert rule: {}", reason),
+            Error::InvalidOwnership(reason) => write!(f, "invalid ownership: {}", reason),
+            Error::InvalidBundle(reason) => write!(f, "invalid bundle: {}", reason),
+            Error::InvalidSpreadsheet(reason) => write!(f, "invalid spreadsheet: {}", reason),
+            Error::ImportConflict(record) => {
+                write!(f, "{} differs from the one in the database", record)
+            }
+            Error::Io(path, e) => write!(f, "could not access {}: {}", path.display(), e),
+            Error::NoData => write!(f, "no data was fetched yet"),
+            Error::MissingConfig(key) => write!(f, "no {} configured", key),
+            Error::InvalidConfig(var, reason) => write!(f, "{} is invalid: {}", var, reason),
+            Error::DatabaseConnectionError(e) => write!(f, "could not open database: {}", e),
+            Error::DatabaseError(e) => write!(f, "database error: {}", e),
+            Error::DatabaseMigrationError(e) => write!(f, "could not migrate database: {}", e),
+            Error::NoDatabase => write!(f, "this needs a database, the store only keeps assets"),
+            Error::StoreError(reason) => write!(f, "store error: {}", reason),
+            Error::BrokenReference(problem) => write!(f, "database is inconsistent: {}", problem),
+            Error::PluginError(e) => write!(f, "{}", e),
+            Error::Anomalies(anomalies) => {
+                write!(f, "the fetched data looks suspicious, nothing was stored:")?;
+                for anomaly in anomalies {
+                    write!(f, "\n  {}", anomaly)?;
+                }
+                Ok(())
+            }
+            Error::QueryError {
+                asset,
+                query,
+                error,
+            } => write!(
+                f,
+                "query '{}' of asset '{}' failed: {}",
+                query, asset, error
+            ),
+        }
+    }
 }
 
 impl From<diesel::result::Error> for Error {
@@ -4,16 +4,63 @@ extern crate dotenv;
 extern crate prettytable;
 extern crate structopt;
 
-use assetman::AssetsCfg;
-use itertools::Itertools;
+mod output;
+mod reports;
+
+use crate::output::Format;
+use assetman::alerts::Condition;
+use assetman::allocation::{Target, TargetKind};
+use assetman::bundle::{Bundle, OnConflict};
+use assetman::doctor::check_plugins;
+use assetman::history::ColumnMapping;
+use assetman::maintenance::Problem;
+use assetman::retention::RetentionPolicy;
+use assetman::sync::AssetDecl;
+use assetman::tags::CATEGORY_KEY;
+use assetman::transactions::{CostBasisMethod, NewTransaction, TransactionKind};
+use assetman::{Assets, AssetsCfg, Error, PluginError};
+use assetman_api::{Decimal, PluginType, Value};
+use chrono::NaiveDate;
+use std::cmp::Reverse;
 use std::collections::btree_set::BTreeSet;
-use std::iter::once;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
 #[structopt(name = "assets", about = "manage assets and track their price")]
 struct Options {
+    #[structopt(
+        long = "config",
+        global = true,
+        env = "AM_CONFIG",
+        parse(from_os_str),
+        help = "config file to use instead of ~/.config/assetman/config.toml"
+    )]
+    config: Option<PathBuf>,
+    #[structopt(
+        long = "no-cache",
+        global = true,
+        help = "always query plugins instead of using recently cached answers"
+    )]
+    no_cache: bool,
+    #[structopt(
+        name = "selected-portfolio",
+        short = "p",
+        long = "portfolio",
+        global = true,
+        help = "only show the assets of this portfolio, scaled by the owned share"
+    )]
+    portfolio: Option<String>,
+    #[structopt(
+        long = "format",
+        global = true,
+        default_value = "table",
+        help = "output format of reports: table, json, csv or tsv"
+    )]
+    format: Format,
     #[structopt(subcommand)]
     command: Commands,
 }
@@ -26,9 +73,256 @@ enum Commands {
         price_query: String,
         holdings_query: String,
         category: String,
+        #[structopt(
+            long = "liability",
+            help = "the asset is a liability like a loan, its value counts negative"
+        )]
+        liability: bool,
+    },
+    #[structopt(name = "liability", about = "flag an existing asset as liability")]
+    Liability {
+        name: String,
+        #[structopt(long = "unset", help = "flag the asset as regular asset again")]
+        unset: bool,
     },
     #[structopt(name = "fetch", about = "fetch new prices and holdings for all assets")]
-    Fetch,
+    Fetch {
+        #[structopt(
+            long = "accept-anomalies",
+            help = "store the update even if values changed suspiciously"
+        )]
+        accept_anomalies: bool,
+    },
+    #[structopt(name = "query", about = "run a single query and print its result")]
+    Query {
+        query: String,
+        #[structopt(
+            short = "t",
+            long = "type",
+            default_value = "Price",
+            parse(try_from_str = parse_plugin_type),
+            help = "type of value to query, e.g. Price or Holdings"
+        )]
+        query_type: PluginType,
+    },
+    #[structopt(
+        name = "backfill",
+        about = "fetch historic prices for an asset from before its first update"
+    )]
+    Backfill {
+        name: String,
+        #[structopt(long = "since", help = "first day to fetch prices for (YYYY-MM-DD)")]
+        since: NaiveDate,
+        #[structopt(
+            long = "holdings",
+            help = "holdings to assume for the whole period, defaults to the current holdings"
+        )]
+        holdings: Option<Decimal>,
+    },
+    #[structopt(
+        name = "doctor",
+        about = "check that all plugins start and all stored queries work"
+    )]
+    Doctor,
+    #[structopt(
+        name = "pnl",
+        about = "show realized and unrealized gains of assets with transactions"
+    )]
+    Pnl {
+        #[structopt(
+            short = "m",
+            long = "method",
+            default_value = "fifo",
+            help = "cost basis method: fifo, lifo or average"
+        )]
+        method: CostBasisMethod,
+        #[structopt(
+            short = "c",
+            long = "group-by-category",
+            help = "also show totals per category"
+        )]
+        group_by_category: bool,
+    },
+    #[structopt(
+        name = "history",
+        about = "show gross assets, liabilities and net worth at every update"
+    )]
+    History {
+        #[structopt(long = "since", help = "start at this day (YYYY-MM-DD)")]
+        since: Option<NaiveDate>,
+        #[structopt(long = "until", help = "end at this day (YYYY-MM-DD)")]
+        until: Option<NaiveDate>,
+    },
+    #[structopt(
+        name = "returns",
+        about = "show time-weighted returns and internal rates of return (XIRR)"
+    )]
+    Returns {
+        #[structopt(
+            long = "since",
+            help = "start at the last update on or before this day (YYYY-MM-DD)"
+        )]
+        since: Option<NaiveDate>,
+        #[structopt(
+            long = "until",
+            help = "end at the last update on or before this day (YYYY-MM-DD)"
+        )]
+        until: Option<NaiveDate>,
+        #[structopt(
+            short = "c",
+            long = "group-by-category",
+            help = "also show returns per category"
+        )]
+        group_by_category: bool,
+    },
+    #[structopt(
+        name = "target",
+        about = "manage target weights and show how far the portfolio drifted from them"
+    )]
+    Target(TargetCommands),
+    #[structopt(
+        name = "rebalance",
+        about = "propose trades that get categories or assets back to their target weights"
+    )]
+    Rebalance {
+        #[structopt(
+            long = "by",
+            default_value = "category",
+            help = "rebalance categories or assets: category or asset"
+        )]
+        kind: TargetKind,
+        #[structopt(long = "cash", default_value = "0", help = "new cash to invest")]
+        cash: Decimal,
+        #[structopt(
+            long = "cash-only",
+            help = "only invest new cash instead of also selling overweight positions"
+        )]
+        cash_only: bool,
+    },
+    #[structopt(
+        name = "tx",
+        about = "manage transactions and show the cost basis of assets"
+    )]
+    Tx(TxCommands),
+    #[structopt(name = "tag", about = "manage key/value tags of assets")]
+    Tag(TagCommands),
+    #[structopt(
+        name = "alert",
+        about = "manage rules that raise alerts after fetching and show past alerts"
+    )]
+    Alert(AlertCommands),
+    #[structopt(
+        name = "portfolio",
+        about = "manage which portfolios own which share of the assets"
+    )]
+    Portfolio(PortfolioCommands),
+    #[structopt(
+        name = "sync",
+        about = "add, change and archive assets to match the assets declared in the config file"
+    )]
+    Sync {
+        #[structopt(long = "dry-run", help = "only show the planned changes")]
+        dry_run: bool,
+    },
+    #[structopt(
+        name = "export",
+        about = "write the whole database to a portable JSON or CSV bundle"
+    )]
+    Export {
+        #[structopt(
+            long = "csv",
+            requires = "path",
+            help = "write a directory with one CSV file per table instead of a JSON file"
+        )]
+        csv: bool,
+        #[structopt(
+            parse(from_os_str),
+            help = "file or directory to write to, JSON is printed if omitted"
+        )]
+        path: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "import-history",
+        about = "import past prices and holdings from a spreadsheet exported as CSV"
+    )]
+    ImportHistory {
+        #[structopt(parse(from_os_str), help = "CSV file with one row per asset and date")]
+        path: PathBuf,
+        #[structopt(long = "date", default_value = "date", help = "column of the date")]
+        date: String,
+        #[structopt(
+            long = "asset",
+            default_value = "asset",
+            help = "column of the asset name"
+        )]
+        asset: String,
+        #[structopt(
+            long = "holdings",
+            default_value = "holdings",
+            help = "column of the holdings"
+        )]
+        holdings: String,
+        #[structopt(long = "price", default_value = "price", help = "column of the price")]
+        price: String,
+        #[structopt(
+            long = "value",
+            default_value = "value",
+            help = "column of the value, used to derive missing holdings or prices"
+        )]
+        value: String,
+        #[structopt(
+            long = "category",
+            default_value = "category",
+            help = "column of the category of new assets"
+        )]
+        category: String,
+        #[structopt(
+            long = "default-category",
+            default_value = "imported",
+            help = "category of new assets if there is no category column"
+        )]
+        default_category: String,
+        #[structopt(
+            long = "date-format",
+            default_value = "%Y-%m-%d",
+            help = "format of the dates, e.g. %d.%m.%Y"
+        )]
+        date_format: String,
+        #[structopt(
+            long = "delimiter",
+            default_value = ",",
+            parse(try_from_str = parse_delimiter),
+            help = "character separating the columns"
+        )]
+        delimiter: u8,
+        #[structopt(
+            long = "decimal-comma",
+            help = "numbers use a comma as decimal separator, e.g. 1.234,50"
+        )]
+        decimal_comma: bool,
+    },
+    #[structopt(
+        name = "import",
+        about = "merge a bundle written by export into the database"
+    )]
+    Import {
+        #[structopt(
+            parse(from_os_str),
+            help = "JSON file or directory of CSV files to read, - reads JSON from stdin"
+        )]
+        path: PathBuf,
+        #[structopt(
+            long = "on-conflict",
+            default_value = "abort",
+            help = "what to do with records that differ from the database: abort, keep or replace"
+        )]
+        on_conflict: OnConflict,
+    },
+    #[structopt(
+        name = "db",
+        about = "back up, check, repair and compact the database"
+    )]
+    Db(DbCommands),
     #[structopt(name = "list", about = "list all assets and their price")]
     List {
         #[structopt(
@@ -40,19 +334,280 @@ enum Commands {
         #[structopt(
             short = "c",
             long = "group-by-category",
-            help = "show assets grouped by category"
+            help = "show assets grouped by category, same as `--group-by category`"
         )]
         group_by_category: bool,
+        #[structopt(
+            short = "g",
+            long = "group-by",
+            use_delimiter = true,
+            conflicts_with = "group-by-category",
+            help = "show assets grouped by tags, nested in the given order, e.g. owner,category"
+        )]
+        group_by: Vec<String>,
+        #[structopt(help = "only list the assets of this group from the config file")]
+        group: Option<String>,
     },
 }
 
+#[derive(StructOpt)]
+enum PortfolioCommands {
+    #[structopt(
+        name = "own",
+        about = "let a portfolio own a share of an asset, replacing its previous share"
+    )]
+    Own {
+        asset: String,
+        portfolio: String,
+        #[structopt(long = "share", default_value = "100", help = "owned share in percent")]
+        share: Decimal,
+    },
+    #[structopt(name = "disown", about = "remove an asset from a portfolio")]
+    Disown { asset: String, portfolio: String },
+    #[structopt(
+        name = "list",
+        about = "show the net worth of every portfolio and which shares of which assets they own"
+    )]
+    List,
+}
+
+#[derive(StructOpt)]
+enum AlertCommands {
+    #[structopt(name = "add", about = "add a rule that is checked after every fetch")]
+    Add(RuleCommands),
+    #[structopt(name = "rm", about = "remove a rule by its id")]
+    Rm { id: i32 },
+    #[structopt(name = "rules", about = "list all rules including their ids")]
+    Rules,
+    #[structopt(name = "list", about = "show recorded alerts, newest first")]
+    List {
+        #[structopt(
+            short = "n",
+            long = "limit",
+            default_value = "20",
+            help = "number of alerts to show"
+        )]
+        limit: i64,
+    },
+    #[structopt(
+        name = "check",
+        about = "check the rules against the latest update again without notifying"
+    )]
+    Check,
+}
+
+#[derive(StructOpt)]
+enum RuleCommands {
+    #[structopt(
+        name = "total-drop",
+        about = "net worth drops by more than a percentage since the previous update"
+    )]
+    TotalDrop { percent: Decimal },
+    #[structopt(
+        name = "price-move",
+        about = "price of an asset moves by more than a percentage since the previous update"
+    )]
+    PriceMove { asset: String, percent: Decimal },
+    #[structopt(name = "price-above", about = "price of an asset rises above a level")]
+    PriceAbove { asset: String, level: Decimal },
+    #[structopt(name = "price-below", about = "price of an asset falls below a level")]
+    PriceBelow { asset: String, level: Decimal },
+    #[structopt(
+        name = "out-of-band",
+        about = "a category or asset drifts out of the tolerance band of its target weight"
+    )]
+    OutOfBand {
+        #[structopt(default_value = "category", help = "category or asset")]
+        kind: TargetKind,
+    },
+}
+
+#[derive(StructOpt)]
+enum TagCommands {
+    #[structopt(
+        name = "set",
+        about = "set a tag of an asset, replacing its previous value"
+    )]
+    Set {
+        asset: String,
+        key: String,
+        value: String,
+    },
+    #[structopt(name = "rm", about = "remove a tag of an asset")]
+    Rm { asset: String, key: String },
+    #[structopt(name = "list", about = "list tags of all assets or a single one")]
+    List { asset: Option<String> },
+}
+
+#[derive(StructOpt)]
+enum TxCommands {
+    #[structopt(name = "add", about = "record a transaction of an asset")]
+    Add {
+        asset: String,
+        #[structopt(help = "buy, sell, deposit, withdrawal or fee")]
+        kind: TransactionKind,
+        #[structopt(help = "number of units")]
+        amount: Decimal,
+        #[structopt(help = "price per unit")]
+        price: Decimal,
+        currency: String,
+        #[structopt(
+            long = "date",
+            help = "date of the transaction (YYYY-MM-DD), defaults to today"
+        )]
+        date: Option<NaiveDate>,
+        #[structopt(
+            long = "fee",
+            default_value = "0",
+            help = "commission paid in the currency on top of the price"
+        )]
+        fee: Decimal,
+    },
+    #[structopt(
+        name = "list",
+        about = "list transactions of all assets or a single one"
+    )]
+    List { asset: Option<String> },
+    #[structopt(name = "rm", about = "remove a transaction by its id")]
+    Rm { id: i32 },
+    #[structopt(
+        name = "basis",
+        about = "show the cost basis of assets with transactions"
+    )]
+    Basis {
+        asset: Option<String>,
+        #[structopt(
+            short = "m",
+            long = "method",
+            default_value = "fifo",
+            help = "fifo, lifo or average"
+        )]
+        method: CostBasisMethod,
+    },
+}
+
+#[derive(StructOpt)]
+enum TargetCommands {
+    #[structopt(name = "set", about = "set the target weight of a category or asset")]
+    Set {
+        #[structopt(help = "category or asset")]
+        kind: TargetKind,
+        name: String,
+        #[structopt(help = "share of the whole portfolio in percent")]
+        weight: Decimal,
+        #[structopt(
+            long = "tolerance",
+            default_value = "5",
+            help = "allowed drift in percentage points"
+        )]
+        tolerance: Decimal,
+    },
+    #[structopt(name = "rm", about = "remove the target weight of a category or asset")]
+    Rm {
+        #[structopt(help = "category or asset")]
+        kind: TargetKind,
+        name: String,
+    },
+    #[structopt(
+        name = "list",
+        about = "compare the latest update to the target weights"
+    )]
+    List,
+}
+
+#[derive(StructOpt)]
+enum DbCommands {
+    #[structopt(
+        name = "backup",
+        about = "copy the SQLite database to a new file, also while it's in use"
+    )]
+    Backup {
+        #[structopt(parse(from_os_str), help = "file to write, must not exist yet")]
+        path: PathBuf,
+    },
+    #[structopt(
+        name = "check",
+        about = "look for rows referring to missing assets or updates, unpaired prices and holdings and values that aren't numbers"
+    )]
+    Check,
+    #[structopt(name = "repair", about = "delete the rows found by check")]
+    Repair {
+        #[structopt(short = "y", long = "yes", help = "don't ask for confirmation")]
+        yes: bool,
+    },
+    #[structopt(
+        name = "compact",
+        about = "thin out old updates according to a retention policy"
+    )]
+    Compact {
+        #[structopt(
+            long = "keep",
+            help = "policy to use instead of the configured one, e.g. 30=all,365=daily,weekly"
+        )]
+        keep: Option<RetentionPolicy>,
+        #[structopt(long = "dry-run", help = "only show what would be deleted")]
+        dry_run: bool,
+    },
+}
+
+/// Exit codes per error class, so scripts can tell a broken setup from e.g. a flaky data source
+const EXIT_CONFIG: i32 = 2;
+const EXIT_DATABASE: i32 = 3;
+const EXIT_PLUGIN: i32 = 4;
+const EXIT_QUERY: i32 = 5;
+const EXIT_NOT_FOUND: i32 = 6;
+const EXIT_INVALID_INPUT: i32 = 7;
+const EXIT_ANOMALIES: i32 = 8;
+const EXIT_IO: i32 = 9;
+
 fn main() {
     dotenv::dotenv().ok();
     pretty_env_logger::init();
 
     let options = Options::from_args();
 
-    let mut assets = assetman::Assets::new(AssetsCfg::from_env().unwrap()).unwrap();
+    if let Err(e) = run(options) {
+        eprintln!("Error: {}", e);
+        if let Some(hint) = hint(&e) {
+            eprintln!("{}", hint);
+        }
+        exit(exit_code(&e));
+    }
+}
+
+fn run(options: Options) -> Result<(), Error> {
+    let cfg = AssetsCfg::load(options.config.as_deref())?;
+
+    if let Commands::Doctor = options.command {
+        exit(doctor(cfg));
+    }
+
+    let group = match &options.command {
+        Commands::List {
+            group: Some(group), ..
+        } => Some(cfg.group(group)?.to_vec()),
+        _ => None,
+    };
+    let declared = match &options.command {
+        Commands::Sync { .. } => Some(cfg.assets.clone().ok_or(Error::MissingConfig("assets"))?),
+        _ => None,
+    };
+    let display = cfg.display.clone();
+    let base_currency = cfg.base_currency.clone();
+    let value_title = match &base_currency {
+        Some(currency) => format!("Value ({})", currency),
+        None => "Value".to_string(),
+    };
+    let format = options.format;
+
+    let mut assets = Assets::new(cfg)?;
+    if options.no_cache {
+        assets.set_cache(None);
+    }
+    // new assets may start a new portfolio, so it doesn't have to exist yet
+    if !matches!(options.command, Commands::Add { .. }) {
+        assets.select_portfolio(options.portfolio.as_deref())?;
+    }
 
     match options.command {
         Commands::Add {
@@ -60,113 +615,753 @@ fn main() {
             price_query,
             holdings_query,
             category,
+            liability,
         } => {
-            assets
-                .add_asset(&name, &category, &price_query, &holdings_query)
-                .expect("Error: Couldn't add asset.");
+            if liability {
+                assets.add_liability(&name, &category, &price_query, &holdings_query)?;
+            } else {
+                assets.add_asset(&name, &category, &price_query, &holdings_query)?;
+            }
+            if let Some(portfolio) = &options.portfolio {
+                assets.set_ownership(&name, portfolio, Decimal::ONE)?;
+            }
         }
-        Commands::Fetch => {
-            assets
-                .fetch_data()
-                .expect("Error: could not update prices and holdings.");
+        Commands::Liability { name, unset } => {
+            assets.set_liability(&name, !unset)?;
         }
+        Commands::Fetch { accept_anomalies } => {
+            assets.accept_anomalies(accept_anomalies);
+            for anomaly in assets.fetch_data()? {
+                println!("Accepted anomaly: {}", anomaly);
+            }
+        }
+        Commands::Query { query, query_type } => match assets.query(&query, query_type)? {
+            Value::Number(number) => println!("{}", number),
+            Value::Metadata(metadata) => {
+                for (key, value) in metadata {
+                    println!("{}: {}", key, value);
+                }
+            }
+            Value::Series(series) => {
+                for point in series {
+                    println!("{}\t{}", point.timestamp, point.value);
+                }
+            }
+        },
+        Commands::Backfill {
+            name,
+            since,
+            holdings,
+        } => {
+            let inserted = assets.backfill(&name, since.and_hms(0, 0, 0), holdings)?;
+            println!("Inserted {} historic prices for {}.", inserted, name);
+        }
+        Commands::Doctor => unreachable!("handled before loading plugins"),
+        Commands::Pnl {
+            method,
+            group_by_category,
+        } => pnl(&assets, method, group_by_category, format)?,
+        Commands::History { since, until } => {
+            let history = assets.history(
+                since.map(|since| since.and_hms(0, 0, 0)),
+                until.map(|until| until.and_hms(23, 59, 59)),
+            )?;
+            match format {
+                Format::Table if history.is_empty() => {
+                    println!("No updates in this range, fetch prices first or choose other days.")
+                }
+                Format::Table => {
+                    reports::history_table(&history).printstd();
+                }
+                format => reports::history_report(&history)
+                    .value("currency", base_currency)
+                    .value("portfolio", assets.portfolio())
+                    .print(format),
+            }
+        }
+        Commands::Returns {
+            since,
+            until,
+            group_by_category,
+        } => {
+            let report = assets.returns(
+                since.map(|since| since.and_hms(23, 59, 59)),
+                until.map(|until| until.and_hms(23, 59, 59)),
+            )?;
+            match format {
+                Format::Table => {
+                    reports::returns_table(&report, group_by_category).printstd();
+                }
+                format => reports::returns_report(&report).print(format),
+            }
+        }
+        Commands::Target(command) => target(&mut assets, command, format)?,
+        Commands::Rebalance {
+            kind,
+            cash,
+            cash_only,
+        } => {
+            let trades = assets.rebalance(kind, cash, cash_only)?;
+            match format {
+                Format::Table if trades.is_empty() => println!("Nothing to do."),
+                Format::Table => {
+                    reports::rebalance_table(&trades).printstd();
+                }
+                format => reports::rebalance_report(&trades).print(format),
+            }
+        }
+        Commands::Tx(command) => tx(&mut assets, command, format)?,
+        Commands::Tag(command) => tag(&mut assets, command, format)?,
+        Commands::Alert(command) => alert(&mut assets, command, format)?,
+        Commands::Portfolio(command) => portfolio(&mut assets, command, format)?,
+        Commands::Sync { dry_run } => {
+            sync(&mut assets, &declared.expect("loaded for sync"), dry_run)?
+        }
+        Commands::Export { csv, path } => export(&assets, csv, path)?,
+        Commands::ImportHistory {
+            path,
+            date,
+            asset,
+            holdings,
+            price,
+            value,
+            category,
+            default_category,
+            date_format,
+            delimiter,
+            decimal_comma,
+        } => {
+            let mapping = ColumnMapping {
+                date,
+                asset,
+                holdings,
+                price,
+                value,
+                category,
+                date_format,
+                delimiter,
+                decimal_comma,
+                default_category,
+            };
+            import_history(&mut assets, &path, &mapping)?
+        }
+        Commands::Import { path, on_conflict } => import(&mut assets, &path, on_conflict)?,
+        Commands::Db(command) => db(&mut assets, command, format)?,
         Commands::List {
             order_by_value,
             group_by_category,
+            mut group_by,
+            ..
         } => {
-            let mut asset_list = assets
-                .list_assets()
-                .unwrap_or_else(|_| {
+            let mut snapshot = match assets.list_assets() {
+                Ok(snapshot) => snapshot,
+                Err(Error::NoData) if format == Format::Table => {
                     println!("No assets in database yet or no data was fetched yet, add asssets or fetch prices.");
-                    exit(0);
-                })
-                .assets;
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            };
 
-            if order_by_value {
-                asset_list.sort_by(|a, b| {
-                    (b.price * b.holdings)
-                        .partial_cmp(&(a.price * a.holdings))
-                        .expect("values shouldn't be NaN or inf")
-                });
+            if let Some(group) = group {
+                snapshot.assets.retain(|asset| group.contains(&asset.name));
             }
 
-            let sum: f64 = asset_list
-                .iter()
-                .map(|asset| asset.price * asset.holdings)
-                .sum();
-
-            let mut table = prettytable::Table::new();
+            // shares are relative to gross assets, so liabilities don't distort them
+            let gross_assets = snapshot.gross_assets();
+            let liabilities = snapshot.liabilities();
+            let mut asset_list = snapshot.assets;
+            if order_by_value || display.sort_by_value {
+                asset_list.sort_by_key(|asset| Reverse(asset.value()));
+            }
 
             if group_by_category {
-                table.set_titles(row!["Asset", "Holdings", "Price", "Value", "Rel"]);
-                let asset_categories = asset_list
-                    .iter()
-                    .map(|asset| asset.category.as_str())
-                    .collect::<BTreeSet<_>>();
-
-                for asset_category in asset_categories {
-                    table.add_empty_row();
-                    table.add_row(row!(bFy -> asset_category, "", "", "", ""));
-
-                    let assets = asset_list
-                        .iter()
-                        .filter(|&asset| asset.category == asset_category)
-                        .collect::<Vec<_>>();
-
-                    let cat_sum: f64 = assets
-                        .iter()
-                        .map(|asset| asset.price * asset.holdings)
-                        .sum();
-
-                    for asset in assets {
-                        table.add_row(row![
-                            asset.name,
-                            r -> format_money(asset.holdings),
-                            r -> format_money(asset.price),
-                            r -> format_money(asset.holdings * asset.price),
-                            r -> ""
-                        ]);
-                    }
-
-                    table.add_row(row!(b -> "Category Sum", "", "", br -> format_money(cat_sum), format!("{:.1}%", cat_sum / sum * 100.0)));
+                group_by = vec![CATEGORY_KEY.to_string()];
+            } else if group_by.is_empty() {
+                group_by = display.group_by;
+            }
+
+            match format {
+                Format::Table if group_by.is_empty() => {
+                    reports::list_table(&asset_list, &value_title, gross_assets, liabilities)
+                        .printstd();
+                }
+                Format::Table => {
+                    let groups = assets.group_assets(asset_list, &group_by)?;
+                    reports::grouped_list_table(&groups, &value_title, gross_assets, liabilities)
+                        .printstd();
+                }
+                format => {
+                    let asset_list = if group_by.is_empty() {
+                        asset_list
+                            .into_iter()
+                            .map(|asset| (asset, Vec::new()))
+                            .collect()
+                    } else {
+                        reports::flatten_groups(assets.group_assets(asset_list, &group_by)?)
+                    };
+                    reports::list_report(snapshot.time, asset_list, &group_by)
+                        .value("currency", base_currency)
+                        .value("portfolio", assets.portfolio())
+                        .value("gross_assets", gross_assets)
+                        .value("liabilities", liabilities)
+                        .value("net_worth", gross_assets + liabilities)
+                        .print(format);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_plugin_type(s: &str) -> Result<PluginType, String> {
+    serde_json::from_value(serde_json::Value::String(s.to_string()))
+        .map_err(|_| format!("unknown type '{}'", s))
+}
+
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [delimiter] => Ok(*delimiter),
+        _ if s == "\\t" => Ok(b'\t'),
+        _ => Err(format!("'{}' isn't a single ASCII character", s)),
+    }
+}
+
+fn exit_code(error: &Error) -> i32 {
+    match error {
+        Error::MissingConfig(_) | Error::InvalidConfig(..) => EXIT_CONFIG,
+        Error::DatabaseConnectionError(_)
+        | Error::DatabaseError(_)
+        | Error::DatabaseMigrationError(_)
+        | Error::NoDatabase
+        | Error::StoreError(_)
+        | Error::BrokenReference(_) => EXIT_DATABASE,
+        Error::PluginError(_) => EXIT_PLUGIN,
+        Error::QueryError { .. } => EXIT_QUERY,
+        Error::AssetNotFound(_)
+        | Error::TransactionNotFound(_)
+        | Error::PortfolioNotFound(_)
+        | Error::GroupNotFound(_)
+        | Error::AlertRuleNotFound(_)
+        | Error::NoData => EXIT_NOT_FOUND,
+        Error::InvalidTransaction(_)
+        | Error::InvalidTarget(_)
+        | Error::InvalidTag(_)
+        | Error::InvalidOwnership(_)
+        | Error::InvalidAlertRule(_)
+        | Error::InvalidBundle(_)
+        | Error::InvalidSpreadsheet(_)
+        | Error::ImportConflict(_) => EXIT_INVALID_INPUT,
+        Error::Io(..) => EXIT_IO,
+        Error::Anomalies(_) => EXIT_ANOMALIES,
+    }
+}
+
+/// Suggests what to do about an error, if there is anything obvious
+fn hint(error: &Error) -> Option<&'static str> {
+    match error {
+        Error::MissingConfig(_) => Some(
+            "Set it in the config file (~/.config/assetman/config.toml or --config), or as AM_* variable in the environment or a .env file.",
+        ),
+        Error::InvalidConfig(..) => {
+            Some("Fix it in the config file or the environment, see the README for the format.")
+        }
+        Error::DatabaseConnectionError(_) => Some(
+            "Check that the configured database points to a writable location or a reachable PostgreSQL server.",
+        ),
+        Error::PluginError(PluginError::PluginStartupFailed(..)) => Some(
+            "Check that the configured plugins are installed, either as absolute paths or as names in $PATH.",
+        ),
+        Error::PluginError(PluginError::Timeout(..))
+        | Error::QueryError {
+            error: PluginError::Timeout(..),
+            ..
+        } => Some(
+            "Raise the plugin's timeout in the config file if it is just slow, or run `assets doctor`.",
+        ),
+        Error::PluginError(_) | Error::QueryError { .. } => {
+            Some("Run `assets doctor` to check all plugins and queries.")
+        }
+        Error::BrokenReference(_) => Some(
+            "Run `assets db check` to see all such rows and `assets db repair` to delete them.",
+        ),
+        Error::AssetNotFound(_) => Some("Asset names are case sensitive."),
+        Error::TransactionNotFound(_) => Some("Run `assets tx list` to see all transaction ids."),
+        Error::AlertRuleNotFound(_) => Some("Run `assets alert rules` to see all rule ids."),
+        Error::Anomalies(_) => Some(
+            "Check the plugins and transactions, or run `assets fetch --accept-anomalies` if the values are correct.",
+        ),
+        Error::ImportConflict(_) => Some(
+            "Nothing was imported. Pass `--on-conflict keep` or `--on-conflict replace` to import the rest anyway.",
+        ),
+        Error::GroupNotFound(_) => {
+            Some("Asset groups are defined in the [groups] section of the config file.")
+        }
+        Error::PortfolioNotFound(_) => {
+            Some("Run `assets portfolio list` to see all portfolios, they are created by `portfolio own`.")
+        }
+        _ => None,
+    }
+}
+
+fn pnl(
+    assets: &Assets,
+    method: CostBasisMethod,
+    group_by_category: bool,
+    format: Format,
+) -> Result<(), Error> {
+    let report = assets.pnl(method)?;
+    match format {
+        Format::Table if report.assets.is_empty() => {
+            println!("No transactions recorded yet, add some with `assets tx add`.")
+        }
+        Format::Table => {
+            reports::pnl_table(&report, group_by_category).printstd();
+            if !report.realized.is_empty() {
+                println!();
+                reports::tax_years_table(&report).printstd();
+            }
+        }
+        format => reports::pnl_report(&report).print(format),
+    }
+    Ok(())
+}
+
+fn target(assets: &mut Assets, command: TargetCommands, format: Format) -> Result<(), Error> {
+    let hundred = Decimal::new(100, 0);
+    match command {
+        TargetCommands::Set {
+            kind,
+            name,
+            weight,
+            tolerance,
+        } => {
+            assets.set_target(&Target {
+                kind,
+                name,
+                weight: weight / hundred,
+                tolerance: tolerance / hundred,
+            })?;
+        }
+        TargetCommands::Rm { kind, name } => {
+            assets.remove_target(kind, &name)?;
+        }
+        TargetCommands::List => {
+            let targets = assets.list_targets()?;
+            let mut drifts = Vec::new();
+            for kind in [TargetKind::Category, TargetKind::Asset] {
+                if targets.iter().any(|target| target.kind == kind) {
+                    drifts.push((kind, assets.drift(kind)?));
+                }
+            }
+            match format {
+                Format::Table if targets.is_empty() => {
+                    println!("No targets set yet, set some with `assets target set`.")
+                }
+                Format::Table => {
+                    reports::drift_table(&drifts).printstd();
+                }
+                format => reports::drift_report(&drifts).print(format),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn portfolio(assets: &mut Assets, command: PortfolioCommands, format: Format) -> Result<(), Error> {
+    match command {
+        PortfolioCommands::Own {
+            asset,
+            portfolio,
+            share,
+        } => {
+            assets.set_ownership(&asset, &portfolio, share / Decimal::new(100, 0))?;
+        }
+        PortfolioCommands::Disown { asset, portfolio } => {
+            assets.remove_ownership(&asset, &portfolio)?;
+        }
+        PortfolioCommands::List => {
+            let ownership = assets.list_ownership()?;
+            let snapshots = match assets.portfolio_snapshots() {
+                Ok(snapshots) => snapshots,
+                // without data there are no values, but the shares are still worth showing
+                Err(Error::NoData) => Default::default(),
+                Err(e) => return Err(e),
+            };
+            let combined = assets.list_assets().ok();
+            match format {
+                Format::Table if ownership.is_empty() => {
+                    println!("No portfolios yet, create one with `assets portfolio own`.")
+                }
+                Format::Table => {
+                    reports::portfolio_table(ownership, &snapshots, combined.as_ref()).printstd();
+                }
+                format => reports::portfolio_report(
+                    assets.list_portfolios()?,
+                    ownership,
+                    &snapshots,
+                    combined.as_ref(),
+                )
+                .print(format),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn alert(assets: &mut Assets, command: AlertCommands, format: Format) -> Result<(), Error> {
+    let hundred = Decimal::new(100, 0);
+    match command {
+        AlertCommands::Add(rule) => {
+            let condition = match rule {
+                RuleCommands::TotalDrop { percent } => Condition::TotalDrop(percent / hundred),
+                RuleCommands::PriceMove { asset, percent } => Condition::PriceMove {
+                    asset,
+                    threshold: percent / hundred,
+                },
+                RuleCommands::PriceAbove { asset, level } => Condition::PriceAbove { asset, level },
+                RuleCommands::PriceBelow { asset, level } => Condition::PriceBelow { asset, level },
+                RuleCommands::OutOfBand { kind } => Condition::OutOfBand(kind),
+            };
+            let id = assets.add_alert_rule(&condition)?;
+            println!("Added alert rule {}.", id);
+        }
+        AlertCommands::Rm { id } => {
+            assets.remove_alert_rule(id)?;
+        }
+        AlertCommands::Rules => {
+            let rules = assets.list_alert_rules()?;
+            match format {
+                Format::Table => {
+                    reports::rules_table(&rules).printstd();
+                }
+                format => reports::rules_report(&rules).print(format),
+            }
+        }
+        AlertCommands::List { limit } => {
+            let alerts = assets.list_alerts(Some(limit))?;
+            match format {
+                Format::Table => {
+                    reports::alerts_table(&alerts).printstd();
                 }
+                format => reports::alerts_report(&alerts).print(format),
+            }
+        }
+        AlertCommands::Check => {
+            let alerts = assets.check_alerts()?;
+            if alerts.is_empty() {
+                println!("No rule matches the latest update.");
+            }
+            for alert in alerts {
+                println!("Alert: {}", alert.message);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn export(assets: &Assets, csv: bool, path: Option<PathBuf>) -> Result<(), Error> {
+    let bundle = assets.export()?;
+    match path {
+        Some(dir) if csv => bundle.write_csv(&dir),
+        Some(path) => {
+            let file = File::create(&path).map_err(|e| Error::Io(path.clone(), e))?;
+            bundle
+                .write_json(BufWriter::new(file))
+                .map_err(|e| Error::Io(path, e))
+        }
+        None => {
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            bundle
+                .write_json(&mut out)
+                .and_then(|_| writeln!(out))
+                .expect("could not write to stdout");
+            Ok(())
+        }
+    }
+}
+
+fn import(assets: &mut Assets, path: &Path, on_conflict: OnConflict) -> Result<(), Error> {
+    let bundle = if path == Path::new("-") {
+        Bundle::read_json(std::io::stdin().lock())?
+    } else if path.is_dir() {
+        Bundle::read_csv(path)?
+    } else {
+        let file = File::open(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+        Bundle::read_json(BufReader::new(file))?
+    };
+
+    let summary = assets.import(&bundle, on_conflict)?;
+    for conflict in &summary.conflicts {
+        let action = match on_conflict {
+            OnConflict::Replace => "replaced",
+            _ => "kept",
+        };
+        println!(
+            "Conflict: {} differs, {} the one in the database",
+            conflict, action
+        );
+    }
+    println!(
+        "Imported {} new records, {} were already in the database.",
+        summary.added, summary.unchanged
+    );
+    Ok(())
+}
+
+fn db(assets: &mut Assets, command: DbCommands, format: Format) -> Result<(), Error> {
+    match command {
+        DbCommands::Backup { path } => {
+            assets.backup_database(&path)?;
+            println!("Backed up the database to {}.", path.display());
+        }
+        DbCommands::Check => {
+            let problems = assets.check_database()?;
+            if format != Format::Table {
+                reports::problems_report(&problems).print(format);
             } else {
-                table.set_titles(row!["Asset", "Holdings", "Price", "Value"]);
-                for asset in asset_list {
-                    table.add_row(row![
-                        asset.name,
-                        r -> format_money(asset.holdings),
-                        r -> format_money(asset.price),
-                        r -> format_money(asset.holdings * asset.price),
-                    ]);
+                let (repairable, notes) = problems
+                    .iter()
+                    .cloned()
+                    .partition::<Vec<_>, _>(Problem::is_repairable);
+                if repairable.is_empty() {
+                    println!("No problems found.");
+                } else {
+                    print_problems(&repairable);
+                    println!("Run `assets db repair` to delete these rows.");
                 }
+                print_notes(&notes);
+            }
+            if problems.iter().any(Problem::is_repairable) {
+                exit(EXIT_DATABASE);
+            }
+        }
+        DbCommands::Repair { yes } => {
+            let (problems, notes) = assets
+                .check_database()?
+                .into_iter()
+                .partition::<Vec<_>, _>(Problem::is_repairable);
+            print_notes(&notes);
+            if problems.is_empty() {
+                println!("No problems found, nothing to repair.");
+                return Ok(());
+            }
+            print_problems(&problems);
+            if !yes && !confirm("Delete these rows?")? {
+                println!("Nothing was changed.");
+                return Ok(());
             }
+            assets.repair_database(&problems)?;
+            println!("Repaired {} problems.", problems.len());
+        }
+        DbCommands::Compact { keep, dry_run } => {
+            let policy = keep
+                .or_else(|| assets.retention().cloned())
+                .unwrap_or_default();
+            let compaction = assets.compact(&policy, dry_run)?;
+            let verb = if dry_run { "Would delete" } else { "Deleted" };
+            println!(
+                "{} {} updates with {} prices and holdings, {} updates are left.",
+                verb, compaction.updates, compaction.values, compaction.kept
+            );
+        }
+    }
+    Ok(())
+}
 
-            table.add_empty_row();
-            table.add_row(row!(b -> "Sum", "", "", br -> format_money(sum)));
+fn print_problems(problems: &[Problem]) {
+    println!("Found {} problems:", problems.len());
+    for problem in problems {
+        println!("  {}", problem);
+    }
+}
+
+/// Prints the problems that aren't repaired, as they are fine if they are expected
+fn print_notes(notes: &[Problem]) {
+    if notes.is_empty() {
+        return;
+    }
+    println!("Assets without values at some updates:");
+    for note in notes {
+        println!("  {}", note);
+    }
+    println!("That's expected if they were archived meanwhile or imported with gaps, otherwise their values were deleted. `assets db repair` leaves them alone.");
+}
+
+/// Asks a yes/no question on the terminal, anything but yes counts as no
+fn confirm(question: &str) -> Result<bool, Error> {
+    print!("{} [y/N] ", question);
+    // the question has no newline, so it may still be buffered
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .map_err(|e| Error::Io(PathBuf::from("stdin"), e))?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
 
-            table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-            table.printstd();
+fn import_history(assets: &mut Assets, path: &Path, mapping: &ColumnMapping) -> Result<(), Error> {
+    let file = File::open(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+    let summary = assets.import_history(BufReader::new(file), mapping)?;
+
+    println!("Mapped columns: {}", summary.columns.join(", "));
+    for skipped in &summary.skipped {
+        println!("Skipped line {}: {}", skipped.line, skipped.reason);
+    }
+    println!(
+        "Imported {} rows into {} new updates, skipped {} rows.",
+        summary.rows,
+        summary.updates,
+        summary.skipped.len()
+    );
+    if !summary.new_assets.is_empty() {
+        println!(
+            "Created {} archived assets with placeholder queries, declare them in the config file and run `assets sync` to fetch them: {}",
+            summary.new_assets.len(),
+            summary.new_assets.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn sync(assets: &mut Assets, declared: &[AssetDecl], dry_run: bool) -> Result<(), Error> {
+    let changes = assets.plan_sync(declared)?;
+    if changes.is_empty() {
+        println!("All assets match the config file, nothing to do.");
+        return Ok(());
+    }
+
+    reports::sync_table(&changes).printstd();
+
+    if dry_run {
+        println!("Dry run, nothing was changed.");
+    } else {
+        assets.apply_sync(&changes)?;
+        println!("Applied {} changes.", changes.len());
+    }
+    Ok(())
+}
+
+fn tag(assets: &mut Assets, command: TagCommands, format: Format) -> Result<(), Error> {
+    match command {
+        TagCommands::Set { asset, key, value } => {
+            assets.set_tag(&asset, &key, &value)?;
+        }
+        TagCommands::Rm { asset, key } => {
+            assets.remove_tag(&asset, &key)?;
+        }
+        TagCommands::List { asset } => {
+            let tags = assets.list_tags(asset.as_deref())?;
+            match format {
+                Format::Table => {
+                    reports::tags_table(&tags).printstd();
+                }
+                format => reports::tags_report(&tags).print(format),
+            }
         }
     }
+
+    Ok(())
 }
 
-fn format_money(amount: f64) -> String {
-    let base = format!("{:.2}", amount);
-    if base.len() > 6 {
-        let rev_chunked = base[..base.len() - 6]
-            .chars()
-            .rev()
-            .chunks(3)
-            .into_iter()
-            .flat_map(|chunk| once('\'').chain(chunk))
-            .collect::<Vec<_>>();
-        rev_chunked
-            .into_iter()
-            .rev()
-            .chain(base[base.len() - 6..].chars())
-            .collect()
+fn tx(assets: &mut Assets, command: TxCommands, format: Format) -> Result<(), Error> {
+    match command {
+        TxCommands::Add {
+            asset,
+            kind,
+            amount,
+            price,
+            currency,
+            date,
+            fee,
+        } => {
+            let tx = NewTransaction {
+                kind,
+                date: date.unwrap_or_else(|| chrono::Local::today().naive_local()),
+                amount,
+                price,
+                fee,
+                currency,
+            };
+            let id = assets.add_transaction(&asset, &tx)?;
+            println!("Added transaction {}.", id);
+        }
+        TxCommands::List { asset } => {
+            let transactions = assets.list_transactions(asset.as_deref())?;
+            match format {
+                Format::Table => {
+                    reports::transactions_table(&transactions).printstd();
+                }
+                format => reports::transactions_report(&transactions).print(format),
+            }
+        }
+        TxCommands::Rm { id } => {
+            assets.remove_transaction(id)?;
+        }
+        TxCommands::Basis { asset, method } => {
+            let asset_names = match asset {
+                Some(asset) => vec![asset],
+                None => assets
+                    .list_transactions(None)?
+                    .into_iter()
+                    .map(|tx| tx.asset)
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect(),
+            };
+
+            let bases = asset_names
+                .into_iter()
+                .map(|asset| {
+                    let basis = assets.cost_basis(&asset, method)?;
+                    Ok((asset, basis))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            match format {
+                Format::Table => {
+                    reports::basis_table(&bases).printstd();
+                }
+                format => reports::basis_report(&bases).print(format),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs all health checks, prints a report and returns the exit code
+fn doctor(cfg: AssetsCfg) -> i32 {
+    let (plugins, plugin_checks) = check_plugins(cfg.plugins.iter());
+    let plugins_failed = plugin_checks.iter().any(|check| check.result.is_err());
+    reports::plugin_checks_table(&plugin_checks).printstd();
+    println!();
+
+    let mut assets = match assetman::Assets::with_plugins(&cfg.db_path, plugins) {
+        Ok(assets) => assets,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return exit_code(&e);
+        }
+    };
+    let query_checks = match assets.check_queries() {
+        Ok(checks) => checks,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return exit_code(&e);
+        }
+    };
+
+    reports::query_checks_table(&query_checks).printstd();
+
+    if plugins_failed {
+        EXIT_PLUGIN
+    } else if query_checks.iter().any(|check| check.result.is_err()) {
+        EXIT_QUERY
     } else {
-        base
+        0
     }
 }
@@ -0,0 +1,590 @@
+use crate::db::DbConnection;
+use crate::decimal::DbDecimal;
+use crate::{schema, Assets, Error};
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// A row found by `check_database` and what's wrong with it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub row: Row,
+    pub defect: Defect,
+}
+
+/// Table and primary key of a row
+#[derive(Debug, Clone, PartialEq)]
+pub enum Row {
+    Price { update_id: i32, asset_id: i32 },
+    Holdings { update_id: i32, asset_id: i32 },
+    Transaction(i32),
+    Tag { asset_id: i32, key: String },
+    Ownership { asset_id: i32, portfolio: String },
+    Alert(i32),
+    Update(i32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Defect {
+    MissingAsset(i32),
+    MissingUpdate(i32),
+    /// A price without holdings of the same asset at the same update or the other way round
+    Unpaired,
+    /// The stored number can't be parsed, e.g. `NaN` or `inf`
+    NotFinite(String),
+    /// An update without any prices and holdings
+    Empty,
+    /// A regular update lacks an asset that has values at the regular updates before and after.
+    /// Only informational: besides deleted values, this is also what an asset archived in between
+    /// or a gap in imported history looks like.
+    MissingValues(i32),
+}
+
+impl Problem {
+    /// Whether `repair_database` deletes the row, it leaves informational problems alone
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self.defect, Defect::MissingValues(_))
+    }
+}
+
+/// `VACUUM INTO` was added in SQLite 3.27
+const MIN_BACKUP_SQLITE: (u32, u32) = (3, 27);
+
+impl Assets {
+    /// Copies the SQLite database to `path`, which must not exist yet. The copy is consistent
+    /// even if other clients write to the database meanwhile. Needs SQLite 3.27 or newer.
+    pub fn backup_database(&self, path: &Path) -> Result<(), Error> {
+        let io_error =
+            |kind, message| Error::Io(path.to_path_buf(), std::io::Error::new(kind, message));
+        if path.exists() {
+            return Err(io_error(
+                std::io::ErrorKind::AlreadyExists,
+                "won't overwrite an existing file",
+            ));
+        }
+        let target = path.to_str().ok_or_else(|| {
+            io_error(
+                std::io::ErrorKind::InvalidInput,
+                "the path isn't valid UTF-8",
+            )
+        })?;
+
+        match self.db()? {
+            DbConnection::Sqlite(db) => {
+                let version =
+                    diesel::select(sql::<Text>("sqlite_version()")).get_result::<String>(db)?;
+                if !supports_backup(&version) {
+                    return Err(Error::InvalidConfig(
+                        "database",
+                        format!(
+                            "backups need SQLite {}.{} or newer, the linked SQLite is {}",
+                            MIN_BACKUP_SQLITE.0, MIN_BACKUP_SQLITE.1, version
+                        ),
+                    ));
+                }
+                diesel::sql_query("VACUUM INTO ?")
+                    .bind::<Text, _>(target)
+                    .execute(db)?;
+                Ok(())
+            }
+            #[cfg(feature = "postgres")]
+            DbConnection::Postgres(_) => Err(Error::InvalidConfig(
+                "database",
+                "PostgreSQL databases are backed up with pg_dump".to_string(),
+            )),
+        }
+    }
+
+    /// Looks for rows referring to assets or updates that don't exist, prices without holdings
+    /// and the other way round, prices or holdings that aren't finite numbers, updates without
+    /// values and regular updates lacking an asset. SQLite doesn't enforce references, so e.g.
+    /// deleting rows by hand can leave such rows behind. Lacking assets aren't necessarily a
+    /// problem, see [`Problem::is_repairable`].
+    pub fn check_database(&self) -> Result<Vec<Problem>, Error> {
+        let db_client = self.db()?;
+        let asset_ids = with_db!(db_client, |db| schema::assets::table
+            .select(schema::assets::id)
+            .load::<i32>(db))?
+        .into_iter()
+        .collect::<HashSet<_>>();
+        let update_ids = with_db!(db_client, |db| schema::updates::table
+            .select(schema::updates::id)
+            .load::<i32>(db))?
+        .into_iter()
+        .collect::<HashSet<_>>();
+        let missing = |asset_id: i32, update_id: Option<i32>| {
+            if !asset_ids.contains(&asset_id) {
+                Some(Defect::MissingAsset(asset_id))
+            } else {
+                update_id
+                    .filter(|id| !update_ids.contains(id))
+                    .map(Defect::MissingUpdate)
+            }
+        };
+
+        let mut problems = Vec::new();
+        let prices = with_db!(db_client, |db| schema::prices::table
+            .select((
+                schema::prices::update_id,
+                schema::prices::asset_id,
+                schema::prices::price,
+            ))
+            .order_by((schema::prices::update_id, schema::prices::asset_id))
+            .load::<(i32, i32, String)>(db))?;
+        let holdings = with_db!(db_client, |db| schema::holdings::table
+            .select((
+                schema::holdings::update_id,
+                schema::holdings::asset_id,
+                schema::holdings::amount,
+            ))
+            .order_by((schema::holdings::update_id, schema::holdings::asset_id))
+            .load::<(i32, i32, String)>(db))?;
+        let keys = |values: &[(i32, i32, String)]| {
+            values
+                .iter()
+                .map(|(update_id, asset_id, _)| (*update_id, *asset_id))
+                .collect::<BTreeSet<_>>()
+        };
+        let (price_keys, holdings_keys) = (keys(&prices), keys(&holdings));
+        let updates = with_db!(db_client, |db| schema::updates::table
+            .select((schema::updates::id, schema::updates::synthetic))
+            .order_by((schema::updates::timestamp, schema::updates::id))
+            .load::<(i32, bool)>(db))?;
+        let mut update_assets = BTreeMap::<i32, BTreeSet<i32>>::new();
+        for (update_id, asset_id) in price_keys.iter().chain(&holdings_keys) {
+            update_assets
+                .entry(*update_id)
+                .or_default()
+                .insert(*asset_id);
+        }
+        let no_assets = BTreeSet::new();
+        let assets_at = |update_id| update_assets.get(&update_id).unwrap_or(&no_assets);
+        let regular = updates
+            .iter()
+            .filter(|(_, synthetic)| !synthetic)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for (update_id, _) in &updates {
+            if assets_at(*update_id).is_empty() {
+                problems.push(Problem {
+                    row: Row::Update(*update_id),
+                    defect: Defect::Empty,
+                });
+            }
+        }
+        // fetches store all active assets at once, so an asset missing in between may have lost
+        // its values, but it may as well have been archived meanwhile or imported with gaps
+        for window in regular.windows(3) {
+            let (before, update_id, after) =
+                (assets_at(window[0]), window[1], assets_at(window[2]));
+            let current = assets_at(update_id);
+            if current.is_empty() {
+                continue;
+            }
+            for asset_id in before.intersection(after) {
+                if !current.contains(asset_id) {
+                    problems.push(Problem {
+                        row: Row::Update(update_id),
+                        defect: Defect::MissingValues(*asset_id),
+                    });
+                }
+            }
+        }
+        for (values, paired_keys, is_price) in [
+            (&prices, &holdings_keys, true),
+            (&holdings, &price_keys, false),
+        ] {
+            for (update_id, asset_id, value) in values {
+                let (update_id, asset_id) = (*update_id, *asset_id);
+                let row = if is_price {
+                    Row::Price {
+                        update_id,
+                        asset_id,
+                    }
+                } else {
+                    Row::Holdings {
+                        update_id,
+                        asset_id,
+                    }
+                };
+                let defect = if let Some(defect) = missing(asset_id, Some(update_id)) {
+                    defect
+                } else if !paired_keys.contains(&(update_id, asset_id)) {
+                    Defect::Unpaired
+                } else if DbDecimal::parse(value).is_none() {
+                    Defect::NotFinite(value.clone())
+                } else {
+                    continue;
+                };
+                problems.push(Problem { row, defect });
+            }
+        }
+
+        let transactions = with_db!(db_client, |db| schema::transactions::table
+            .select((schema::transactions::id, schema::transactions::asset_id))
+            .order_by(schema::transactions::id)
+            .load::<(i32, i32)>(db))?;
+        for (id, asset_id) in transactions {
+            if let Some(defect) = missing(asset_id, None) {
+                problems.push(Problem {
+                    row: Row::Transaction(id),
+                    defect,
+                });
+            }
+        }
+        let tags = with_db!(db_client, |db| schema::tags::table
+            .select((schema::tags::asset_id, schema::tags::key))
+            .order_by((schema::tags::asset_id, schema::tags::key))
+            .load::<(i32, String)>(db))?;
+        for (asset_id, key) in tags {
+            if let Some(defect) = missing(asset_id, None) {
+                problems.push(Problem {
+                    row: Row::Tag { asset_id, key },
+                    defect,
+                });
+            }
+        }
+        let ownership = with_db!(db_client, |db| schema::ownership::table
+            .select((schema::ownership::asset_id, schema::ownership::portfolio))
+            .order_by((schema::ownership::asset_id, schema::ownership::portfolio))
+            .load::<(i32, String)>(db))?;
+        for (asset_id, portfolio) in ownership {
+            if let Some(defect) = missing(asset_id, None) {
+                problems.push(Problem {
+                    row: Row::Ownership {
+                        asset_id,
+                        portfolio,
+                    },
+                    defect,
+                });
+            }
+        }
+        let alerts = with_db!(db_client, |db| schema::alerts::table
+            .select((schema::alerts::id, schema::alerts::update_id))
+            .order_by(schema::alerts::id)
+            .load::<(i32, i32)>(db))?;
+        for (id, update_id) in alerts {
+            if !update_ids.contains(&update_id) {
+                problems.push(Problem {
+                    row: Row::Alert(id),
+                    defect: Defect::MissingUpdate(update_id),
+                });
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// Deletes the rows of problems found by `check_database` all at once. Prices and holdings
+    /// only make sense together, so an unpaired or unparsable one is deleted with its partner.
+    /// Empty updates are deleted with their alerts, as are updates left without values by the
+    /// repair. Problems that aren't repairable are skipped.
+    pub fn repair_database(&mut self, problems: &[Problem]) -> Result<(), Error> {
+        with_db!(self.db()?, |db| db.transaction(|| {
+            let mut updates = BTreeSet::new();
+            let mut touched = BTreeSet::new();
+            for problem in problems.iter().filter(|problem| problem.is_repairable()) {
+                let pair_too = matches!(problem.defect, Defect::Unpaired | Defect::NotFinite(_));
+                match &problem.row {
+                    Row::Price {
+                        update_id,
+                        asset_id,
+                    }
+                    | Row::Holdings {
+                        update_id,
+                        asset_id,
+                    } => {
+                        let key = (*update_id, *asset_id);
+                        touched.insert(*update_id);
+                        let is_price = matches!(problem.row, Row::Price { .. });
+                        if is_price || pair_too {
+                            diesel::delete(schema::prices::table.find(key)).execute(db)?;
+                        }
+                        if !is_price || pair_too {
+                            diesel::delete(schema::holdings::table.find(key)).execute(db)?;
+                        }
+                    }
+                    Row::Transaction(id) => {
+                        diesel::delete(schema::transactions::table.find(id)).execute(db)?;
+                    }
+                    Row::Tag { asset_id, key } => {
+                        diesel::delete(schema::tags::table.find((asset_id, key))).execute(db)?;
+                    }
+                    Row::Ownership {
+                        asset_id,
+                        portfolio,
+                    } => {
+                        diesel::delete(schema::ownership::table.find((asset_id, portfolio)))
+                            .execute(db)?;
+                    }
+                    Row::Alert(id) => {
+                        diesel::delete(schema::alerts::table.find(id)).execute(db)?;
+                    }
+                    Row::Update(id) => {
+                        updates.insert(*id);
+                    }
+                }
+            }
+
+            for id in touched {
+                let values = schema::prices::table
+                    .filter(schema::prices::update_id.eq(id))
+                    .count()
+                    .get_result::<i64>(db)?
+                    + schema::holdings::table
+                        .filter(schema::holdings::update_id.eq(id))
+                        .count()
+                        .get_result::<i64>(db)?;
+                if values == 0 {
+                    updates.insert(id);
+                }
+            }
+            for id in updates {
+                diesel::delete(schema::prices::table.filter(schema::prices::update_id.eq(id)))
+                    .execute(db)?;
+                diesel::delete(schema::holdings::table.filter(schema::holdings::update_id.eq(id)))
+                    .execute(db)?;
+                diesel::delete(schema::alerts::table.filter(schema::alerts::update_id.eq(id)))
+                    .execute(db)?;
+                diesel::delete(schema::updates::table.find(id)).execute(db)?;
+            }
+            Ok::<_, Error>(())
+        }))
+    }
+}
+
+impl Display for Row {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Row::Price {
+                update_id,
+                asset_id,
+            } => write!(f, "price of asset {} at update {}", asset_id, update_id),
+            Row::Holdings {
+                update_id,
+                asset_id,
+            } => write!(f, "holdings of asset {} at update {}", asset_id, update_id),
+            Row::Transaction(id) => write!(f, "transaction {}", id),
+            Row::Tag { asset_id, key } => write!(f, "tag '{}' of asset {}", key, asset_id),
+            Row::Ownership {
+                asset_id,
+                portfolio,
+            } => write!(f, "share of asset {} in '{}'", asset_id, portfolio),
+            Row::Alert(id) => write!(f, "alert {}", id),
+            Row::Update(id) => write!(f, "update {}", id),
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.defect {
+            Defect::MissingAsset(id) => write!(f, "{} refers to missing asset {}", self.row, id),
+            Defect::MissingUpdate(id) => {
+                write!(f, "{} refers to missing update {}", self.row, id)
+            }
+            Defect::Unpaired => {
+                let partner = match self.row {
+                    Row::Holdings { .. } => "price",
+                    _ => "holdings",
+                };
+                write!(f, "{} has no {}", self.row, partner)
+            }
+            Defect::NotFinite(value) => {
+                write!(f, "{} isn't a finite number: '{}'", self.row, value)
+            }
+            Defect::Empty => write!(f, "{} has no prices and holdings", self.row),
+            Defect::MissingValues(id) => write!(f, "{} lacks asset {}", self.row, id),
+        }
+    }
+}
+
+/// Whether an SQLite version like `3.31.1` has `VACUUM INTO`
+fn supports_backup(version: &str) -> bool {
+    let mut parts = version
+        .split('.')
+        .map(|part| part.parse::<u32>().unwrap_or(0));
+    let major = parts.next().unwrap_or(0);
+    let minor = parts.next().unwrap_or(0);
+    (major, minor) >= MIN_BACKUP_SQLITE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{supports_backup, Defect, Problem, Row};
+    use crate::{schema, Assets, Plugins};
+    use diesel::prelude::*;
+
+    #[test]
+    fn test_check_and_repair() {
+        let mut assets = Assets::with_plugins(":memory:", Plugins::default()).unwrap();
+        with_db!(assets.db().unwrap(), |db| db.transaction(|| {
+            diesel::insert_into(schema::assets::table)
+                .values((
+                    schema::assets::id.eq(1),
+                    schema::assets::name.eq("Gold"),
+                    schema::assets::price_query.eq("static(1800)"),
+                    schema::assets::holdings_query.eq("static(2)"),
+                    schema::assets::category.eq("metals"),
+                ))
+                .execute(db)?;
+            diesel::insert_into(schema::updates::table)
+                .values(schema::updates::id.eq(1))
+                .execute(db)?;
+            diesel::insert_into(schema::prices::table)
+                .values(&vec![
+                    (
+                        schema::prices::update_id.eq(1),
+                        schema::prices::asset_id.eq(1),
+                        schema::prices::price.eq("NaN"),
+                    ),
+                    (
+                        schema::prices::update_id.eq(2),
+                        schema::prices::asset_id.eq(1),
+                        schema::prices::price.eq("1800"),
+                    ),
+                ])
+                .execute(db)?;
+            diesel::insert_into(schema::holdings::table)
+                .values((
+                    schema::holdings::update_id.eq(1),
+                    schema::holdings::asset_id.eq(1),
+                    schema::holdings::amount.eq("2"),
+                ))
+                .execute(db)?;
+            diesel::insert_into(schema::tags::table)
+                .values((
+                    schema::tags::asset_id.eq(7),
+                    schema::tags::key.eq("owner"),
+                    schema::tags::value.eq("alice"),
+                ))
+                .execute(db)
+        }))
+        .unwrap();
+
+        let problems = assets.check_database().unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                Problem {
+                    row: Row::Price {
+                        update_id: 1,
+                        asset_id: 1
+                    },
+                    defect: Defect::NotFinite("NaN".to_string()),
+                },
+                Problem {
+                    row: Row::Price {
+                        update_id: 2,
+                        asset_id: 1
+                    },
+                    defect: Defect::MissingUpdate(2),
+                },
+                Problem {
+                    row: Row::Tag {
+                        asset_id: 7,
+                        key: "owner".to_string()
+                    },
+                    defect: Defect::MissingAsset(7),
+                },
+            ]
+        );
+        assert_eq!(
+            problems[0].to_string(),
+            "price of asset 1 at update 1 isn't a finite number: 'NaN'"
+        );
+
+        assets.repair_database(&problems).unwrap();
+        assert!(assets.check_database().unwrap().is_empty());
+        // the holdings paired with the unparsable price are gone too
+        let holdings = with_db!(assets.db().unwrap(), |db| schema::holdings::table
+            .count()
+            .get_result::<i64>(db))
+        .unwrap();
+        assert_eq!(holdings, 0);
+    }
+
+    #[test]
+    fn test_empty_and_incomplete_updates() {
+        let mut assets = Assets::with_plugins(":memory:", Plugins::default()).unwrap();
+        with_db!(assets.db().unwrap(), |db| db.transaction(|| {
+            for (id, name) in &[(1, "Gold"), (2, "Silver")] {
+                diesel::insert_into(schema::assets::table)
+                    .values((
+                        schema::assets::id.eq(id),
+                        schema::assets::name.eq(name),
+                        schema::assets::price_query.eq("static(1)"),
+                        schema::assets::holdings_query.eq("static(1)"),
+                        schema::assets::category.eq("metals"),
+                    ))
+                    .execute(db)?;
+            }
+            // update 2 lacks silver, update 4 is empty
+            for (update_id, asset_ids) in
+                &[(1, vec![1, 2]), (2, vec![1]), (3, vec![1, 2]), (4, vec![])]
+            {
+                diesel::insert_into(schema::updates::table)
+                    .values(schema::updates::id.eq(update_id))
+                    .execute(db)?;
+                for asset_id in asset_ids {
+                    diesel::insert_into(schema::prices::table)
+                        .values((
+                            schema::prices::update_id.eq(update_id),
+                            schema::prices::asset_id.eq(asset_id),
+                            schema::prices::price.eq("1"),
+                        ))
+                        .execute(db)?;
+                    diesel::insert_into(schema::holdings::table)
+                        .values((
+                            schema::holdings::update_id.eq(update_id),
+                            schema::holdings::asset_id.eq(asset_id),
+                            schema::holdings::amount.eq("1"),
+                        ))
+                        .execute(db)?;
+                }
+            }
+            Ok::<_, diesel::result::Error>(())
+        }))
+        .unwrap();
+
+        let problems = assets.check_database().unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                Problem {
+                    row: Row::Update(4),
+                    defect: Defect::Empty,
+                },
+                Problem {
+                    row: Row::Update(2),
+                    defect: Defect::MissingValues(2),
+                },
+            ]
+        );
+        assert_eq!(problems[1].to_string(), "update 2 lacks asset 2");
+
+        assert!(problems[0].is_repairable());
+        assert!(!problems[1].is_repairable());
+
+        // only the empty update is deleted, the values at update 2 may well be all there is
+        assets.repair_database(&problems).unwrap();
+        assert_eq!(assets.check_database().unwrap(), problems[1..]);
+        let updates = with_db!(assets.db().unwrap(), |db| schema::updates::table
+            .select(schema::updates::id)
+            .order_by(schema::updates::id)
+            .load::<i32>(db))
+        .unwrap();
+        assert_eq!(updates, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_supports_backup() {
+        assert!(supports_backup("3.27.0"));
+        assert!(supports_backup("3.31.1"));
+        assert!(!supports_backup("3.26.2"));
+        assert!(!supports_backup("garbage"));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::alerts::Alert;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// Delivers alerts somewhere, e.g. to a terminal, a script or a mailbox
+pub trait Notifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError>;
+}
+
+/// Built-in notifiers as configured in `notify` or `AM_NOTIFY`
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotifierCfg {
+    /// Prints alerts to stdout
+    Stdout,
+    /// Runs a program with the message as argument and the alert in `AM_ALERT_*` variables
+    Command(PathBuf),
+    /// Mails alerts to an address using the local `sendmail`
+    Sendmail(String),
+    /// Posts alerts as JSON to an URL using the local `curl`
+    Webhook(String),
+}
+
+#[derive(Debug)]
+pub enum NotifyError {
+    Io(std::io::Error),
+    /// A command exited unsuccessfully
+    CommandFailed(String, std::process::ExitStatus),
+}
+
+struct StdoutNotifier;
+
+struct CommandNotifier {
+    program: PathBuf,
+}
+
+struct SendmailNotifier {
+    to: String,
+}
+
+struct WebhookNotifier {
+    url: String,
+}
+
+impl NotifierCfg {
+    pub fn build(&self) -> Box<dyn Notifier> {
+        match self {
+            NotifierCfg::Stdout => Box::new(StdoutNotifier),
+            NotifierCfg::Command(program) => Box::new(CommandNotifier {
+                program: program.clone(),
+            }),
+            NotifierCfg::Sendmail(to) => Box::new(SendmailNotifier { to: to.clone() }),
+            NotifierCfg::Webhook(url) => Box::new(WebhookNotifier { url: url.clone() }),
+        }
+    }
+}
+
+/// Parses a comma separated list of notifiers, each either `stdout`, `command:<program>`,
+/// `sendmail:<address>` or `webhook:<url>`
+pub fn parse_notifiers(s: &str) -> Result<Vec<NotifierCfg>, String> {
+    s.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+impl FromStr for NotifierCfg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (kind, target) = match s.find(':') {
+            Some(idx) => (&s[..idx], Some(s[idx + 1..].trim())),
+            None => (s, None),
+        };
+        let target = || match target {
+            Some(target) if !target.is_empty() => Ok(target.to_string()),
+            _ => Err(format!(
+                "notifier '{}' needs a target like '{}:…'",
+                kind, kind
+            )),
+        };
+        match kind {
+            "stdout" => Ok(NotifierCfg::Stdout),
+            "command" => Ok(NotifierCfg::Command(PathBuf::from(target()?))),
+            "sendmail" => Ok(NotifierCfg::Sendmail(target()?)),
+            "webhook" => Ok(NotifierCfg::Webhook(target()?)),
+            _ => Err(format!("unknown notifier '{}'", kind)),
+        }
+    }
+}
+
+impl Notifier for StdoutNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        println!("Alert: {}", alert.message);
+        Ok(())
+    }
+}
+
+impl Notifier for CommandNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        let status = Command::new(&self.program)
+            .arg(&alert.message)
+            .env("AM_ALERT_TIME", alert.time.to_string())
+            .env("AM_ALERT_RULE", &alert.rule)
+            .env("AM_ALERT_MESSAGE", &alert.message)
+            .status()?;
+        if !status.success() {
+            return Err(NotifyError::CommandFailed(
+                self.program.display().to_string(),
+                status,
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Notifier for SendmailNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        let mut sendmail = Command::new("sendmail")
+            .arg("-t")
+            .stdin(Stdio::piped())
+            .spawn()?;
+        // messages contain plugin answers, which must not be able to add headers
+        let subject = alert.message.replace(&['\r', '\n'][..], " ");
+        let mail = format!(
+            "To: {}\nSubject: Assetman alert: {}\n\n{}\n\nRule: {}\nUpdate: {}\n",
+            self.to, subject, alert.message, alert.rule, alert.time
+        );
+        sendmail
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(mail.as_bytes())?;
+        let status = sendmail.wait()?;
+        if !status.success() {
+            return Err(NotifyError::CommandFailed("sendmail".to_string(), status));
+        }
+        Ok(())
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        let mut curl = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--max-time", "30"])
+            .args(["--header", "Content-Type: application/json"])
+            .args(["--data-binary", "@-"])
+            .arg("--")
+            .arg(&self.url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        let body = serde_json::json!({
+            "time": alert.time.to_string(),
+            "rule": alert.rule,
+            "message": alert.message,
+        });
+        curl.stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(body.to_string().as_bytes())?;
+        let status = curl.wait()?;
+        if !status.success() {
+            return Err(NotifyError::CommandFailed("curl".to_string(), status));
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for NotifyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NotifyError::Io(e) => Some(e),
+            NotifyError::CommandFailed(..) => None,
+        }
+    }
+}
+
+impl Display for NotifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotifyError::Io(e) => write!(f, "could not run notifier: {}", e),
+            NotifyError::CommandFailed(program, status) => {
+                write!(f, "{} failed with {}", program, status)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for NotifyError {
+    fn from(e: std::io::Error) -> Self {
+        NotifyError::Io(e)
+    }
+}
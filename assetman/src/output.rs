@@ -0,0 +1,232 @@
+use assetman_api::Decimal;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::ser::{SerializeMap, SerializeSeq, Serializer};
+use serde::Serialize;
+use serde_json::value::RawValue;
+use std::io::Write;
+use std::str::FromStr;
+
+/// How reports are printed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Human readable table with rounded and grouped numbers
+    Table,
+    Json,
+    Csv,
+    Tsv,
+}
+
+/// Single value of a report, numbers are printed exactly as stored
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Text(String),
+    Number(Decimal),
+    Integer(i64),
+    Bool(bool),
+    Null,
+}
+
+/// Rows with a fixed set of columns, the column names are the JSON keys and the CSV header
+#[derive(Debug)]
+struct Table {
+    name: &'static str,
+    columns: Vec<String>,
+    rows: Vec<Vec<Field>>,
+}
+
+/// Machine-readable form of a report. Report-wide values like totals or the snapshot time are
+/// only part of the JSON output, CSV and TSV only contain the first table.
+#[derive(Debug, Default)]
+pub struct Report {
+    values: Vec<(&'static str, Field)>,
+    tables: Vec<Table>,
+}
+
+impl Report {
+    pub fn new() -> Report {
+        Report::default()
+    }
+
+    /// Adds a report-wide value
+    pub fn value(mut self, name: &'static str, value: impl Into<Field>) -> Report {
+        self.values.push((name, value.into()));
+        self
+    }
+
+    /// Adds a table, every row has to contain one field per column
+    pub fn table<C: ToString>(
+        mut self,
+        name: &'static str,
+        columns: &[C],
+        rows: Vec<Vec<Field>>,
+    ) -> Report {
+        let columns = columns.iter().map(ToString::to_string).collect::<Vec<_>>();
+        debug_assert!(rows.iter().all(|row| row.len() == columns.len()));
+        self.tables.push(Table {
+            name,
+            columns,
+            rows,
+        });
+        self
+    }
+
+    /// Prints the report to stdout, panicking like `println!` if stdout is closed
+    pub fn print(&self, format: Format) {
+        let stdout = std::io::stdout();
+        self.write(format, stdout.lock())
+            .expect("could not write to stdout");
+    }
+
+    pub fn write(&self, format: Format, mut out: impl Write) -> std::io::Result<()> {
+        match format {
+            Format::Table => unreachable!("tables are printed by the commands themselves"),
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut out, self)?;
+                writeln!(out)
+            }
+            Format::Csv => self.write_delimited(out, b','),
+            Format::Tsv => self.write_delimited(out, b'\t'),
+        }
+    }
+
+    fn write_delimited(&self, out: impl Write, delimiter: u8) -> std::io::Result<()> {
+        let table = match self.tables.first() {
+            Some(table) => table,
+            None => return Ok(()),
+        };
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .from_writer(out);
+        writer.write_record(&table.columns)?;
+        for row in &table.rows {
+            writer.write_record(row.iter().map(Field::to_string))?;
+        }
+        writer.flush()
+    }
+}
+
+impl Serialize for Report {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.values.len() + self.tables.len()))?;
+        for (name, value) in &self.values {
+            map.serialize_entry(name, value)?;
+        }
+        for table in &self.tables {
+            map.serialize_entry(table.name, table)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Table {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct Row<'a>(&'a [String], &'a [Field]);
+        impl Serialize for Row<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut map = serializer.serialize_map(Some(self.0.len()))?;
+                for (column, field) in self.0.iter().zip(self.1) {
+                    map.serialize_entry(column, field)?;
+                }
+                map.end()
+            }
+        }
+
+        let mut seq = serializer.serialize_seq(Some(self.rows.len()))?;
+        for row in &self.rows {
+            seq.serialize_element(&Row(&self.columns, row))?;
+        }
+        seq.end()
+    }
+}
+
+impl Serialize for Field {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Field::Text(text) => serializer.serialize_str(text),
+            // a raw number keeps all digits, unlike a float
+            Field::Number(number) => RawValue::from_string(number.normalize().to_string())
+                .expect("decimals are valid JSON numbers")
+                .serialize(serializer),
+            Field::Integer(integer) => serializer.serialize_i64(*integer),
+            Field::Bool(bool) => serializer.serialize_bool(*bool),
+            Field::Null => serializer.serialize_none(),
+        }
+    }
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Field::Text(text) => write!(f, "{}", text),
+            Field::Number(number) => write!(f, "{}", number.normalize()),
+            Field::Integer(integer) => write!(f, "{}", integer),
+            Field::Bool(bool) => write!(f, "{}", bool),
+            Field::Null => Ok(()),
+        }
+    }
+}
+
+impl From<&str> for Field {
+    fn from(text: &str) -> Self {
+        Field::Text(text.to_string())
+    }
+}
+
+impl From<String> for Field {
+    fn from(text: String) -> Self {
+        Field::Text(text)
+    }
+}
+
+impl From<Decimal> for Field {
+    fn from(number: Decimal) -> Self {
+        Field::Number(number)
+    }
+}
+
+impl From<i32> for Field {
+    fn from(integer: i32) -> Self {
+        Field::Integer(integer.into())
+    }
+}
+
+impl From<bool> for Field {
+    fn from(bool: bool) -> Self {
+        Field::Bool(bool)
+    }
+}
+
+impl From<NaiveDateTime> for Field {
+    fn from(time: NaiveDateTime) -> Self {
+        Field::Text(time.format("%Y-%m-%dT%H:%M:%S").to_string())
+    }
+}
+
+impl From<NaiveDate> for Field {
+    fn from(date: NaiveDate) -> Self {
+        Field::Text(date.format("%Y-%m-%d").to_string())
+    }
+}
+
+impl<T: Into<Field>> From<Option<T>> for Field {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Field::Null)
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(format!(
+                "unknown format '{}', expected table, json, csv or tsv",
+                s
+            )),
+        }
+    }
+}
@@ -1,21 +1,42 @@
-use assetman_api::{Answer, PluginInfo, PluginType, Request};
+use assetman_api::{Answer, DataPoint, Decimal, PluginInfo, PluginType, Request, TimeRange, Value};
 use log::info;
 use regex::Regex;
 use serde_json::de::Deserializer;
 use serde_json::to_writer;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Debug, Display, Formatter};
-use std::path::Path;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Plugins {
     plugins: HashMap<String, Plugin>,
 }
 
-#[derive(Debug)]
+/// How to start a plugin and how long to wait for it
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PluginCfg {
+    pub path: PathBuf,
+    /// Additional environment variables of the plugin process, e.g. servers or API keys
+    pub env: BTreeMap<String, String>,
+    /// How long to wait for an answer, `None` waits forever
+    pub timeout: Option<Duration>,
+}
+
+type AnswerResult = Result<Result<Answer, assetman_api::Error>, serde_json::Error>;
+
 struct Plugin {
-    process: Child,
+    process: Option<Child>,
+    stdin: Box<dyn Write>,
+    /// Answers read from the plugin's stdout by a background thread, so waiting can time out
+    answers: Receiver<AnswerResult>,
+    timeout: Option<Duration>,
+    /// Number of answers to timed out requests that still have to be skipped
+    stale: usize,
     meta: PluginInfo,
 }
 
@@ -23,38 +44,98 @@ impl Plugins {
     pub fn from_paths<P: AsRef<Path> + Debug>(
         paths: impl Iterator<Item = P>,
     ) -> Result<Self, PluginError> {
-        let plugins = paths
-            .map(|ref path| {
-                info!("Loading plugin {:?}", path);
-                let mut plugin = Command::new(path.as_ref())
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .spawn()?;
-
-                let plugin_info = Deserializer::from_reader(
-                    plugin
-                        .stdout
-                        .as_mut()
-                        .expect("Plugin child process has no stdout"),
-                )
-                .into_iter::<PluginInfo>()
-                .next()
-                .expect("Plugin sent no info.")
-                .map_err(|_| PluginError::BadAnswer)?;
-
-                info!("Loaded plugin '{}'", &plugin_info.name);
-
-                Ok((
-                    plugin_info.name.clone(),
-                    Plugin {
-                        process: plugin,
-                        meta: plugin_info,
-                    },
-                ))
-            })
-            .collect::<Result<HashMap<String, Plugin>, PluginError>>()?;
+        let mut plugins = Plugins::default();
+        for path in paths {
+            plugins.load(path)?;
+        }
+        Ok(plugins)
+    }
 
-        Ok(Plugins { plugins })
+    /// Starts all plugins with their settings
+    pub fn from_cfgs<'a>(cfgs: impl Iterator<Item = &'a PluginCfg>) -> Result<Self, PluginError> {
+        let mut plugins = Plugins::default();
+        for cfg in cfgs {
+            plugins.load_with(cfg)?;
+        }
+        Ok(plugins)
+    }
+
+    /// Loads plugins that aren't child processes but connected via arbitrary streams, e.g. mock
+    /// plugins in tests. Each item is a `(plugin stdout, plugin stdin)` pair.
+    pub fn from_streams<R: Read + Send + 'static, W: Write + 'static>(
+        streams: impl Iterator<Item = (R, W)>,
+    ) -> Result<Self, PluginError> {
+        let mut plugins = Plugins::default();
+        for (idx, (stdout, stdin)) in streams.enumerate() {
+            plugins.add(Plugin::connect(
+                &format!("stream #{}", idx),
+                None,
+                Box::new(stdout),
+                Box::new(stdin),
+            )?);
+        }
+        Ok(plugins)
+    }
+
+    /// Starts the plugin at `path` and adds it to the registry
+    pub fn load<P: AsRef<Path> + Debug>(&mut self, path: P) -> Result<&PluginInfo, PluginError> {
+        self.load_with(&PluginCfg::from(path.as_ref().to_path_buf()))
+    }
+
+    /// Starts a plugin with the environment and timeout of `cfg` and adds it to the registry
+    pub fn load_with(&mut self, cfg: &PluginCfg) -> Result<&PluginInfo, PluginError> {
+        info!("Loading plugin {:?}", cfg.path);
+        let mut process = Command::new(&cfg.path)
+            .envs(&cfg.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| PluginError::PluginStartupFailed(cfg.path.clone(), e))?;
+
+        let stdin = process
+            .stdin
+            .take()
+            .expect("Plugin child process has no stdin");
+        let stdout = process
+            .stdout
+            .take()
+            .expect("Plugin child process has no stdout");
+
+        let mut plugin = Plugin::connect(
+            &cfg.path.display().to_string(),
+            Some(process),
+            Box::new(stdout),
+            Box::new(stdin),
+        )?;
+        plugin.timeout = cfg.timeout;
+        Ok(self.add(plugin))
+    }
+
+    /// Changes how long to wait for answers of a loaded plugin, `None` waits forever
+    pub fn set_timeout(
+        &mut self,
+        plugin: &str,
+        timeout: Option<Duration>,
+    ) -> Result<(), PluginError> {
+        self.plugins
+            .get_mut(plugin)
+            .ok_or_else(|| PluginError::UnknownPlugin(plugin.to_string()))?
+            .timeout = timeout;
+        Ok(())
+    }
+
+    /// Adds `plugin` to the registry, replacing a previously loaded plugin of the same name
+    fn add(&mut self, plugin: Plugin) -> &PluginInfo {
+        let name = plugin.meta.name.clone();
+        if self.plugins.insert(name.clone(), plugin).is_some() {
+            info!("Plugin '{}' replaced an earlier one of the same name", name);
+        }
+        &self.plugins[&name].meta
+    }
+
+    /// Returns the info of all loaded plugins
+    pub fn infos(&self) -> impl Iterator<Item = &PluginInfo> {
+        self.plugins.values().map(|plugin| &plugin.meta)
     }
 
     pub fn query_plugin(
@@ -62,102 +143,373 @@ impl Plugins {
         plugin: &str,
         arguments: &str,
         expected_type: PluginType,
-    ) -> Result<f64, PluginError> {
+    ) -> Result<Value, PluginError> {
         info!("Querying plugin {}: {}", plugin, arguments);
 
-        let plugin = self
-            .plugins
-            .get_mut(plugin)
-            .ok_or(PluginError::UnknownPlugin)?;
+        let req = Request {
+            arguments: arguments.to_string(),
+            history: None,
+        };
+        self.request(plugin, req, expected_type, expected_type)
+    }
 
-        if plugin.meta.plugin_type != PluginType::Any && plugin.meta.plugin_type != expected_type {
-            return Err(PluginError::WrongType);
-        }
+    /// Asks a price plugin for historic prices in the given time range. Plugins of type
+    /// `PriceHistory` are asked the same way.
+    pub fn query_plugin_history(
+        &mut self,
+        plugin: &str,
+        arguments: &str,
+        range: TimeRange,
+    ) -> Result<Vec<DataPoint>, PluginError> {
+        info!(
+            "Querying history from plugin {}: {} ({} - {})",
+            plugin, arguments, range.start, range.end
+        );
 
-        let stdin = plugin
-            .process
-            .stdin
-            .as_mut()
-            .expect("Plugin child process has no stdin");
-        let stdout = plugin
-            .process
-            .stdout
-            .as_mut()
-            .expect("Plugin child process has no stdout");
+        let plugin_type = match self.plugins.get(plugin).map(|p| p.meta.plugin_type) {
+            Some(PluginType::PriceHistory) => PluginType::PriceHistory,
+            _ => PluginType::Price,
+        };
 
         let req = Request {
             arguments: arguments.to_string(),
+            history: Some(range),
         };
-        to_writer(stdin, &req).expect("Write error");
+        match self.request(plugin, req, plugin_type, PluginType::PriceHistory)? {
+            Value::Series(series) => Ok(series),
+            _ => Err(PluginError::WrongAnswerType(plugin.to_string())),
+        }
+    }
+
+    fn request(
+        &mut self,
+        plugin: &str,
+        req: Request,
+        plugin_type: PluginType,
+        answer_type: PluginType,
+    ) -> Result<Value, PluginError> {
+        self.check_type(plugin, plugin_type)?;
+
+        let name = plugin;
+        let plugin = self
+            .plugins
+            .get_mut(name)
+            .ok_or_else(|| PluginError::UnknownPlugin(name.to_string()))?;
+
+        to_writer(&mut plugin.stdin, &req)
+            .map_err(|e| PluginError::ConnectionError(name.to_string(), e.into()))?;
+
+        let answer = plugin
+            .receive(name)?
+            .map_err(|_| PluginError::BadAnswer(name.to_string()))?
+            .map_err(|e| PluginError::PluginError(name.to_string(), e))?;
+
+        if !answer_type.accepts(&answer.answer) {
+            return Err(PluginError::WrongAnswerType(name.to_string()));
+        }
 
-        let answer = Deserializer::from_reader(stdout)
-            .into_iter::<Result<Answer, assetman_api::Error>>()
-            .next()
-            .ok_or(PluginError::BadAnswer)?
-            .map_err(|_| PluginError::BadAnswer)??;
         Ok(answer.answer)
     }
 
-    pub fn query(&mut self, query: &str, expected_type: PluginType) -> Result<f64, PluginError> {
-        let query_re = Regex::new(r"([^(]*)\((.*)\)").unwrap();
-        let captures = query_re
-            .captures(query)
-            .ok_or(PluginError::QueryParseError)?;
-        let plugin = captures
-            .get(1)
-            .ok_or(PluginError::QueryParseError)?
-            .as_str();
-        let arguments = captures
-            .get(2)
-            .ok_or(PluginError::QueryParseError)?
-            .as_str();
+    /// Checks that a plugin with the given name is loaded and can be queried for `expected_type`
+    pub fn check_type(&self, plugin: &str, expected_type: PluginType) -> Result<(), PluginError> {
+        let plugin_type = self
+            .plugins
+            .get(plugin)
+            .ok_or_else(|| PluginError::UnknownPlugin(plugin.to_string()))?
+            .meta
+            .plugin_type;
 
+        if plugin_type.provides(expected_type) {
+            Ok(())
+        } else {
+            Err(PluginError::WrongType {
+                plugin: plugin.to_string(),
+                plugin_type,
+                requested: expected_type,
+            })
+        }
+    }
+
+    pub fn query(&mut self, query: &str, expected_type: PluginType) -> Result<Value, PluginError> {
+        let (plugin, arguments) = parse_query(query)?;
         self.query_plugin(plugin, arguments, expected_type)
     }
+
+    /// Runs a price query for historic prices in the given time range
+    pub fn query_history(
+        &mut self,
+        query: &str,
+        range: TimeRange,
+    ) -> Result<Vec<DataPoint>, PluginError> {
+        let (plugin, arguments) = parse_query(query)?;
+        self.query_plugin_history(plugin, arguments, range)
+    }
+
+    /// Runs a query that has to return a plain number, like holdings or prices
+    pub fn query_number(
+        &mut self,
+        query: &str,
+        expected_type: PluginType,
+    ) -> Result<Decimal, PluginError> {
+        let (plugin, arguments) = parse_query(query)?;
+        self.query_plugin(plugin, arguments, expected_type)?
+            .number()
+            .ok_or_else(|| PluginError::WrongAnswerType(plugin.to_string()))
+    }
 }
 
-#[derive(Debug)]
-pub enum PluginError {
-    PluginStartupFailed(std::io::Error),
-    QueryParseError,
-    UnknownPlugin,
-    WrongType,
-    BadAnswer,
-    PluginError(assetman_api::Error),
+impl Plugin {
+    /// Reads the plugin info the plugin sends on startup, `origin` describes where the plugin came
+    /// from for error messages
+    fn connect(
+        origin: &str,
+        process: Option<Child>,
+        mut stdout: Box<dyn Read + Send>,
+        stdin: Box<dyn Write>,
+    ) -> Result<Plugin, PluginError> {
+        let meta = Deserializer::from_reader(&mut stdout)
+            .into_iter::<PluginInfo>()
+            .next()
+            .ok_or_else(|| PluginError::BadHandshake(origin.to_string()))?
+            .map_err(|_| PluginError::BadHandshake(origin.to_string()))?;
+
+        info!("Loaded plugin '{}'", &meta.name);
+
+        let (sender, answers) = channel();
+        thread::spawn(move || loop {
+            let answer = match Deserializer::from_reader(&mut stdout)
+                .into_iter::<Result<Answer, assetman_api::Error>>()
+                .next()
+            {
+                Some(Err(e)) if e.is_io() => break,
+                Some(answer) => answer,
+                None => break,
+            };
+            if sender.send(answer).is_err() {
+                break;
+            }
+        });
+
+        Ok(Plugin {
+            process,
+            stdin,
+            answers,
+            timeout: None,
+            stale: 0,
+            meta,
+        })
+    }
+
+    /// Waits for the answer to the latest request, skipping late answers to earlier requests
+    /// that timed out
+    fn receive(&mut self, name: &str) -> Result<AnswerResult, PluginError> {
+        loop {
+            let answer = match self.timeout {
+                Some(timeout) => match self.answers.recv_timeout(timeout) {
+                    Ok(answer) => answer,
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.stale += 1;
+                        return Err(PluginError::Timeout(name.to_string(), timeout));
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(PluginError::BadAnswer(name.to_string()))
+                    }
+                },
+                None => self
+                    .answers
+                    .recv()
+                    .map_err(|_| PluginError::BadAnswer(name.to_string()))?,
+            };
+            if self.stale == 0 {
+                return Ok(answer);
+            }
+            self.stale -= 1;
+        }
+    }
 }
 
-impl std::error::Error for PluginError {}
+impl From<PathBuf> for PluginCfg {
+    fn from(path: PathBuf) -> Self {
+        PluginCfg {
+            path,
+            ..PluginCfg::default()
+        }
+    }
+}
 
-impl Display for PluginError {
+impl Debug for Plugin {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        Debug::fmt(self, f)
+        f.debug_struct("Plugin")
+            .field("process", &self.process)
+            .field("timeout", &self.timeout)
+            .field("meta", &self.meta)
+            .finish()
     }
 }
 
-impl From<std::io::Error> for PluginError {
-    fn from(e: std::io::Error) -> Self {
-        PluginError::PluginStartupFailed(e)
+/// Splits a query of the form `plugin(arguments)` into plugin name and arguments
+pub fn parse_query(query: &str) -> Result<(&str, &str), PluginError> {
+    let parse_error = || PluginError::QueryParseError(query.to_string());
+    let query_re = Regex::new(r"([^(]*)\((.*)\)").unwrap();
+    let captures = query_re.captures(query).ok_or_else(parse_error)?;
+    let plugin = captures.get(1).ok_or_else(parse_error)?.as_str();
+    let arguments = captures.get(2).ok_or_else(parse_error)?.as_str();
+
+    Ok((plugin, arguments))
+}
+
+#[derive(Debug)]
+pub enum PluginError {
+    /// The plugin executable couldn't be started
+    PluginStartupFailed(PathBuf, std::io::Error),
+    /// The plugin didn't send a valid plugin info on startup
+    BadHandshake(String),
+    /// The query isn't of the form `plugin(arguments)`
+    QueryParseError(String),
+    UnknownPlugin(String),
+    WrongType {
+        plugin: String,
+        plugin_type: PluginType,
+        requested: PluginType,
+    },
+    /// The plugin answered with a value of the wrong shape, e.g. a number instead of a series
+    WrongAnswerType(String),
+    /// The plugin's answer couldn't be parsed or it didn't answer at all
+    BadAnswer(String),
+    ConnectionError(String, std::io::Error),
+    /// The plugin answered with an error
+    PluginError(String, assetman_api::Error),
+    /// The plugin didn't answer within its configured timeout
+    Timeout(String, Duration),
+}
+
+impl std::error::Error for PluginError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PluginError::PluginStartupFailed(_, e) => Some(e),
+            PluginError::ConnectionError(_, e) => Some(e),
+            PluginError::PluginError(_, e) => Some(e),
+            _ => None,
+        }
     }
 }
 
-impl From<assetman_api::Error> for PluginError {
-    fn from(e: assetman_api::Error) -> Self {
-        PluginError::PluginError(e)
+impl Display for PluginError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginError::PluginStartupFailed(path, e) => {
+                write!(f, "could not start plugin {}: {}", path.display(), e)
+            }
+            PluginError::BadHandshake(origin) => {
+                write!(f, "plugin {} sent no valid plugin info on startup", origin)
+            }
+            PluginError::QueryParseError(query) => write!(
+                f,
+                "could not parse query '{}', expected the form plugin(arguments)",
+                query
+            ),
+            PluginError::UnknownPlugin(plugin) => write!(f, "no plugin named '{}' loaded", plugin),
+            PluginError::WrongType {
+                plugin,
+                plugin_type,
+                requested,
+            } => write!(
+                f,
+                "plugin '{}' provides {:?} data but was asked for {:?}",
+                plugin, plugin_type, requested
+            ),
+            PluginError::WrongAnswerType(plugin) => {
+                write!(
+                    f,
+                    "plugin '{}' answered with the wrong kind of value",
+                    plugin
+                )
+            }
+            PluginError::BadAnswer(plugin) => {
+                write!(f, "plugin '{}' sent a malformed answer or exited", plugin)
+            }
+            PluginError::ConnectionError(plugin, e) => {
+                write!(f, "lost connection to plugin '{}': {}", plugin, e)
+            }
+            PluginError::PluginError(plugin, e) => {
+                write!(f, "plugin '{}' reported {}", plugin, e)
+            }
+            PluginError::Timeout(plugin, timeout) => write!(
+                f,
+                "plugin '{}' didn't answer within {} s",
+                plugin,
+                timeout.as_secs_f64()
+            ),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::plugins::Plugins;
-    use assetman_api::PluginType::Holdings;
+    use crate::plugins::{PluginError, Plugins};
+    use assetman_api::Decimal;
+    use assetman_api::PluginType::{Holdings, Price};
+    use assetman_mock::MockPlugin;
+    use std::time::Duration;
 
     #[test]
     fn test_plugins() {
-        let plugins = &[
-            "../target/debug/assetman-static-holdings",
-            "../target/debug/assetman-static-price",
+        let plugins = vec![
+            MockPlugin::new("static_h", Holdings)
+                .answer("1.234", Decimal::new(1234, 3))
+                .spawn()
+                .unwrap(),
+            MockPlugin::new("static_p", Price)
+                .answer("5", Decimal::new(5, 0))
+                .spawn()
+                .unwrap(),
         ];
-        let mut registry = Plugins::from_paths(plugins.into_iter()).unwrap();
-        assert_eq!(registry.query("static_h(1.234)", Holdings).unwrap(), 1.234);
+        let mut registry = Plugins::from_streams(plugins.into_iter()).unwrap();
+        assert_eq!(
+            registry.query_number("static_h(1.234)", Holdings).unwrap(),
+            Decimal::new(1234, 3)
+        );
+        assert_eq!(
+            registry.query_number("static_p(5)", Price).unwrap(),
+            Decimal::new(5, 0)
+        );
+
+        match registry.query_number("static_p(5)", Holdings) {
+            Err(PluginError::WrongType { .. }) => {}
+            res => panic!("expected type error, got {:?}", res),
+        }
+        match registry.query_number("static_x(5)", Price) {
+            Err(PluginError::UnknownPlugin(plugin)) => assert_eq!(plugin, "static_x"),
+            res => panic!("expected unknown plugin error, got {:?}", res),
+        }
+        match registry.query_number("static_p", Price) {
+            Err(PluginError::QueryParseError(_)) => {}
+            res => panic!("expected parse error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let plugin = MockPlugin::new("slow", Price)
+            .delay("slow", Duration::from_millis(300), Decimal::new(1, 0))
+            .answer("fast", Decimal::new(2, 0))
+            .spawn()
+            .unwrap();
+        let mut registry = Plugins::from_streams(std::iter::once(plugin)).unwrap();
+        registry
+            .set_timeout("slow", Some(Duration::from_millis(50)))
+            .unwrap();
+
+        match registry.query_number("slow(slow)", Price) {
+            Err(PluginError::Timeout(plugin, _)) => assert_eq!(plugin, "slow"),
+            res => panic!("expected timeout, got {:?}", res),
+        }
+        // the late answer to the timed out request must not be mistaken for this one
+        registry.set_timeout("slow", None).unwrap();
+        assert_eq!(
+            registry.query_number("slow(fast)", Price).unwrap(),
+            Decimal::new(2, 0)
+        );
     }
 }
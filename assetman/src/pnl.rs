@@ -0,0 +1,172 @@
+use crate::decimal::DbDecimal;
+use crate::transactions::{CostBasis, CostBasisMethod};
+use crate::{schema, Assets, Error};
+use assetman_api::Decimal;
+use diesel::prelude::*;
+use itertools::Itertools;
+use std::collections::{BTreeMap, HashMap};
+
+/// Profit and loss of a single asset with transactions
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetPnl {
+    pub name: String,
+    pub category: String,
+    pub currency: Option<String>,
+    /// Units held according to the transactions
+    pub amount: Decimal,
+    /// What was paid for the units still held
+    pub cost: Decimal,
+    /// What was paid for all units ever bought or deposited
+    pub invested: Decimal,
+    /// Value of the units still held at the latest price, `None` if no price was fetched yet
+    pub market_value: Option<Decimal>,
+    pub realized: BTreeMap<i32, Decimal>,
+}
+
+/// Profit and loss summed up over several assets
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PnlTotals {
+    pub cost: Decimal,
+    pub invested: Decimal,
+    pub market_value: Decimal,
+    pub unrealized: Decimal,
+    pub realized: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PnlReport {
+    /// Assets with transactions, ordered by name
+    pub assets: Vec<AssetPnl>,
+    /// Totals per category of the assets above
+    pub categories: BTreeMap<String, PnlTotals>,
+    /// Gains realized per tax year over all assets
+    pub realized: BTreeMap<i32, Decimal>,
+    pub total: PnlTotals,
+}
+
+impl AssetPnl {
+    /// Gain of the units still held, `None` if no price was fetched yet
+    pub fn unrealized(&self) -> Option<Decimal> {
+        self.market_value.map(|value| value - self.cost)
+    }
+
+    pub fn realized_total(&self) -> Decimal {
+        self.realized.values().sum()
+    }
+
+    pub fn totals(&self) -> PnlTotals {
+        PnlTotals {
+            cost: self.cost,
+            invested: self.invested,
+            market_value: self.market_value.unwrap_or(self.cost),
+            unrealized: self.unrealized().unwrap_or(Decimal::ZERO),
+            realized: self.realized_total(),
+        }
+    }
+}
+
+impl PnlTotals {
+    /// Realized and unrealized gains together
+    pub fn total_return(&self) -> Decimal {
+        self.realized + self.unrealized
+    }
+
+    /// Total return relative to everything invested, `None` if nothing was invested
+    pub fn total_return_rate(&self) -> Option<Decimal> {
+        if self.invested.is_zero() {
+            None
+        } else {
+            Some(self.total_return() / self.invested)
+        }
+    }
+
+    fn add(&mut self, other: &PnlTotals) {
+        self.cost += other.cost;
+        self.invested += other.invested;
+        self.market_value += other.market_value;
+        self.unrealized += other.unrealized;
+        self.realized += other.realized;
+    }
+}
+
+impl Assets {
+    /// Reports realized and unrealized gains of all assets with transactions. Unrealized gains are
+    /// based on the prices of the latest regular update. Assets without a price count at cost.
+    /// All transactions have to be in the base currency, or in the same currency if none is set.
+    pub fn pnl(&self, method: CostBasisMethod) -> Result<PnlReport, Error> {
+        let latest_prices = self.latest_prices()?;
+        let transactions = self.list_transactions(None)?;
+        self.transactions_currency(&transactions)?;
+        let categories = with_db!(self.db()?, |db| schema::assets::table
+            .select((schema::assets::name, schema::assets::category))
+            .load::<(String, String)>(db))?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        let mut report = PnlReport {
+            assets: Vec::new(),
+            categories: BTreeMap::new(),
+            realized: BTreeMap::new(),
+            total: PnlTotals::default(),
+        };
+        let by_asset = transactions
+            .into_iter()
+            .sorted_by(|a, b| a.asset.cmp(&b.asset))
+            .group_by(|tx| tx.asset.clone());
+        for (name, transactions) in &by_asset {
+            let basis = CostBasis::compute(
+                &transactions.collect::<Vec<_>>(),
+                method,
+                self.tax_year_start,
+            )?;
+            let market_value = latest_prices.get(&name).map(|price| *price * basis.amount);
+            let asset = AssetPnl {
+                category: categories.get(&name).cloned().unwrap_or_default(),
+                currency: basis.currency,
+                amount: basis.amount,
+                cost: basis.cost,
+                invested: basis.invested,
+                market_value,
+                realized: basis.realized,
+                name,
+            };
+
+            let totals = asset.totals();
+            report
+                .categories
+                .entry(asset.category.clone())
+                .or_default()
+                .add(&totals);
+            report.total.add(&totals);
+            for (year, gain) in &asset.realized {
+                *report.realized.entry(*year).or_insert(Decimal::ZERO) += *gain;
+            }
+            report.assets.push(asset);
+        }
+
+        Ok(report)
+    }
+
+    /// Prices of the latest regular update by asset name
+    fn latest_prices(&self) -> Result<HashMap<String, Decimal>, Error> {
+        let last_update = with_db!(self.db()?, |db| schema::updates::table
+            .select(schema::updates::id)
+            .filter(schema::updates::synthetic.eq(false))
+            .order(schema::updates::timestamp.desc())
+            .first::<i32>(db))
+        .optional()?;
+        let last_update = match last_update {
+            Some(id) => id,
+            None => return Ok(HashMap::new()),
+        };
+
+        Ok(with_db!(self.db()?, |db| schema::assets::table
+            .inner_join(schema::prices::table)
+            .select((schema::assets::name, schema::prices::price))
+            .filter(schema::prices::update_id.eq(last_update))
+            .load::<(String, DbDecimal)>(db))?
+        .into_iter()
+        .map(|(name, price)| (name, price.into()))
+        .collect())
+    }
+}
@@ -0,0 +1,187 @@
+use crate::decimal::DbDecimal;
+use crate::schema::ownership;
+use crate::{schema, Assets, AssetsSnapshot, Error};
+use assetman_api::Decimal;
+use diesel::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// Share of an asset a portfolio owns, e.g. `0.5` for one half of a joint account
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ownership {
+    pub asset: String,
+    pub portfolio: String,
+    pub share: Decimal,
+}
+
+#[derive(Debug, Queryable)]
+struct OwnershipRow {
+    asset: String,
+    portfolio: String,
+    #[diesel(deserialize_as = "DbDecimal")]
+    share: Decimal,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "ownership"]
+struct InsertOwnership<'a> {
+    asset_id: i32,
+    portfolio: &'a str,
+    share: DbDecimal,
+}
+
+impl Assets {
+    /// Restricts all reports to the assets owned by `portfolio`, scaled by the owned share.
+    /// `None` selects the combined view of all assets.
+    pub fn select_portfolio(&mut self, portfolio: Option<&str>) -> Result<(), Error> {
+        if let Some(portfolio) = portfolio {
+            if !self.list_portfolios()?.iter().any(|name| name == portfolio) {
+                return Err(Error::PortfolioNotFound(portfolio.to_string()));
+            }
+        }
+        self.portfolio = portfolio.map(str::to_string);
+        Ok(())
+    }
+
+    /// Currently selected portfolio, `None` for the combined view
+    pub fn portfolio(&self) -> Option<&str> {
+        self.portfolio.as_deref()
+    }
+
+    /// Assigns a share of an asset to a portfolio, creating the portfolio if it doesn't exist
+    /// yet. The shares of all portfolios owning an asset may add up to at most 100%.
+    pub fn set_ownership(
+        &mut self,
+        asset: &str,
+        portfolio: &str,
+        share: Decimal,
+    ) -> Result<(), Error> {
+        if portfolio.trim().is_empty() {
+            return Err(Error::InvalidOwnership(
+                "the portfolio name can't be empty".to_string(),
+            ));
+        }
+        if share.is_sign_negative() || share.is_zero() || share > Decimal::ONE {
+            return Err(Error::InvalidOwnership(
+                "the share has to be above 0% and at most 100%".to_string(),
+            ));
+        }
+        let asset_id = self.asset_id(asset)?;
+        let others: Decimal = self
+            .list_ownership()?
+            .into_iter()
+            .filter(|other| other.asset == asset && other.portfolio != portfolio)
+            .map(|other| other.share)
+            .sum();
+        if others + share > Decimal::ONE {
+            return Err(Error::InvalidOwnership(format!(
+                "the shares of '{}' would add up to more than 100%",
+                asset
+            )));
+        }
+
+        with_db!(self.db()?, |db| db.transaction(|| {
+            diesel::delete(schema::ownership::table.find((asset_id, portfolio))).execute(db)?;
+            diesel::insert_into(schema::ownership::table)
+                .values(InsertOwnership {
+                    asset_id,
+                    portfolio,
+                    share: DbDecimal(share),
+                })
+                .execute(db)
+        }))?;
+        Ok(())
+    }
+
+    pub fn remove_ownership(&mut self, asset: &str, portfolio: &str) -> Result<(), Error> {
+        let asset_id = self.asset_id(asset)?;
+        let deleted = with_db!(self.db()?, |db| diesel::delete(
+            schema::ownership::table.find((asset_id, portfolio))
+        )
+        .execute(db))?;
+        if deleted == 0 {
+            return Err(Error::InvalidOwnership(format!(
+                "portfolio '{}' doesn't own '{}'",
+                portfolio, asset
+            )));
+        }
+        Ok(())
+    }
+
+    /// Lists which portfolios own which share of which asset, ordered by portfolio and asset
+    pub fn list_ownership(&self) -> Result<Vec<Ownership>, Error> {
+        Ok(with_db!(self.db()?, |db| schema::ownership::table
+            .inner_join(schema::assets::table)
+            .select((
+                schema::assets::name,
+                schema::ownership::portfolio,
+                schema::ownership::share,
+            ))
+            .order_by((schema::ownership::portfolio, schema::assets::name))
+            .load::<OwnershipRow>(db))?
+        .into_iter()
+        .map(|row| Ownership {
+            asset: row.asset,
+            portfolio: row.portfolio,
+            share: row.share,
+        })
+        .collect())
+    }
+
+    /// Names of all portfolios that own at least a share of one asset
+    pub fn list_portfolios(&self) -> Result<Vec<String>, Error> {
+        Ok(with_db!(self.db()?, |db| schema::ownership::table
+            .select(schema::ownership::portfolio)
+            .distinct()
+            .order_by(schema::ownership::portfolio)
+            .load::<String>(db))?)
+    }
+
+    /// Latest update of every portfolio, see `list_assets`
+    pub fn portfolio_snapshots(&self) -> Result<BTreeMap<String, AssetsSnapshot>, Error> {
+        let combined = self.combined_snapshot()?;
+        let mut shares = BTreeMap::<String, HashMap<String, Decimal>>::new();
+        for ownership in self.list_ownership()? {
+            shares
+                .entry(ownership.portfolio)
+                .or_default()
+                .insert(ownership.asset, ownership.share);
+        }
+        Ok(shares
+            .into_iter()
+            .map(|(portfolio, shares)| {
+                let snapshot = AssetsSnapshot {
+                    time: combined.time,
+                    assets: combined.assets.clone(),
+                };
+                (portfolio, snapshot.owned(&shares))
+            })
+            .collect())
+    }
+
+    /// Shares of the assets owned by the selected portfolio by asset name, `None` in the
+    /// combined view
+    pub(crate) fn owned_shares(&self) -> Result<Option<HashMap<String, Decimal>>, Error> {
+        let portfolio = match &self.portfolio {
+            Some(portfolio) => portfolio,
+            None => return Ok(None),
+        };
+        Ok(Some(
+            self.list_ownership()?
+                .into_iter()
+                .filter(|ownership| &ownership.portfolio == portfolio)
+                .map(|ownership| (ownership.asset, ownership.share))
+                .collect(),
+        ))
+    }
+}
+
+impl AssetsSnapshot {
+    /// Keeps only the owned assets and scales their holdings by the owned share
+    pub(crate) fn owned(mut self, shares: &HashMap<String, Decimal>) -> AssetsSnapshot {
+        self.assets.retain(|asset| shares.contains_key(&asset.name));
+        for asset in &mut self.assets {
+            asset.holdings *= shares[&asset.name];
+        }
+        self
+    }
+}
@@ -0,0 +1,1047 @@
+//! Renders the results of the commands, as tables for the terminal and as [`Report`]s for the
+//! machine-readable formats
+
+use crate::output::{Field, Report};
+use assetman::alerts::{Alert, AlertRule};
+use assetman::allocation::{Drift, TargetKind, Trade};
+use assetman::doctor::{PluginCheck, QueryCheck};
+use assetman::maintenance::Problem;
+use assetman::pnl::{PnlReport, PnlTotals};
+use assetman::portfolios::Ownership;
+use assetman::returns::{Returns, ReturnsReport};
+use assetman::sync::SyncChange;
+use assetman::tags::{AssetGroup, Tag, CATEGORY_KEY, UNTAGGED};
+use assetman::transactions::{CostBasis, Transaction};
+use assetman::{Asset, AssetsSnapshot};
+use assetman_api::{Decimal, RoundingStrategy};
+use chrono::NaiveDateTime;
+use itertools::Itertools;
+use prettytable::Table;
+use std::collections::BTreeMap;
+use std::iter::once;
+use std::time::Duration;
+
+pub fn list_table(
+    assets: &[Asset],
+    value_title: &str,
+    gross_assets: Decimal,
+    liabilities: Decimal,
+) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Asset", "Holdings", "Price", value_title]);
+    for asset in assets {
+        table.add_row(row![
+            asset.name,
+            r -> format_money(asset.holdings),
+            r -> format_money(asset.price),
+            r -> format_money(asset.value()),
+        ]);
+    }
+    add_sum_rows(&mut table, gross_assets, liabilities);
+    table
+}
+
+/// Lists the assets by group, each group followed by its sum and share of the gross assets
+pub fn grouped_list_table(
+    groups: &[AssetGroup],
+    value_title: &str,
+    gross_assets: Decimal,
+    liabilities: Decimal,
+) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Asset", "Holdings", "Price", value_title, "Rel"]);
+    for group in groups {
+        add_group_rows(&mut table, group, 0, gross_assets);
+    }
+    add_sum_rows(&mut table, gross_assets, liabilities);
+    table
+}
+
+/// One record per asset including the values of the tags it is grouped by
+pub fn list_report(
+    time: NaiveDateTime,
+    assets: Vec<(Asset, Vec<(String, String)>)>,
+    group_by: &[String],
+) -> Report {
+    let tag_keys = group_by
+        .iter()
+        .filter(|key| *key != CATEGORY_KEY)
+        .collect::<Vec<_>>();
+    let mut columns = [
+        "time",
+        "name",
+        "category",
+        "liability",
+        "holdings",
+        "price",
+        "value",
+    ]
+    .iter()
+    .map(|column| column.to_string())
+    .collect::<Vec<_>>();
+    columns.extend(tag_keys.iter().map(|key| format!("tag_{}", key)));
+
+    let rows = assets
+        .into_iter()
+        .map(|(asset, path)| {
+            let mut row = vec![
+                time.into(),
+                asset.name.as_str().into(),
+                asset.category.as_str().into(),
+                asset.liability.into(),
+                asset.holdings.into(),
+                asset.price.into(),
+                asset.value().into(),
+            ];
+            row.extend(tag_keys.iter().map(|key| {
+                path.iter()
+                    .find(|(group_key, _)| group_key == *key)
+                    .map(|(_, value)| value.as_str())
+                    .filter(|value| *value != UNTAGGED)
+                    .into()
+            }));
+            row
+        })
+        .collect();
+
+    Report::new()
+        .value("time", time)
+        .table("assets", &columns, rows)
+}
+
+/// Collects the assets of the groups and their subgroups together with the keys and values of
+/// the groups they are in
+pub fn flatten_groups(groups: Vec<AssetGroup>) -> Vec<(Asset, Vec<(String, String)>)> {
+    let mut assets = Vec::new();
+    for group in groups {
+        flatten_group(group, &mut Vec::new(), &mut assets);
+    }
+    assets
+}
+
+fn flatten_group(
+    group: AssetGroup,
+    path: &mut Vec<(String, String)>,
+    assets: &mut Vec<(Asset, Vec<(String, String)>)>,
+) {
+    path.push((group.key, group.value));
+    for subgroup in group.groups {
+        flatten_group(subgroup, path, assets);
+    }
+    for asset in group.assets {
+        assets.push((asset, path.clone()));
+    }
+    path.pop();
+}
+
+/// Adds a group with its subgroups or assets followed by its sum, indented by nesting depth
+fn add_group_rows(table: &mut Table, group: &AssetGroup, depth: usize, gross_assets: Decimal) {
+    let indent = "  ".repeat(depth);
+    if depth == 0 {
+        table.add_empty_row();
+    }
+    table.add_row(row!(bFy -> format!("{}{}", indent, group.value), "", "", "", ""));
+
+    for subgroup in &group.groups {
+        add_group_rows(table, subgroup, depth + 1, gross_assets);
+    }
+    for asset in &group.assets {
+        table.add_row(row![
+            format!("{}{}", indent, asset.name),
+            r -> format_money(asset.holdings),
+            r -> format_money(asset.price),
+            r -> format_money(asset.value()),
+            r -> ""
+        ]);
+    }
+
+    let value = group.value();
+    table.add_row(row!(
+        b -> format!("{}{} Sum", indent, capitalize(&group.key)),
+        "",
+        "",
+        br -> format_money(value),
+        r -> format_share(value, gross_assets)
+    ));
+}
+
+fn add_sum_rows(table: &mut Table, gross_assets: Decimal, liabilities: Decimal) {
+    table.add_empty_row();
+    if liabilities.is_zero() {
+        table.add_row(row!(b -> "Sum", "", "", br -> format_money(gross_assets)));
+    } else {
+        table.add_row(row!(b -> "Gross Assets", "", "", br -> format_money(gross_assets)));
+        table.add_row(row!(b -> "Liabilities", "", "", br -> format_money(liabilities)));
+        table.add_row(
+            row!(b -> "Net Worth", "", "", br -> format_money(gross_assets + liabilities)),
+        );
+    }
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+pub fn history_table(history: &[AssetsSnapshot]) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Time", "Gross Assets", "Liabilities", "Net Worth"]);
+    for snapshot in history {
+        table.add_row(row![
+            snapshot.time,
+            r -> format_money(snapshot.gross_assets()),
+            r -> format_money(snapshot.liabilities()),
+            r -> format_money(snapshot.net_worth()),
+        ]);
+    }
+    table
+}
+
+/// One record per regular update with the totals at that time
+pub fn history_report(history: &[AssetsSnapshot]) -> Report {
+    let rows = history
+        .iter()
+        .map(|snapshot| {
+            vec![
+                snapshot.time.into(),
+                snapshot.gross_assets().into(),
+                snapshot.liabilities().into(),
+                snapshot.net_worth().into(),
+            ]
+        })
+        .collect();
+    Report::new().table(
+        "updates",
+        &["time", "gross_assets", "liabilities", "net_worth"],
+        rows,
+    )
+}
+
+pub fn returns_table(report: &ReturnsReport, group_by_category: bool) -> Table {
+    let mut table = new_table();
+    table.set_titles(row![
+        "Name",
+        "Start",
+        "End",
+        "Start Value",
+        "End Value",
+        "Net Flows",
+        "TWR",
+        "XIRR"
+    ]);
+    for returns in &report.assets {
+        add_returns_row(&mut table, returns, false);
+    }
+    if group_by_category {
+        table.add_empty_row();
+        for returns in &report.categories {
+            add_returns_row(&mut table, returns, true);
+        }
+    }
+    table.add_empty_row();
+    add_returns_row(&mut table, &report.portfolio, true);
+    table
+}
+
+fn add_returns_row(table: &mut Table, returns: &Returns, bold: bool) {
+    let date =
+        |time: Option<NaiveDateTime>| time.map(|time| time.date().to_string()).unwrap_or_default();
+    let name = if bold {
+        cell!(b -> returns.name)
+    } else {
+        cell!(returns.name)
+    };
+    table.add_row(prettytable::Row::new(vec![
+        name,
+        cell!(date(returns.start)),
+        cell!(date(returns.end)),
+        cell!(r -> format_money(returns.start_value)),
+        cell!(r -> format_money(returns.end_value)),
+        cell!(r -> format_money(returns.net_flows)),
+        cell!(r -> format_rate(returns.time_weighted)),
+        cell!(r -> format_rate(returns.money_weighted)),
+    ]));
+}
+
+pub fn returns_report(report: &ReturnsReport) -> Report {
+    let portfolio = &report.portfolio;
+    Report::new()
+        .value("start", portfolio.start)
+        .value("end", portfolio.end)
+        .value("start_value", portfolio.start_value)
+        .value("end_value", portfolio.end_value)
+        .value("net_flows", portfolio.net_flows)
+        .value("time_weighted", portfolio.time_weighted)
+        .value("money_weighted", portfolio.money_weighted)
+        .table("assets", RETURNS_COLUMNS, returns_rows(&report.assets))
+        .table(
+            "categories",
+            RETURNS_COLUMNS,
+            returns_rows(&report.categories),
+        )
+}
+
+const RETURNS_COLUMNS: &[&str] = &[
+    "name",
+    "start",
+    "end",
+    "start_value",
+    "end_value",
+    "net_flows",
+    "time_weighted",
+    "money_weighted",
+];
+
+fn returns_rows(returns: &[Returns]) -> Vec<Vec<Field>> {
+    returns
+        .iter()
+        .map(|returns| {
+            vec![
+                returns.name.as_str().into(),
+                returns.start.into(),
+                returns.end.into(),
+                returns.start_value.into(),
+                returns.end_value.into(),
+                returns.net_flows.into(),
+                returns.time_weighted.into(),
+                returns.money_weighted.into(),
+            ]
+        })
+        .collect()
+}
+
+pub fn pnl_table(report: &PnlReport, group_by_category: bool) -> Table {
+    let mut table = new_table();
+    table.set_titles(row![
+        "Asset",
+        "Amount",
+        "Cost",
+        "Value",
+        "Unrealized",
+        "Realized",
+        "Return"
+    ]);
+    for asset in &report.assets {
+        let totals = asset.totals();
+        table.add_row(row![
+            asset.name,
+            r -> asset.amount,
+            r -> format_money(asset.cost),
+            r -> asset.market_value.map(format_money).unwrap_or_default(),
+            r -> asset.unrealized().map(format_money).unwrap_or_default(),
+            r -> format_money(totals.realized),
+            r -> format_rate(totals.total_return_rate()),
+        ]);
+    }
+    if group_by_category {
+        table.add_empty_row();
+        for (category, totals) in &report.categories {
+            add_totals_row(&mut table, category, totals);
+        }
+    }
+    table.add_empty_row();
+    add_totals_row(&mut table, "Sum", &report.total);
+    table
+}
+
+fn add_totals_row(table: &mut Table, name: &str, totals: &PnlTotals) {
+    table.add_row(row![
+        b -> name,
+        "",
+        r -> format_money(totals.cost),
+        r -> format_money(totals.market_value),
+        r -> format_money(totals.unrealized),
+        r -> format_money(totals.realized),
+        r -> format_rate(totals.total_return_rate()),
+    ]);
+}
+
+/// Realized gains per tax year
+pub fn tax_years_table(report: &PnlReport) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Tax Year", "Realized"]);
+    for (year, gain) in &report.realized {
+        table.add_row(row![year, r -> format_money(*gain)]);
+    }
+    table
+}
+
+pub fn pnl_report(report: &PnlReport) -> Report {
+    let asset_rows = report
+        .assets
+        .iter()
+        .map(|asset| {
+            let totals = asset.totals();
+            vec![
+                asset.name.as_str().into(),
+                asset.category.as_str().into(),
+                asset.currency.clone().into(),
+                asset.amount.into(),
+                asset.cost.into(),
+                asset.market_value.into(),
+                asset.unrealized().into(),
+                totals.realized.into(),
+                totals.total_return_rate().into(),
+            ]
+        })
+        .collect();
+    let category_rows = report
+        .categories
+        .iter()
+        .map(|(category, totals)| {
+            vec![
+                category.as_str().into(),
+                totals.cost.into(),
+                totals.market_value.into(),
+                totals.unrealized.into(),
+                totals.realized.into(),
+                totals.total_return_rate().into(),
+            ]
+        })
+        .collect();
+    let year_rows = report
+        .realized
+        .iter()
+        .map(|(year, gain)| vec![(*year).into(), (*gain).into()])
+        .collect();
+    Report::new()
+        .value("cost", report.total.cost)
+        .value("market_value", report.total.market_value)
+        .value("unrealized", report.total.unrealized)
+        .value("realized", report.total.realized)
+        .value("return", report.total.total_return_rate())
+        .table(
+            "assets",
+            &[
+                "name",
+                "category",
+                "currency",
+                "amount",
+                "cost",
+                "market_value",
+                "unrealized",
+                "realized",
+                "return",
+            ],
+            asset_rows,
+        )
+        .table(
+            "categories",
+            &[
+                "category",
+                "cost",
+                "market_value",
+                "unrealized",
+                "realized",
+                "return",
+            ],
+            category_rows,
+        )
+        .table("tax_years", &["year", "realized"], year_rows)
+}
+
+/// Lists the drift of every kind of target, headed by the kind
+pub fn drift_table(drifts: &[(TargetKind, Vec<Drift>)]) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Name", "Value", "Weight", "Target", "Drift", "Status"]);
+    for (kind, drifts) in drifts {
+        table.add_empty_row();
+        table.add_row(row!(bFy -> kind, "", "", "", "", ""));
+        for drift in drifts {
+            add_drift_row(&mut table, drift);
+        }
+    }
+    table
+}
+
+fn add_drift_row(table: &mut Table, drift: &Drift) {
+    let target = match &drift.target {
+        Some(target) => target,
+        None => {
+            table.add_row(row![
+                drift.name,
+                r -> format_money(drift.value),
+                r -> format_share(drift.weight, Decimal::ONE),
+                "", "", ""
+            ]);
+            return;
+        }
+    };
+    let difference = drift.drift().unwrap_or_default();
+    let band = format!(
+        "{} ±{}",
+        format_share(target.weight, Decimal::ONE),
+        format_share(target.tolerance, Decimal::ONE)
+    );
+    let status = if !drift.out_of_band() {
+        cell!(Fg -> "ok")
+    } else if difference.is_sign_positive() {
+        cell!(Fr -> "over")
+    } else {
+        cell!(Fr -> "under")
+    };
+    table.add_row(prettytable::Row::new(vec![
+        cell!(drift.name),
+        cell!(r -> format_money(drift.value)),
+        cell!(r -> format_share(drift.weight, Decimal::ONE)),
+        cell!(r -> band),
+        cell!(r -> format_share(difference, Decimal::ONE)),
+        status,
+    ]));
+}
+
+pub fn drift_report(drifts: &[(TargetKind, Vec<Drift>)]) -> Report {
+    let rows = drifts
+        .iter()
+        .flat_map(|(kind, drifts)| {
+            drifts.iter().map(move |drift| {
+                let target = drift.target.as_ref();
+                vec![
+                    kind.to_string().into(),
+                    drift.name.as_str().into(),
+                    drift.value.into(),
+                    drift.weight.into(),
+                    target.map(|target| target.weight).into(),
+                    target.map(|target| target.tolerance).into(),
+                    drift.drift().into(),
+                    drift.out_of_band().into(),
+                ]
+            })
+        })
+        .collect();
+    Report::new().table(
+        "drift",
+        &[
+            "kind",
+            "name",
+            "value",
+            "weight",
+            "target",
+            "tolerance",
+            "drift",
+            "out_of_band",
+        ],
+        rows,
+    )
+}
+
+pub fn rebalance_table(trades: &[Trade]) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Name", "Buy", "Sell"]);
+    for trade in trades {
+        if trade.amount.is_sign_negative() {
+            table.add_row(row![trade.name, "", r -> format_money(-trade.amount)]);
+        } else {
+            table.add_row(row![trade.name, r -> format_money(trade.amount), ""]);
+        }
+    }
+    table.add_empty_row();
+    table.add_row(row!(b -> "Net", br -> format_money(net_amount(trades)), ""));
+    table
+}
+
+pub fn rebalance_report(trades: &[Trade]) -> Report {
+    let rows = trades
+        .iter()
+        .map(|trade| vec![trade.name.as_str().into(), trade.amount.into()])
+        .collect();
+    Report::new()
+        .value("net", net_amount(trades))
+        .table("trades", &["name", "amount"], rows)
+}
+
+fn net_amount(trades: &[Trade]) -> Decimal {
+    trades.iter().map(|trade| trade.amount).sum()
+}
+
+/// Lists the owned shares by portfolio, each headed by the values of the portfolio
+pub fn portfolio_table(
+    ownership: Vec<Ownership>,
+    snapshots: &BTreeMap<String, AssetsSnapshot>,
+    combined: Option<&AssetsSnapshot>,
+) -> Table {
+    let mut table = new_table();
+    table.set_titles(row![
+        "Portfolio",
+        "Share",
+        "Gross Assets",
+        "Liabilities",
+        "Net Worth"
+    ]);
+    for (portfolio, owned) in &ownership.into_iter().group_by(|o| o.portfolio.clone()) {
+        table.add_empty_row();
+        match snapshots.get(&portfolio) {
+            Some(snapshot) => table.add_row(row![
+                bFy -> portfolio,
+                "",
+                r -> format_money(snapshot.gross_assets()),
+                r -> format_money(snapshot.liabilities()),
+                br -> format_money(snapshot.net_worth())
+            ]),
+            None => table.add_row(row![bFy -> portfolio, "", "", "", ""]),
+        };
+        for ownership in owned {
+            table.add_row(row![
+                format!("  {}", ownership.asset),
+                r -> format_share(ownership.share, Decimal::ONE),
+                "",
+                "",
+                ""
+            ]);
+        }
+    }
+
+    if let Some(combined) = combined {
+        table.add_empty_row();
+        table.add_row(row![
+            b -> "Combined",
+            "",
+            r -> format_money(combined.gross_assets()),
+            r -> format_money(combined.liabilities()),
+            br -> format_money(combined.net_worth())
+        ]);
+    }
+    table
+}
+
+pub fn portfolio_report(
+    portfolios: Vec<String>,
+    ownership: Vec<Ownership>,
+    snapshots: &BTreeMap<String, AssetsSnapshot>,
+    combined: Option<&AssetsSnapshot>,
+) -> Report {
+    let portfolio_rows = portfolios
+        .into_iter()
+        .map(|portfolio| {
+            let snapshot = snapshots.get(&portfolio);
+            vec![
+                portfolio.as_str().into(),
+                snapshot.map(|snapshot| snapshot.gross_assets()).into(),
+                snapshot.map(|snapshot| snapshot.liabilities()).into(),
+                snapshot.map(|snapshot| snapshot.net_worth()).into(),
+            ]
+        })
+        .collect();
+    let ownership_rows = ownership
+        .into_iter()
+        .map(|ownership| {
+            vec![
+                ownership.portfolio.into(),
+                ownership.asset.into(),
+                ownership.share.into(),
+            ]
+        })
+        .collect();
+    Report::new()
+        .value(
+            "gross_assets",
+            combined.map(|combined| combined.gross_assets()),
+        )
+        .value(
+            "liabilities",
+            combined.map(|combined| combined.liabilities()),
+        )
+        .value("net_worth", combined.map(|combined| combined.net_worth()))
+        .table(
+            "portfolios",
+            &["portfolio", "gross_assets", "liabilities", "net_worth"],
+            portfolio_rows,
+        )
+        .table(
+            "ownership",
+            &["portfolio", "asset", "share"],
+            ownership_rows,
+        )
+}
+
+pub fn rules_table(rules: &[AlertRule]) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Id", "Rule"]);
+    for rule in rules {
+        table.add_row(row![r -> rule.id, rule.condition]);
+    }
+    table
+}
+
+pub fn rules_report(rules: &[AlertRule]) -> Report {
+    let rows = rules
+        .iter()
+        .map(|rule| vec![rule.id.into(), rule.condition.to_string().into()])
+        .collect();
+    Report::new().table("rules", &["id", "rule"], rows)
+}
+
+pub fn alerts_table(alerts: &[Alert]) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Time", "Rule", "Alert"]);
+    for alert in alerts {
+        table.add_row(row![
+            alert.time.format("%Y-%m-%d %H:%M"),
+            alert.rule,
+            alert.message
+        ]);
+    }
+    table
+}
+
+pub fn alerts_report(alerts: &[Alert]) -> Report {
+    let rows = alerts
+        .iter()
+        .map(|alert| {
+            vec![
+                alert.id.into(),
+                alert.time.into(),
+                alert.rule.as_str().into(),
+                alert.message.as_str().into(),
+            ]
+        })
+        .collect();
+    Report::new().table("alerts", &["id", "time", "rule", "message"], rows)
+}
+
+pub fn tags_table(tags: &[Tag]) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Asset", "Key", "Value"]);
+    for tag in tags {
+        table.add_row(row![tag.asset, tag.key, tag.value]);
+    }
+    table
+}
+
+pub fn tags_report(tags: &[Tag]) -> Report {
+    let rows = tags
+        .iter()
+        .map(|tag| {
+            vec![
+                tag.asset.as_str().into(),
+                tag.key.as_str().into(),
+                tag.value.as_str().into(),
+            ]
+        })
+        .collect();
+    Report::new().table("tags", &["asset", "key", "value"], rows)
+}
+
+pub fn transactions_table(transactions: &[Transaction]) -> Table {
+    let mut table = new_table();
+    table.set_titles(row![
+        "Id", "Date", "Asset", "Kind", "Amount", "Price", "Total", "Fee", "Currency"
+    ]);
+    for tx in transactions {
+        table.add_row(row![
+            r -> tx.id,
+            tx.date,
+            tx.asset,
+            tx.kind,
+            r -> tx.amount,
+            r -> format_money(tx.price),
+            r -> format_money(tx.amount * tx.price),
+            r -> format_money(tx.fee),
+            tx.currency
+        ]);
+    }
+    table
+}
+
+pub fn transactions_report(transactions: &[Transaction]) -> Report {
+    let rows = transactions
+        .iter()
+        .map(|tx| {
+            vec![
+                tx.id.into(),
+                tx.date.into(),
+                tx.asset.as_str().into(),
+                tx.kind.to_string().into(),
+                tx.amount.into(),
+                tx.price.into(),
+                (tx.amount * tx.price).into(),
+                tx.fee.into(),
+                tx.currency.as_str().into(),
+            ]
+        })
+        .collect();
+    Report::new().table(
+        "transactions",
+        &[
+            "id", "date", "asset", "kind", "amount", "price", "total", "fee", "currency",
+        ],
+        rows,
+    )
+}
+
+pub fn basis_table(bases: &[(String, CostBasis)]) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Asset", "Amount", "Cost", "Avg Price", "Currency"]);
+    for (asset, basis) in bases {
+        table.add_row(row![
+            asset,
+            r -> basis.amount,
+            r -> format_money(basis.cost),
+            r -> basis.average_price().map(format_money).unwrap_or_default(),
+            basis.currency.clone().unwrap_or_default()
+        ]);
+    }
+    table
+}
+
+pub fn basis_report(bases: &[(String, CostBasis)]) -> Report {
+    let rows = bases
+        .iter()
+        .map(|(asset, basis)| {
+            vec![
+                asset.as_str().into(),
+                basis.amount.into(),
+                basis.cost.into(),
+                basis.average_price().into(),
+                basis.currency.clone().into(),
+            ]
+        })
+        .collect();
+    Report::new().table(
+        "basis",
+        &["asset", "amount", "cost", "average_price", "currency"],
+        rows,
+    )
+}
+
+pub fn problems_report(problems: &[Problem]) -> Report {
+    let rows = problems
+        .iter()
+        .map(|problem| vec![problem.to_string().into(), problem.is_repairable().into()])
+        .collect();
+    Report::new().table("problems", &["problem", "repairable"], rows)
+}
+
+pub fn sync_table(changes: &[SyncChange]) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Action", "Asset", "Change"]);
+    for change in changes {
+        match change {
+            SyncChange::Add(decl) => {
+                let liability = if decl.liability { ", liability" } else { "" };
+                table.add_row(row![Fg -> "add", decl.name, format!(
+                    "{}, {}, {}{}",
+                    decl.category, decl.price_query, decl.holdings_query, liability
+                )]);
+                for (key, value) in &decl.tags {
+                    table.add_row(row!["", "", format!("tag {}: {}", key, value)]);
+                }
+            }
+            SyncChange::Restore(asset) => {
+                table.add_row(row![Fg -> "restore", asset, ""]);
+            }
+            SyncChange::Update {
+                asset,
+                field,
+                before,
+                after,
+            } => {
+                let unset = || "(unset)".to_string();
+                table.add_row(row![Fy -> "change", asset, format!(
+                    "{}: {} -> {}",
+                    field,
+                    before.clone().unwrap_or_else(unset),
+                    after.clone().unwrap_or_else(unset)
+                )]);
+            }
+            SyncChange::Archive(asset) => {
+                table.add_row(row![Fr -> "archive", asset, ""]);
+            }
+        }
+    }
+    table
+}
+
+pub fn plugin_checks_table(checks: &[PluginCheck]) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Plugin", "Name", "Type", "Status", "Time"]);
+    for check in checks {
+        let path = check.path.display();
+        let time = format_duration(check.duration);
+        match &check.result {
+            Ok(info) => {
+                let plugin_type = format!("{:?}", info.plugin_type);
+                match &check.warning {
+                    Some(warning) => table.add_row(
+                        row![path, info.name, plugin_type, Fy -> format!("warning: {}", warning), r -> time],
+                    ),
+                    None => table.add_row(row![path, info.name, plugin_type, Fg -> "ok", r -> time]),
+                };
+            }
+            Err(e) => {
+                table.add_row(row![path, "", "", Fr -> e, r -> time]);
+            }
+        }
+    }
+    table
+}
+
+pub fn query_checks_table(checks: &[QueryCheck]) -> Table {
+    let mut table = new_table();
+    table.set_titles(row!["Asset", "Type", "Query", "Status", "Time"]);
+    for check in checks {
+        let query_type = format!("{:?}", check.query_type);
+        let time = format_duration(check.duration);
+        match &check.result {
+            Ok(value) => {
+                table.add_row(row![check.asset, query_type, check.query, Fg -> format!("ok: {}", value), r -> time]);
+            }
+            Err(e) => {
+                table.add_row(row![check.asset, query_type, check.query, Fr -> e, r -> time]);
+            }
+        }
+    }
+    table
+}
+
+fn new_table() -> Table {
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{} ms", duration.as_millis())
+}
+
+/// Formats a part of a total as percentage, an empty total has no meaningful shares
+fn format_share(part: Decimal, total: Decimal) -> String {
+    if total.is_zero() {
+        return "-".to_string();
+    }
+    let share = (part / total * Decimal::new(100, 0))
+        .round_dp_with_strategy(1, RoundingStrategy::MidpointAwayFromZero);
+    format!("{:.1}%", share)
+}
+
+fn format_rate(rate: Option<Decimal>) -> String {
+    match rate {
+        Some(rate) => format_share(rate, Decimal::ONE),
+        None => String::new(),
+    }
+}
+
+fn format_money(amount: Decimal) -> String {
+    let amount = amount.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero);
+    if amount.is_sign_negative() && !amount.is_zero() {
+        return format!("-{}", format_money(-amount));
+    }
+    let base = format!("{:.2}", amount.abs());
+    if base.len() > 6 {
+        let rev_chunked = base[..base.len() - 6]
+            .chars()
+            .rev()
+            .chunks(3)
+            .into_iter()
+            .flat_map(|chunk| once('\'').chain(chunk))
+            .collect::<Vec<_>>();
+        rev_chunked
+            .into_iter()
+            .rev()
+            .chain(base[base.len() - 6..].chars())
+            .collect()
+    } else {
+        base
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{history_report, list_report};
+    use crate::output::{Format, Report};
+    use assetman::{Asset, AssetsSnapshot};
+    use chrono::NaiveDate;
+
+    fn asset(name: &str, price: &str, holdings: &str, liability: bool) -> Asset {
+        Asset {
+            id: 0,
+            name: name.to_string(),
+            price: price.parse().unwrap(),
+            holdings: holdings.parse().unwrap(),
+            category: "cash".to_string(),
+            liability,
+        }
+    }
+
+    fn written(report: &Report, format: Format) -> String {
+        let mut out = Vec::new();
+        report.write(format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_list_report() {
+        let time = NaiveDate::from_ymd(2020, 1, 2).and_hms(3, 4, 5);
+        let assets = vec![
+            (
+                asset("Dimes", "0.10", "3", false),
+                vec![("owner".to_string(), "alice".to_string())],
+            ),
+            (asset("Loan", "1", "1000.50", true), Vec::new()),
+        ];
+        let report = list_report(time, assets, &["owner".to_string()]);
+
+        let json =
+            serde_json::from_str::<serde_json::Value>(&written(&report, Format::Json)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "time": "2020-01-02T03:04:05",
+                "assets": [
+                    {
+                        "time": "2020-01-02T03:04:05",
+                        "name": "Dimes",
+                        "category": "cash",
+                        "liability": false,
+                        "holdings": 3,
+                        "price": 0.1,
+                        "value": 0.3,
+                        "tag_owner": "alice"
+                    },
+                    {
+                        "time": "2020-01-02T03:04:05",
+                        "name": "Loan",
+                        "category": "cash",
+                        "liability": true,
+                        "holdings": 1000.5,
+                        "price": 1,
+                        "value": -1000.5,
+                        "tag_owner": null
+                    }
+                ]
+            })
+        );
+        // decimals are written with all of their digits instead of as floats
+        assert!(written(&report, Format::Json).contains("\"value\": 0.3,"));
+
+        assert_eq!(
+            written(&report, Format::Csv),
+            "time,name,category,liability,holdings,price,value,tag_owner\n\
+             2020-01-02T03:04:05,Dimes,cash,false,3,0.1,0.3,alice\n\
+             2020-01-02T03:04:05,Loan,cash,true,1000.5,1,-1000.5,\n"
+        );
+        assert_eq!(
+            written(&report, Format::Tsv).lines().next(),
+            Some("time\tname\tcategory\tliability\tholdings\tprice\tvalue\ttag_owner")
+        );
+    }
+
+    #[test]
+    fn test_history_report() {
+        let history = vec![AssetsSnapshot {
+            time: NaiveDate::from_ymd(2020, 1, 2).and_hms(0, 0, 0),
+            assets: vec![
+                asset("Dimes", "0.1", "3", false),
+                asset("Loan", "1", "0.2", true),
+            ],
+        }];
+
+        assert_eq!(
+            written(&history_report(&history), Format::Csv),
+            "time,gross_assets,liabilities,net_worth\n2020-01-02T00:00:00,0.3,-0.2,0.1\n"
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use crate::store::Update;
+use crate::{schema, Assets, Error};
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use diesel::prelude::*;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// How many regular updates to keep of a period
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    All,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// Which regular updates to keep depending on their age. Each tier keeps updates younger than
+/// its number of days at its resolution, older updates are kept at the `older` resolution.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetentionPolicy {
+    /// Ordered by age
+    pub tiers: Vec<(u32, Resolution)>,
+    pub older: Resolution,
+}
+
+/// Outcome of compacting the history
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Compaction {
+    /// Number of deleted updates
+    pub updates: usize,
+    /// Number of deleted prices and holdings, counted once per asset and update
+    pub values: usize,
+    /// Number of regular updates left
+    pub kept: usize,
+}
+
+impl Default for RetentionPolicy {
+    /// Everything for 30 days, daily for a year and weekly after that
+    fn default() -> Self {
+        RetentionPolicy {
+            tiers: vec![(30, Resolution::All), (365, Resolution::Daily)],
+            older: Resolution::Weekly,
+        }
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "all" => Ok(Resolution::All),
+            "hourly" => Ok(Resolution::Hourly),
+            "daily" => Ok(Resolution::Daily),
+            "weekly" => Ok(Resolution::Weekly),
+            "monthly" => Ok(Resolution::Monthly),
+            other => Err(format!(
+                "'{}' isn't all, hourly, daily, weekly or monthly",
+                other
+            )),
+        }
+    }
+}
+
+/// Parses a comma separated list of `days=resolution` tiers followed by the resolution of older
+/// updates, e.g. `30=all,365=daily,weekly`
+impl FromStr for RetentionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tiers = Vec::new();
+        let mut older = None;
+        for entry in s.split(',').map(str::trim) {
+            if older.is_some() {
+                return Err("the resolution of older updates has to come last".to_string());
+            }
+            match entry.find('=') {
+                Some(idx) => {
+                    let days = entry[..idx]
+                        .trim()
+                        .parse::<u32>()
+                        .map_err(|_| format!("'{}' is not a number of days", &entry[..idx]))?;
+                    if matches!(tiers.last(), Some((last, _)) if *last >= days) {
+                        return Err("tiers have to be ordered by age".to_string());
+                    }
+                    tiers.push((days, entry[idx + 1..].parse()?));
+                }
+                None => older = Some(entry.parse()?),
+            }
+        }
+        Ok(RetentionPolicy {
+            tiers,
+            older: older.ok_or("the resolution of older updates is missing")?,
+        })
+    }
+}
+
+impl Resolution {
+    /// Period an update falls into, `None` if every update is kept
+    fn period(self, time: NaiveDateTime) -> Option<(i32, u32, u32)> {
+        match self {
+            Resolution::All => None,
+            Resolution::Hourly => Some((time.year(), time.ordinal(), time.hour())),
+            Resolution::Daily => Some((time.year(), time.ordinal(), 0)),
+            Resolution::Weekly => {
+                let week = time.iso_week();
+                Some((week.year(), week.week(), 0))
+            }
+            Resolution::Monthly => Some((time.year(), time.month(), 0)),
+        }
+    }
+}
+
+impl RetentionPolicy {
+    fn resolution(&self, age_days: i64) -> Resolution {
+        self.tiers
+            .iter()
+            .find(|(days, _)| age_days < i64::from(*days))
+            .map_or(self.older, |(_, resolution)| *resolution)
+    }
+
+    /// Maps each regular update that isn't kept at `now` to the update kept for its period,
+    /// which is the latest one of that period. This always keeps the latest update and the first
+    /// one is kept as well, so the whole history still starts and ends at the same values.
+    /// `updates` have to be ordered by time.
+    fn expired(&self, updates: &[Update], now: NaiveDateTime) -> Vec<(i32, i32)> {
+        let mut periods = BTreeMap::<_, Vec<i32>>::new();
+        for (idx, update) in updates.iter().enumerate() {
+            if update.synthetic || idx == 0 {
+                continue;
+            }
+            let resolution = self.resolution(now.signed_duration_since(update.time).num_days());
+            if let Some(period) = resolution.period(update.time) {
+                periods
+                    .entry((resolution as u8, period))
+                    .or_default()
+                    .push(update.id);
+            }
+        }
+
+        let mut expired = Vec::new();
+        for ids in periods.values() {
+            let (kept, older) = ids.split_last().expect("periods aren't empty");
+            expired.extend(older.iter().map(|id| (*id, *kept)));
+        }
+        expired.sort_unstable();
+        expired
+    }
+}
+
+impl Assets {
+    /// Compacts the history after every fetch, `None` keeps all updates
+    pub fn set_retention(&mut self, policy: Option<RetentionPolicy>) {
+        self.retention = policy;
+    }
+
+    pub fn retention(&self) -> Option<&RetentionPolicy> {
+        self.retention.as_ref()
+    }
+
+    /// Deletes the regular updates that `policy` doesn't keep together with their prices and
+    /// holdings. Alerts of deleted updates are moved to the update kept for their period.
+    /// Synthetic updates are left alone. With `dry_run` only the outcome is computed.
+    pub fn compact(
+        &mut self,
+        policy: &RetentionPolicy,
+        dry_run: bool,
+    ) -> Result<Compaction, Error> {
+        let now = chrono::Utc::now().naive_utc();
+        self.compact_at(policy, now, dry_run)
+    }
+
+    fn compact_at(
+        &mut self,
+        policy: &RetentionPolicy,
+        now: NaiveDateTime,
+        dry_run: bool,
+    ) -> Result<Compaction, Error> {
+        let db_client = self.db()?;
+        let mut updates = self.store().updates()?;
+        updates.retain(|update| !update.synthetic);
+        let expired = policy.expired(&updates, now);
+        let mut compaction = Compaction {
+            updates: expired.len(),
+            values: 0,
+            kept: updates.len() - expired.len(),
+        };
+        if dry_run {
+            for (update_id, _) in &expired {
+                compaction.values += with_db!(db_client, |db| schema::prices::table
+                    .filter(schema::prices::update_id.eq(update_id))
+                    .count()
+                    .get_result::<i64>(db))? as usize;
+            }
+            return Ok(compaction);
+        }
+
+        compaction.values = with_db!(db_client, |db| db.transaction(|| {
+            let mut values = 0;
+            for (update_id, kept) in &expired {
+                diesel::update(
+                    schema::alerts::table.filter(schema::alerts::update_id.eq(update_id)),
+                )
+                .set(schema::alerts::update_id.eq(kept))
+                .execute(db)?;
+                values += diesel::delete(
+                    schema::prices::table.filter(schema::prices::update_id.eq(update_id)),
+                )
+                .execute(db)?;
+                diesel::delete(
+                    schema::holdings::table.filter(schema::holdings::update_id.eq(update_id)),
+                )
+                .execute(db)?;
+                diesel::delete(schema::updates::table.find(update_id)).execute(db)?;
+            }
+            Ok::<_, Error>(values)
+        }))?;
+        Ok(compaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Resolution, RetentionPolicy};
+    use crate::store::Update;
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(
+            "30=all,365=daily,weekly".parse::<RetentionPolicy>(),
+            Ok(RetentionPolicy::default())
+        );
+        assert_eq!(
+            " 7 = hourly , monthly".parse::<RetentionPolicy>(),
+            Ok(RetentionPolicy {
+                tiers: vec![(7, Resolution::Hourly)],
+                older: Resolution::Monthly,
+            })
+        );
+        assert!("30=all".parse::<RetentionPolicy>().is_err());
+        assert!("weekly,30=all".parse::<RetentionPolicy>().is_err());
+        assert!("365=daily,30=all,weekly"
+            .parse::<RetentionPolicy>()
+            .is_err());
+        assert!("30=yearly,weekly".parse::<RetentionPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_expired() {
+        let now = NaiveDate::from_ymd(2021, 6, 30).and_hms(12, 0, 0);
+        let update = |id, time: NaiveDateTime| Update {
+            id,
+            time,
+            synthetic: false,
+        };
+        let day = |month, day, hour| NaiveDate::from_ymd(2021, month, day).and_hms(hour, 0, 0);
+        let updates = vec![
+            // the first update is kept although its week has later ones
+            update(1, day(1, 4, 8)),
+            // Monday, Wednesday and Friday of the same week
+            update(2, day(1, 4, 20)),
+            update(3, day(1, 6, 8)),
+            update(4, day(1, 8, 8)),
+            update(5, day(1, 8, 20)),
+            // younger than 30 days, all are kept
+            update(6, day(6, 20, 8)),
+            update(7, day(6, 20, 20)),
+        ];
+        let policy = RetentionPolicy {
+            tiers: vec![(30, Resolution::All), (100, Resolution::Daily)],
+            older: Resolution::Weekly,
+        };
+        assert_eq!(policy.expired(&updates, now), vec![(2, 5), (3, 5), (4, 5)]);
+
+        let daily = RetentionPolicy {
+            tiers: Vec::new(),
+            older: Resolution::Daily,
+        };
+        // the latest update is the one kept of its day
+        assert_eq!(daily.expired(&updates, now), vec![(4, 5), (6, 7)]);
+    }
+}
@@ -0,0 +1,376 @@
+use crate::decimal::DbDecimal;
+use crate::transactions::TransactionKind;
+use crate::{schema, Asset, Assets, Error};
+use assetman_api::{from_float, Decimal};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use rust_decimal::prelude::ToPrimitive;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Value of an asset or a group of assets at a certain time
+pub type Valuation = (NaiveDateTime, Decimal);
+
+/// Money moved into (positive) or out of (negative) an asset or a group of assets
+pub type CashFlow = (NaiveDateTime, Decimal);
+
+/// Performance of an asset, a category or the whole portfolio over a range of updates
+#[derive(Debug, Clone, PartialEq)]
+pub struct Returns {
+    pub name: String,
+    /// Time of the first update of the range, `None` if there is no update in the range
+    pub start: Option<NaiveDateTime>,
+    pub end: Option<NaiveDateTime>,
+    pub start_value: Decimal,
+    pub end_value: Decimal,
+    /// Sum of money moved in by buys and deposits minus money moved out by sells and withdrawals
+    pub net_flows: Decimal,
+    /// Return of the market independent of cash flows, not annualized
+    pub time_weighted: Option<Decimal>,
+    /// Annualized internal rate of return (XIRR) considering the timing of cash flows
+    pub money_weighted: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReturnsReport {
+    pub assets: Vec<Returns>,
+    pub categories: Vec<Returns>,
+    pub portfolio: Returns,
+}
+
+/// An asset at an update
+struct ValuationRow {
+    timestamp: NaiveDateTime,
+    synthetic: bool,
+    asset: Asset,
+}
+
+impl Assets {
+    /// Computes time- and money-weighted returns of every asset, category and the portfolio
+    /// between the latest update at or before `since` and the latest update at or before `until`.
+    /// Cash flows are taken from the recorded transactions, which have to be in the base currency
+    /// like the prices. Synthetic updates only cover single assets, so they are used for asset
+    /// returns but not for categories and the portfolio.
+    pub fn returns(
+        &self,
+        since: Option<NaiveDateTime>,
+        until: Option<NaiveDateTime>,
+    ) -> Result<ReturnsReport, Error> {
+        let rows = with_db!(self.db()?, |db| schema::updates::table
+            .inner_join(schema::prices::table)
+            .inner_join(
+                schema::holdings::table.on(schema::holdings::update_id
+                    .eq(schema::prices::update_id)
+                    .and(schema::holdings::asset_id.eq(schema::prices::asset_id))),
+            )
+            .inner_join(schema::assets::table.on(schema::assets::id.eq(schema::prices::asset_id)))
+            .select((
+                schema::updates::timestamp,
+                schema::updates::synthetic,
+                schema::assets::id,
+                schema::assets::name,
+                schema::assets::category,
+                schema::assets::liability,
+                schema::prices::price,
+                schema::holdings::amount,
+            ))
+            .order_by(schema::updates::timestamp)
+            .load::<(
+                NaiveDateTime,
+                bool,
+                i32,
+                String,
+                String,
+                bool,
+                DbDecimal,
+                DbDecimal,
+            )>(db))?;
+        let shares = self.owned_shares()?;
+        let rows = rows
+            .into_iter()
+            .filter_map(
+                |(timestamp, synthetic, id, name, category, liability, price, holdings)| {
+                    let share = match &shares {
+                        Some(shares) => *shares.get(&name)?,
+                        None => Decimal::ONE,
+                    };
+                    Some(ValuationRow {
+                        timestamp,
+                        synthetic,
+                        asset: Asset {
+                            id,
+                            name,
+                            price: price.0,
+                            holdings: holdings.0 * share,
+                            category,
+                            liability,
+                        },
+                    })
+                },
+            )
+            .collect::<Vec<_>>();
+        let transactions = self.list_transactions(None)?;
+        self.transactions_currency(&transactions)?;
+        let asset_categories = with_db!(self.db()?, |db| schema::assets::table
+            .select((schema::assets::name, schema::assets::category))
+            .load::<(String, String)>(db))?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        let returns =
+            |name: &str, include: &dyn Fn(&str, &str) -> bool, with_synthetic: bool| -> Returns {
+                let mut valuations = BTreeMap::<NaiveDateTime, Decimal>::new();
+                for row in &rows {
+                    if include(&row.asset.name, &row.asset.category)
+                        && (with_synthetic || !row.synthetic)
+                    {
+                        *valuations.entry(row.timestamp).or_insert(Decimal::ZERO) +=
+                            row.asset.value();
+                    }
+                }
+                let flows = transactions
+                    .iter()
+                    .filter(|tx| include(&tx.asset, &asset_categories[&tx.asset]))
+                    .filter_map(|tx| {
+                        let value = tx.amount * tx.price;
+                        // cash fees are paid in addition to buying and from the proceeds of selling
+                        let flow = match tx.kind {
+                            TransactionKind::Buy | TransactionKind::Deposit => value + tx.fee,
+                            TransactionKind::Sell | TransactionKind::Withdrawal => tx.fee - value,
+                            // units paid as fee are a loss of the asset's value, not money moved
+                            // elsewhere
+                            TransactionKind::Fee if tx.fee.is_zero() => return None,
+                            TransactionKind::Fee => tx.fee,
+                        };
+                        Some((tx.date.and_hms(0, 0, 0), flow))
+                    })
+                    .collect::<Vec<_>>();
+
+                Returns::compute(
+                    name,
+                    &valuations.into_iter().collect::<Vec<_>>(),
+                    &flows,
+                    since,
+                    until,
+                )
+            };
+
+        let asset_names = rows
+            .iter()
+            .map(|row| row.asset.name.as_str())
+            .collect::<BTreeSet<_>>();
+        let categories = rows
+            .iter()
+            .map(|row| row.asset.category.as_str())
+            .collect::<BTreeSet<_>>();
+
+        Ok(ReturnsReport {
+            assets: asset_names
+                .into_iter()
+                .map(|asset| returns(asset, &|name, _| name == asset, true))
+                .collect(),
+            categories: categories
+                .into_iter()
+                .map(|category| returns(category, &|_, cat| cat == category, false))
+                .collect(),
+            portfolio: returns("Portfolio", &|_, _| true, false),
+        })
+    }
+}
+
+impl Returns {
+    /// Computes the returns from valuations ordered by time and cash flows in any order. The range
+    /// is extended to the latest valuation at or before `since` (or the first one if there is
+    /// none) and shortened to the latest valuation at or before `until`.
+    pub fn compute(
+        name: &str,
+        valuations: &[Valuation],
+        flows: &[CashFlow],
+        since: Option<NaiveDateTime>,
+        until: Option<NaiveDateTime>,
+    ) -> Returns {
+        let in_range = |time: &NaiveDateTime| match until {
+            Some(until) => *time <= until,
+            None => true,
+        };
+        let start_idx = since
+            .and_then(|since| valuations.iter().rposition(|(time, _)| *time <= since))
+            .unwrap_or(0);
+        let valuations = valuations
+            .iter()
+            .skip(start_idx)
+            .filter(|(time, _)| in_range(time))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let (start, end) = match (valuations.first(), valuations.last()) {
+            (Some(start), Some(end)) => (*start, *end),
+            _ => {
+                return Returns {
+                    name: name.to_string(),
+                    start: None,
+                    end: None,
+                    start_value: Decimal::ZERO,
+                    end_value: Decimal::ZERO,
+                    net_flows: Decimal::ZERO,
+                    time_weighted: None,
+                    money_weighted: None,
+                }
+            }
+        };
+        let flows = flows
+            .iter()
+            .filter(|(time, _)| *time > start.0 && *time <= end.0)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // from the investor's point of view the starting value is paid in and the end value paid
+        // out, just like buys are paid in and sells paid out
+        let mut cash_flows = vec![(start.0, -start.1)];
+        cash_flows.extend(flows.iter().map(|(time, flow)| (*time, -*flow)));
+        cash_flows.push((end.0, end.1));
+
+        Returns {
+            name: name.to_string(),
+            start: Some(start.0),
+            end: Some(end.0),
+            start_value: start.1,
+            end_value: end.1,
+            net_flows: flows.iter().map(|(_, flow)| *flow).sum(),
+            time_weighted: time_weighted_return(&valuations, &flows),
+            money_weighted: xirr(&cash_flows),
+        }
+    }
+}
+
+/// Chains the returns of the periods between consecutive valuations, removing the cash flows of
+/// each period from its end value. Periods starting at a value of zero are skipped, `None` is
+/// returned if there is no period left.
+pub fn time_weighted_return(valuations: &[Valuation], flows: &[CashFlow]) -> Option<Decimal> {
+    let mut growth = None::<Decimal>;
+    for period in valuations.windows(2) {
+        let ((start, start_value), (end, end_value)) = (period[0], period[1]);
+        if start_value.is_zero() {
+            continue;
+        }
+        let period_flows: Decimal = flows
+            .iter()
+            .filter(|(time, _)| *time > start && *time <= end)
+            .map(|(_, flow)| *flow)
+            .sum();
+        let period_growth = (end_value - period_flows) / start_value;
+        growth = Some(growth.unwrap_or(Decimal::ONE) * period_growth);
+    }
+    growth.map(|growth| growth - Decimal::ONE)
+}
+
+/// Annualized rate at which the net present value of the cash flows is zero, using actual days
+/// over 365. Payments are negative, receipts positive. Returns `None` if there aren't both or
+/// no rate between -100% and 1'000'000'000% fits.
+pub fn xirr(cash_flows: &[CashFlow]) -> Option<Decimal> {
+    let first = cash_flows.iter().map(|(time, _)| *time).min()?;
+    let flows = cash_flows
+        .iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(time, amount)| {
+            let years = (*time - first).num_seconds() as f64 / (365.0 * 86400.0);
+            Some((years, amount.to_f64()?))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if !flows.iter().any(|(_, amount)| *amount < 0.0)
+        || !flows.iter().any(|(_, amount)| *amount > 0.0)
+    {
+        return None;
+    }
+
+    let npv = |rate: f64| -> f64 {
+        flows
+            .iter()
+            .map(|(years, amount)| amount / (1.0 + rate).powf(*years))
+            .sum()
+    };
+
+    // the net present value falls with rising rates if payments come before receipts, but that
+    // isn't guaranteed, so bisect between two rates with differently signed values
+    let mut low = -0.999_999;
+    let mut high = 1.0;
+    while npv(low).signum() == npv(high).signum() {
+        high *= 10.0;
+        if high > 1e7 {
+            return None;
+        }
+    }
+    for _ in 0..200 {
+        let mid = (low + high) / 2.0;
+        if npv(mid).signum() == npv(low).signum() {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    from_float((low + high) / 2.0).map(|rate| rate.round_dp(6))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::returns::{time_weighted_return, xirr, Returns};
+    use assetman_api::Decimal;
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(year, month, day).and_hms(0, 0, 0)
+    }
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_time_weighted_return() {
+        // +10% in the first period, then 100 deposited and -10% in the second one
+        let valuations = vec![
+            (day(2020, 1, 1), dec("100")),
+            (day(2020, 2, 1), dec("110")),
+            (day(2020, 3, 1), dec("199")),
+        ];
+        let flows = vec![(day(2020, 2, 15), dec("100"))];
+        assert_eq!(
+            time_weighted_return(&valuations, &flows),
+            Some(dec("-0.01"))
+        );
+        assert_eq!(
+            time_weighted_return(&valuations[..2], &flows),
+            Some(dec("0.1"))
+        );
+        assert_eq!(time_weighted_return(&valuations[..1], &flows), None);
+    }
+
+    #[test]
+    fn test_xirr() {
+        // 1000 growing to 1100 in exactly one year
+        let flows = vec![
+            (day(2019, 1, 1), dec("-1000")),
+            (day(2020, 1, 1), dec("1100")),
+        ];
+        assert_eq!(xirr(&flows), Some(dec("0.1")));
+        assert_eq!(xirr(&flows[..1]), None);
+
+        // 1000 more deposited after a year, both growing by 10% a year
+        let returns = Returns::compute(
+            "test",
+            &[
+                (day(2019, 1, 1), dec("1000")),
+                (day(2021, 1, 1), dec("2310")),
+            ],
+            &[(day(2020, 1, 1), dec("1000"))],
+            None,
+            None,
+        );
+        assert_eq!(returns.net_flows, dec("1000"));
+        assert_eq!(returns.time_weighted, Some(dec("0.31")));
+        assert_eq!(
+            returns.money_weighted.map(|rate| rate.round_dp(2)),
+            Some(dec("0.1"))
+        );
+    }
+}
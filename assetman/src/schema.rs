@@ -5,6 +5,8 @@ table! {
         price_query -> Text,
         holdings_query -> Text,
         category -> Text,
+        liability -> Bool,
+        archived -> Bool,
     }
 }
 
@@ -12,6 +14,7 @@ table! {
     updates {
         id -> Integer,
         timestamp -> Timestamp,
+        synthetic -> Bool,
     }
 }
 
@@ -19,7 +22,7 @@ table! {
     holdings (update_id, asset_id) {
         update_id -> Integer,
         asset_id -> Integer,
-        amount -> Double,
+        amount -> Text,
     }
 }
 
@@ -27,7 +30,73 @@ table! {
     prices (update_id, asset_id) {
         update_id -> Integer,
         asset_id -> Integer,
-        price -> Double,
+        price -> Text,
+    }
+}
+
+table! {
+    plugin_cache (plugin, plugin_type, arguments) {
+        plugin -> Text,
+        plugin_type -> Text,
+        arguments -> Text,
+        value -> Text,
+        fetched_at -> Timestamp,
+    }
+}
+
+table! {
+    transactions {
+        id -> Integer,
+        asset_id -> Integer,
+        kind -> Text,
+        date -> Date,
+        amount -> Text,
+        price -> Text,
+        currency -> Text,
+        fee -> Text,
+    }
+}
+
+table! {
+    targets (kind, name) {
+        kind -> Text,
+        name -> Text,
+        weight -> Text,
+        tolerance -> Text,
+    }
+}
+
+table! {
+    tags (asset_id, key) {
+        asset_id -> Integer,
+        key -> Text,
+        value -> Text,
+    }
+}
+
+table! {
+    ownership (asset_id, portfolio) {
+        asset_id -> Integer,
+        portfolio -> Text,
+        share -> Text,
+    }
+}
+
+table! {
+    alert_rules {
+        id -> Integer,
+        kind -> Text,
+        subject -> Nullable<Text>,
+        threshold -> Nullable<Text>,
+    }
+}
+
+table! {
+    alerts {
+        id -> Integer,
+        update_id -> Integer,
+        rule -> Text,
+        message -> Text,
     }
 }
 
@@ -35,4 +104,17 @@ joinable!(prices -> updates (update_id));
 joinable!(prices -> assets (asset_id));
 joinable!(holdings -> updates (update_id));
 joinable!(holdings -> assets (asset_id));
-allow_tables_to_appear_in_same_query!(assets, prices, holdings);
+joinable!(transactions -> assets (asset_id));
+joinable!(tags -> assets (asset_id));
+joinable!(ownership -> assets (asset_id));
+joinable!(alerts -> updates (update_id));
+allow_tables_to_appear_in_same_query!(
+    assets,
+    updates,
+    prices,
+    holdings,
+    transactions,
+    tags,
+    ownership,
+    alerts
+);
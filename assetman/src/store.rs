@@ -0,0 +1,254 @@
+use crate::decimal::DbDecimal;
+use crate::schema::assets;
+use crate::Error;
+use assetman_api::Decimal;
+use chrono::NaiveDateTime;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+/// Keeps assets and their prices and holdings at each update, the data everything else is derived
+/// from. `Assets` keeps them in its database unless `Assets::with_store` is given another store.
+/// Methods take `&self` like a database connection does, so stores that keep their state in
+/// memory need interior mutability.
+pub trait Store {
+    /// All assets including archived ones, ordered by name
+    fn assets(&self) -> Result<Vec<StoredAsset>, Error>;
+
+    fn asset(&self, name: &str) -> Result<Option<StoredAsset>, Error>;
+
+    /// Adds an asset and returns its id
+    fn add_asset(&self, asset: &NewAsset) -> Result<i32, Error>;
+
+    /// Overwrites everything but the id of the asset with the id of `asset`
+    fn update_asset(&self, asset: &StoredAsset) -> Result<(), Error>;
+
+    /// Adds an update, at the current time if no `time` is given, and returns its id
+    fn add_update(&self, time: Option<NaiveDateTime>, synthetic: bool) -> Result<i32, Error>;
+
+    /// All updates ordered by time, updates sharing a time by id
+    fn updates(&self) -> Result<Vec<Update>, Error>;
+
+    /// Stores price and holdings of an asset at an update, there is at most one of each
+    fn add_value(&self, value: &StoredValue) -> Result<(), Error>;
+
+    /// Prices and holdings at one update or at all updates, ordered by update and asset id
+    fn values(&self, update_id: Option<i32>) -> Result<Vec<StoredValue>, Error>;
+
+    /// Prices and holdings of one asset at all updates, ordered by update id
+    fn asset_values(&self, asset_id: i32) -> Result<Vec<StoredValue>, Error>;
+
+    /// Runs `f` so that either all of its changes are stored or, if it fails, none
+    fn atomically(&self, f: &mut dyn FnMut() -> Result<(), Error>) -> Result<(), Error>;
+}
+
+/// An asset as stored, including its queries and whether it's archived
+#[derive(Debug, Clone, PartialEq, Queryable)]
+pub struct StoredAsset {
+    pub id: i32,
+    pub name: String,
+    pub price_query: String,
+    pub holdings_query: String,
+    pub category: String,
+    pub liability: bool,
+    /// Archived assets are kept with their history but not fetched anymore
+    pub archived: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Insertable)]
+#[table_name = "assets"]
+pub struct NewAsset<'a> {
+    pub name: &'a str,
+    pub price_query: &'a str,
+    pub holdings_query: &'a str,
+    pub category: &'a str,
+    pub liability: bool,
+    pub archived: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Queryable)]
+pub struct Update {
+    pub id: i32,
+    pub time: NaiveDateTime,
+    /// Synthetic updates are backfilled from price histories instead of fetched
+    pub synthetic: bool,
+}
+
+/// Price and holdings of an asset at an update
+#[derive(Debug, Clone, Copy, PartialEq, Queryable)]
+pub struct StoredValue {
+    pub update_id: i32,
+    pub asset_id: i32,
+    #[diesel(deserialize_as = "DbDecimal")]
+    pub price: Decimal,
+    #[diesel(deserialize_as = "DbDecimal")]
+    pub holdings: Decimal,
+}
+
+/// Keeps everything in memory, e.g. for tests or to evaluate queries without touching a database
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    data: RefCell<MemoryData>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct MemoryData {
+    assets: Vec<StoredAsset>,
+    updates: Vec<Update>,
+    values: BTreeMap<(i32, i32), StoredValue>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl Store for MemoryStore {
+    fn assets(&self) -> Result<Vec<StoredAsset>, Error> {
+        let mut assets = self.data.borrow().assets.clone();
+        assets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(assets)
+    }
+
+    fn asset(&self, name: &str) -> Result<Option<StoredAsset>, Error> {
+        Ok(self
+            .data
+            .borrow()
+            .assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .cloned())
+    }
+
+    fn add_asset(&self, asset: &NewAsset) -> Result<i32, Error> {
+        let mut data = self.data.borrow_mut();
+        let id = data.assets.iter().map(|asset| asset.id).max().unwrap_or(0) + 1;
+        data.assets.push(StoredAsset {
+            id,
+            name: asset.name.to_string(),
+            price_query: asset.price_query.to_string(),
+            holdings_query: asset.holdings_query.to_string(),
+            category: asset.category.to_string(),
+            liability: asset.liability,
+            archived: asset.archived,
+        });
+        Ok(id)
+    }
+
+    fn update_asset(&self, asset: &StoredAsset) -> Result<(), Error> {
+        let mut data = self.data.borrow_mut();
+        match data.assets.iter_mut().find(|stored| stored.id == asset.id) {
+            Some(stored) => *stored = asset.clone(),
+            None => return Err(Error::AssetNotFound(asset.name.clone())),
+        }
+        Ok(())
+    }
+
+    fn add_update(&self, time: Option<NaiveDateTime>, synthetic: bool) -> Result<i32, Error> {
+        let mut data = self.data.borrow_mut();
+        let id = data
+            .updates
+            .iter()
+            .map(|update| update.id)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        // the database only keeps seconds too
+        let now = || NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+        data.updates.push(Update {
+            id,
+            time: time.unwrap_or_else(now),
+            synthetic,
+        });
+        Ok(id)
+    }
+
+    fn updates(&self) -> Result<Vec<Update>, Error> {
+        let mut updates = self.data.borrow().updates.clone();
+        updates.sort_by_key(|update| (update.time, update.id));
+        Ok(updates)
+    }
+
+    fn add_value(&self, value: &StoredValue) -> Result<(), Error> {
+        self.data
+            .borrow_mut()
+            .values
+            .insert((value.update_id, value.asset_id), *value);
+        Ok(())
+    }
+
+    fn values(&self, update_id: Option<i32>) -> Result<Vec<StoredValue>, Error> {
+        Ok(self
+            .data
+            .borrow()
+            .values
+            .values()
+            .filter(|value| match update_id {
+                Some(id) => value.update_id == id,
+                None => true,
+            })
+            .copied()
+            .collect())
+    }
+
+    fn asset_values(&self, asset_id: i32) -> Result<Vec<StoredValue>, Error> {
+        Ok(self
+            .data
+            .borrow()
+            .values
+            .values()
+            .filter(|value| value.asset_id == asset_id)
+            .copied()
+            .collect())
+    }
+
+    fn atomically(&self, f: &mut dyn FnMut() -> Result<(), Error>) -> Result<(), Error> {
+        let before = self.data.borrow().clone();
+        let result = f();
+        if result.is_err() {
+            *self.data.borrow_mut() = before;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryStore, NewAsset, Store, StoredValue};
+    use crate::Error;
+    use assetman_api::Decimal;
+
+    #[test]
+    fn test_memory_store_rollback() {
+        let store = MemoryStore::new();
+        let asset = NewAsset {
+            name: "Gold",
+            price_query: "static(1800)",
+            holdings_query: "static(2)",
+            category: "metals",
+            liability: false,
+            archived: false,
+        };
+        let asset_id = store.add_asset(&asset).unwrap();
+
+        let result = store.atomically(&mut || {
+            let update_id = store.add_update(None, false)?;
+            store.add_value(&StoredValue {
+                update_id,
+                asset_id,
+                price: Decimal::ONE,
+                holdings: Decimal::ONE,
+            })?;
+            Err(Error::NoData)
+        });
+        assert!(result.is_err());
+        assert!(store.updates().unwrap().is_empty());
+        assert!(store.values(None).unwrap().is_empty());
+        assert_eq!(store.assets().unwrap().len(), 1);
+
+        store
+            .atomically(&mut || store.add_update(None, true).map(|_| ()))
+            .unwrap();
+        assert_eq!(store.updates().unwrap().len(), 1);
+    }
+}
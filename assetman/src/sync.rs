@@ -0,0 +1,348 @@
+use crate::store::{NewAsset, StoredAsset};
+use crate::tags::CATEGORY_KEY;
+use crate::{cache, holdings_type, schema, Assets, Error};
+use assetman_api::PluginType::Price;
+use diesel::prelude::*;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// Asset as declared in the `[[assets]]` section of the config file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AssetDecl {
+    pub name: String,
+    pub category: String,
+    #[serde(rename = "price")]
+    pub price_query: String,
+    #[serde(rename = "holdings")]
+    pub holdings_query: String,
+    #[serde(default)]
+    pub liability: bool,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Difference between the declared assets and the database
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncChange {
+    /// A declared asset doesn't exist yet
+    Add(AssetDecl),
+    /// An archived asset is declared again
+    Restore(String),
+    /// A field of an existing asset differs from its declaration, `None` means unset
+    Update {
+        asset: String,
+        field: AssetField,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    /// An asset isn't declared anymore. Archived assets keep their history but aren't fetched.
+    Archive(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetField {
+    Category,
+    PriceQuery,
+    HoldingsQuery,
+    Liability,
+    Tag(String),
+}
+
+impl Assets {
+    /// Compares the declared assets to the database. Changes are ordered by asset name, archives
+    /// come last.
+    pub fn plan_sync(&self, declared: &[AssetDecl]) -> Result<Vec<SyncChange>, Error> {
+        check_declarations(declared)?;
+
+        let existing = with_db!(self.db()?, |db| schema::assets::table
+            .order_by(schema::assets::name)
+            .load::<StoredAsset>(db))?
+        .into_iter()
+        .map(|asset| (asset.name.clone(), asset))
+        .collect::<HashMap<_, _>>();
+        let mut tags = HashMap::<String, BTreeMap<String, String>>::new();
+        for tag in self.list_tags(None)? {
+            tags.entry(tag.asset)
+                .or_default()
+                .insert(tag.key, tag.value);
+        }
+
+        let mut declared = declared.iter().collect::<Vec<_>>();
+        declared.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut changes = Vec::new();
+        for decl in &declared {
+            let asset = match existing.get(&decl.name) {
+                Some(asset) => asset,
+                None => {
+                    changes.push(SyncChange::Add((*decl).clone()));
+                    continue;
+                }
+            };
+            if asset.archived {
+                changes.push(SyncChange::Restore(decl.name.clone()));
+            }
+
+            let mut update = |field, before: String, after: String| {
+                if before != after {
+                    changes.push(SyncChange::Update {
+                        asset: decl.name.clone(),
+                        field,
+                        before: Some(before),
+                        after: Some(after),
+                    });
+                }
+            };
+            update(
+                AssetField::Category,
+                asset.category.clone(),
+                decl.category.clone(),
+            );
+            update(
+                AssetField::PriceQuery,
+                asset.price_query.clone(),
+                decl.price_query.clone(),
+            );
+            update(
+                AssetField::HoldingsQuery,
+                asset.holdings_query.clone(),
+                decl.holdings_query.clone(),
+            );
+            update(
+                AssetField::Liability,
+                asset.liability.to_string(),
+                decl.liability.to_string(),
+            );
+
+            let no_tags = BTreeMap::new();
+            let current = tags.get(&decl.name).unwrap_or(&no_tags);
+            let keys = current
+                .keys()
+                .chain(decl.tags.keys())
+                .collect::<BTreeSet<_>>();
+            for key in keys {
+                let before = current.get(key);
+                let after = decl.tags.get(key);
+                if before != after {
+                    changes.push(SyncChange::Update {
+                        asset: decl.name.clone(),
+                        field: AssetField::Tag(key.clone()),
+                        before: before.cloned(),
+                        after: after.cloned(),
+                    });
+                }
+            }
+        }
+
+        let declared_names = declared
+            .iter()
+            .map(|decl| decl.name.as_str())
+            .collect::<HashSet<_>>();
+        let mut archived = existing
+            .values()
+            .filter(|asset| !asset.archived && !declared_names.contains(asset.name.as_str()))
+            .map(|asset| asset.name.clone())
+            .collect::<Vec<_>>();
+        archived.sort();
+        changes.extend(archived.into_iter().map(SyncChange::Archive));
+
+        Ok(changes)
+    }
+
+    /// Applies planned changes all at once. New and changed queries are tried first, so nothing
+    /// is changed if one of them fails.
+    pub fn apply_sync(&mut self, changes: &[SyncChange]) -> Result<(), Error> {
+        let mut holdings_changed = BTreeSet::new();
+        for change in changes {
+            match change {
+                SyncChange::Add(decl) => {
+                    self.check_query(&decl.name, &decl.price_query, Price)?;
+                    self.check_query(
+                        &decl.name,
+                        &decl.holdings_query,
+                        holdings_type(decl.liability),
+                    )?;
+                }
+                SyncChange::Update {
+                    asset,
+                    field: AssetField::PriceQuery,
+                    after: Some(query),
+                    ..
+                } => self.check_query(asset, query, Price)?,
+                SyncChange::Update {
+                    asset,
+                    field: AssetField::HoldingsQuery | AssetField::Liability,
+                    ..
+                } => {
+                    holdings_changed.insert(asset);
+                }
+                _ => {}
+            }
+        }
+        // liabilities are queried as a different type, so flipping the flag can break a query
+        for asset in holdings_changed {
+            let mut current = with_db!(self.db()?, |db| schema::assets::table
+                .filter(schema::assets::name.eq(asset))
+                .first::<StoredAsset>(db))?;
+            for change in changes {
+                match change {
+                    SyncChange::Update {
+                        asset: other,
+                        field,
+                        after: Some(after),
+                        ..
+                    } if other == asset => match field {
+                        AssetField::HoldingsQuery => current.holdings_query = after.clone(),
+                        AssetField::Liability => current.liability = after == "true",
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+            self.check_query(asset, &current.holdings_query, current.holdings_type())?;
+        }
+
+        with_db!(self.db()?, |db| db.transaction(|| {
+            for change in changes {
+                match change {
+                    SyncChange::Add(decl) => {
+                        diesel::insert_into(schema::assets::table)
+                            .values(NewAsset {
+                                name: &decl.name,
+                                price_query: &decl.price_query,
+                                holdings_query: &decl.holdings_query,
+                                category: &decl.category,
+                                liability: decl.liability,
+                                archived: false,
+                            })
+                            .execute(db)?;
+                        let asset_id = self.asset_id(&decl.name)?;
+                        for (key, value) in &decl.tags {
+                            diesel::insert_into(schema::tags::table)
+                                .values((
+                                    schema::tags::asset_id.eq(asset_id),
+                                    schema::tags::key.eq(key),
+                                    schema::tags::value.eq(value),
+                                ))
+                                .execute(db)?;
+                        }
+                    }
+                    SyncChange::Restore(asset) => self.set_archived(asset, false)?,
+                    SyncChange::Archive(asset) => self.set_archived(asset, true)?,
+                    SyncChange::Update {
+                        asset,
+                        field: AssetField::Tag(key),
+                        after,
+                        ..
+                    } => {
+                        let asset_id = self.asset_id(asset)?;
+                        diesel::delete(schema::tags::table.find((asset_id, key))).execute(db)?;
+                        if let Some(value) = after {
+                            diesel::insert_into(schema::tags::table)
+                                .values((
+                                    schema::tags::asset_id.eq(asset_id),
+                                    schema::tags::key.eq(key),
+                                    schema::tags::value.eq(value),
+                                ))
+                                .execute(db)?;
+                        }
+                    }
+                    SyncChange::Update {
+                        asset,
+                        field,
+                        after,
+                        ..
+                    } => {
+                        let after = after.as_deref().unwrap_or_default();
+                        let target = schema::assets::table.filter(schema::assets::name.eq(asset));
+                        let update = diesel::update(target);
+                        match field {
+                            AssetField::Category => {
+                                update.set(schema::assets::category.eq(after)).execute(db)?
+                            }
+                            AssetField::PriceQuery => update
+                                .set(schema::assets::price_query.eq(after))
+                                .execute(db)?,
+                            AssetField::HoldingsQuery => update
+                                .set(schema::assets::holdings_query.eq(after))
+                                .execute(db)?,
+                            AssetField::Liability => update
+                                .set(schema::assets::liability.eq(after == "true"))
+                                .execute(db)?,
+                            AssetField::Tag(_) => unreachable!("handled above"),
+                        };
+                    }
+                }
+            }
+            Ok(())
+        }))
+    }
+
+    fn set_archived(&self, asset: &str, archived: bool) -> Result<(), Error> {
+        with_db!(self.db()?, |db| diesel::update(
+            schema::assets::table.filter(schema::assets::name.eq(asset))
+        )
+        .set(schema::assets::archived.eq(archived))
+        .execute(db))?;
+        Ok(())
+    }
+
+    pub(crate) fn check_query(
+        &mut self,
+        asset: &str,
+        query: &str,
+        query_type: assetman_api::PluginType,
+    ) -> Result<(), Error> {
+        cache::query_number(
+            self.storage.db(),
+            &mut self.plugins,
+            self.cache.as_ref(),
+            query,
+            query_type,
+        )
+        .map_err(|e| e.in_query(asset, query))?;
+        Ok(())
+    }
+}
+
+/// Rejects declarations that couldn't be stored, e.g. duplicate names or invalid tags
+fn check_declarations(declared: &[AssetDecl]) -> Result<(), Error> {
+    let mut names = HashSet::new();
+    for decl in declared {
+        if decl.name.trim().is_empty() {
+            return Err(Error::InvalidConfig(
+                "assets",
+                "asset names can't be empty".to_string(),
+            ));
+        }
+        if !names.insert(decl.name.as_str()) {
+            return Err(Error::InvalidConfig(
+                "assets",
+                format!("'{}' is declared more than once", decl.name),
+            ));
+        }
+        for (key, value) in &decl.tags {
+            if key == CATEGORY_KEY || key.trim().is_empty() || value.trim().is_empty() {
+                return Err(Error::InvalidConfig(
+                    "assets",
+                    format!("tag '{}' of '{}' is invalid", key, decl.name),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Display for AssetField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetField::Category => write!(f, "category"),
+            AssetField::PriceQuery => write!(f, "price"),
+            AssetField::HoldingsQuery => write!(f, "holdings"),
+            AssetField::Liability => write!(f, "liability"),
+            AssetField::Tag(key) => write!(f, "tag {}", key),
+        }
+    }
+}
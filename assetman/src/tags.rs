@@ -0,0 +1,175 @@
+use crate::schema::tags;
+use crate::{schema, Asset, Assets, Error};
+use assetman_api::Decimal;
+use diesel::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// Group key that refers to the category of an asset instead of a tag
+pub const CATEGORY_KEY: &str = "category";
+
+/// Label of the group of assets that don't have the tag grouped by
+pub const UNTAGGED: &str = "(untagged)";
+
+/// Free-form key/value label of an asset, e.g. `owner=alice` or `custodian=bank`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Tag {
+    pub asset: String,
+    pub key: String,
+    pub value: String,
+}
+
+/// Assets sharing the same value of a tag. Nested groups split the assets further by the next
+/// key, only the innermost groups contain assets directly.
+#[derive(Debug)]
+pub struct AssetGroup {
+    pub key: String,
+    pub value: String,
+    pub assets: Vec<Asset>,
+    pub groups: Vec<AssetGroup>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "tags"]
+struct InsertTag<'a> {
+    asset_id: i32,
+    key: &'a str,
+    value: &'a str,
+}
+
+impl AssetGroup {
+    /// Value of all assets in this group and its subgroups
+    pub fn value(&self) -> Decimal {
+        self.assets.iter().map(Asset::value).sum::<Decimal>()
+            + self.groups.iter().map(AssetGroup::value).sum::<Decimal>()
+    }
+}
+
+impl Assets {
+    /// Sets a tag of an asset, replacing its previous value
+    pub fn set_tag(&mut self, asset: &str, key: &str, value: &str) -> Result<(), Error> {
+        if key.trim().is_empty() || value.trim().is_empty() {
+            return Err(Error::InvalidTag(
+                "key and value can't be empty".to_string(),
+            ));
+        }
+        if key == CATEGORY_KEY {
+            return Err(Error::InvalidTag(format!(
+                "'{}' is reserved for the category of the asset",
+                CATEGORY_KEY
+            )));
+        }
+        let asset_id = self.asset_id(asset)?;
+        with_db!(self.db()?, |db| db.transaction(|| {
+            diesel::delete(schema::tags::table.find((asset_id, key))).execute(db)?;
+            diesel::insert_into(schema::tags::table)
+                .values(InsertTag {
+                    asset_id,
+                    key,
+                    value,
+                })
+                .execute(db)
+        }))?;
+        Ok(())
+    }
+
+    pub fn remove_tag(&mut self, asset: &str, key: &str) -> Result<(), Error> {
+        let asset_id = self.asset_id(asset)?;
+        let deleted = with_db!(self.db()?, |db| diesel::delete(
+            schema::tags::table.find((asset_id, key))
+        )
+        .execute(db))?;
+        if deleted == 0 {
+            return Err(Error::InvalidTag(format!(
+                "asset '{}' has no tag '{}'",
+                asset, key
+            )));
+        }
+        Ok(())
+    }
+
+    /// Lists the tags of all assets or a single one, ordered by asset and key
+    pub fn list_tags(&self, asset: Option<&str>) -> Result<Vec<Tag>, Error> {
+        if let Some(asset) = asset {
+            self.asset_id(asset)?;
+        }
+        let rows = with_db!(self.db()?, |db| {
+            let mut query = schema::tags::table
+                .inner_join(schema::assets::table)
+                .select((schema::assets::name, schema::tags::key, schema::tags::value))
+                .order_by((schema::assets::name, schema::tags::key))
+                .into_boxed();
+            if let Some(asset) = asset {
+                query = query.filter(schema::assets::name.eq(asset));
+            }
+            query.load::<(String, String, String)>(db)
+        })?;
+        Ok(rows
+            .into_iter()
+            .map(|(asset, key, value)| Tag { asset, key, value })
+            .collect())
+    }
+
+    /// Groups assets by the given tag keys, nested in their order. `category` groups by the
+    /// category of the assets. See `group_assets`.
+    pub fn group_assets(
+        &self,
+        assets: Vec<Asset>,
+        keys: &[String],
+    ) -> Result<Vec<AssetGroup>, Error> {
+        let mut tags = HashMap::<String, HashMap<String, String>>::new();
+        for tag in self.list_tags(None)? {
+            tags.entry(tag.asset)
+                .or_default()
+                .insert(tag.key, tag.value);
+        }
+        Ok(group_assets(assets, &tags, keys))
+    }
+}
+
+/// Groups assets by the values of the first key and each group recursively by the remaining
+/// keys. Groups are ordered by value, assets keep their order. Assets without the tag end up in
+/// the `UNTAGGED` group of that level.
+pub fn group_assets(
+    assets: Vec<Asset>,
+    tags: &HashMap<String, HashMap<String, String>>,
+    keys: &[String],
+) -> Vec<AssetGroup> {
+    let (key, remaining) = match keys.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+
+    let mut groups = BTreeMap::<String, Vec<Asset>>::new();
+    for asset in assets {
+        let value = if key == CATEGORY_KEY {
+            asset.category.clone()
+        } else {
+            tags.get(&asset.name)
+                .and_then(|tags| tags.get(key))
+                .cloned()
+                .unwrap_or_else(|| UNTAGGED.to_string())
+        };
+        groups.entry(value).or_default().push(asset);
+    }
+
+    groups
+        .into_iter()
+        .map(|(value, assets)| {
+            if remaining.is_empty() {
+                AssetGroup {
+                    key: key.clone(),
+                    value,
+                    assets,
+                    groups: Vec::new(),
+                }
+            } else {
+                AssetGroup {
+                    key: key.clone(),
+                    value,
+                    assets: Vec::new(),
+                    groups: group_assets(assets, tags, remaining),
+                }
+            }
+        })
+        .collect()
+}
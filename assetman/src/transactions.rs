@@ -0,0 +1,658 @@
+use crate::db::LastInsertId;
+use crate::decimal::DbDecimal;
+use crate::schema::transactions;
+use crate::{schema, Assets, Error};
+use assetman_api::Decimal;
+use chrono::Datelike;
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// What happened to the asset in a transaction. Buys and deposits add units, all other kinds
+/// remove units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionKind {
+    /// Units bought at `price` per unit
+    Buy,
+    /// Units sold at `price` per unit
+    Sell,
+    /// Units transferred in, `price` is their cost basis per unit
+    Deposit,
+    /// Units transferred out, `price` is their value per unit at the time
+    Withdrawal,
+    /// Units paid as fee, e.g. a network fee, `price` is their value per unit at the time
+    Fee,
+}
+
+/// How units that are removed are matched against the units that were added
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CostBasisMethod {
+    /// First in, first out
+    Fifo,
+    /// Last in, first out
+    Lifo,
+    /// All units cost the average price paid
+    Average,
+}
+
+/// Month and day a tax year starts on, tax years are labelled by the calendar year they start in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaxYearStart {
+    pub month: u32,
+    pub day: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    pub id: i32,
+    pub asset: String,
+    pub kind: TransactionKind,
+    pub date: NaiveDate,
+    pub amount: Decimal,
+    pub price: Decimal,
+    /// Commission paid in `currency` on top of the units, part of the cost of added units and
+    /// subtracted from the proceeds of removed ones
+    pub fee: Decimal,
+    pub currency: String,
+}
+
+/// Transaction that wasn't stored yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewTransaction {
+    pub kind: TransactionKind,
+    pub date: NaiveDate,
+    pub amount: Decimal,
+    pub price: Decimal,
+    /// Commission paid in `currency` on top of the units, part of the cost of added units and
+    /// subtracted from the proceeds of removed ones
+    pub fee: Decimal,
+    pub currency: String,
+}
+
+/// Units of an asset that were added by a single transaction and not removed yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lot {
+    pub date: NaiveDate,
+    pub amount: Decimal,
+    pub price: Decimal,
+}
+
+/// What is left of an asset's transactions after matching removed units against added ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostBasis {
+    pub method: CostBasisMethod,
+    /// Currency all transactions of the asset are denominated in, `None` without transactions
+    pub currency: Option<String>,
+    /// Units still held according to the transactions
+    pub amount: Decimal,
+    /// What was paid for the units still held
+    pub cost: Decimal,
+    /// What was paid for all units ever bought or deposited
+    pub invested: Decimal,
+    /// Gains realized by sales per tax year, fees count as loss of their cost basis and
+    /// withdrawals don't realize anything
+    pub realized: BTreeMap<i32, Decimal>,
+    /// Remaining lots, oldest first. With the average method all lots cost the average price.
+    pub lots: Vec<Lot>,
+}
+
+#[derive(Debug, Queryable)]
+struct TransactionRow {
+    id: i32,
+    asset: String,
+    kind: String,
+    date: NaiveDate,
+    #[diesel(deserialize_as = "DbDecimal")]
+    amount: Decimal,
+    #[diesel(deserialize_as = "DbDecimal")]
+    price: Decimal,
+    #[diesel(deserialize_as = "DbDecimal")]
+    fee: Decimal,
+    currency: String,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "transactions"]
+struct InsertTransaction<'a> {
+    asset_id: i32,
+    kind: String,
+    date: NaiveDate,
+    amount: DbDecimal,
+    price: DbDecimal,
+    fee: DbDecimal,
+    currency: &'a str,
+}
+
+impl Assets {
+    /// Records a transaction of the asset named `asset`. All transactions of an asset have to use
+    /// the same currency. Returns the id of the new transaction.
+    pub fn add_transaction(&mut self, asset: &str, tx: &NewTransaction) -> Result<i32, Error> {
+        if tx.amount <= Decimal::ZERO {
+            return Err(Error::InvalidTransaction(
+                "the amount has to be positive".to_string(),
+            ));
+        }
+        if tx.price.is_sign_negative() {
+            return Err(Error::InvalidTransaction(
+                "the price can't be negative".to_string(),
+            ));
+        }
+        if tx.fee.is_sign_negative() {
+            return Err(Error::InvalidTransaction(
+                "the fee can't be negative".to_string(),
+            ));
+        }
+
+        with_db!(self.db()?, |db| db.transaction(|| {
+            let asset_id = self.asset_id(asset)?;
+            let currency = schema::transactions::table
+                .select(schema::transactions::currency)
+                .filter(schema::transactions::asset_id.eq(asset_id))
+                .first::<String>(db)
+                .optional()?;
+            if let Some(currency) = currency.filter(|currency| currency != &tx.currency) {
+                return Err(Error::InvalidTransaction(format!(
+                    "transactions of '{}' are in {}, not {}",
+                    asset, currency, tx.currency
+                )));
+            }
+
+            diesel::insert_into(schema::transactions::table)
+                .values(InsertTransaction {
+                    asset_id,
+                    kind: tx.kind.to_string(),
+                    date: tx.date,
+                    amount: DbDecimal(tx.amount),
+                    price: DbDecimal(tx.price),
+                    fee: DbDecimal(tx.fee),
+                    currency: &tx.currency,
+                })
+                .execute(db)?;
+
+            Ok(db.last_insert_id("transactions")?)
+        }))
+    }
+
+    /// Lists the transactions of all assets or just a single one, ordered by date. With a selected
+    /// portfolio only transactions of owned assets are listed with amounts scaled by the share.
+    pub fn list_transactions(&self, asset: Option<&str>) -> Result<Vec<Transaction>, Error> {
+        if let Some(asset) = asset {
+            self.asset_id(asset)?;
+        }
+        let rows = with_db!(self.db()?, |db| {
+            let mut query = schema::transactions::table
+                .inner_join(schema::assets::table)
+                .select((
+                    schema::transactions::id,
+                    schema::assets::name,
+                    schema::transactions::kind,
+                    schema::transactions::date,
+                    schema::transactions::amount,
+                    schema::transactions::price,
+                    schema::transactions::fee,
+                    schema::transactions::currency,
+                ))
+                .order_by((schema::transactions::date, schema::transactions::id))
+                .into_boxed();
+            if let Some(asset) = asset {
+                query = query.filter(schema::assets::name.eq(asset));
+            }
+            query.load::<TransactionRow>(db)
+        })?;
+        let shares = self.owned_shares()?;
+
+        rows.into_iter()
+            .filter_map(|row| match &shares {
+                Some(shares) => {
+                    let share = *shares.get(&row.asset)?;
+                    Some(TransactionRow {
+                        amount: row.amount * share,
+                        fee: row.fee * share,
+                        ..row
+                    })
+                }
+                None => Some(row),
+            })
+            .map(|row| {
+                Ok(Transaction {
+                    id: row.id,
+                    asset: row.asset,
+                    kind: row.kind.parse().map_err(Error::InvalidTransaction)?,
+                    date: row.date,
+                    amount: row.amount,
+                    price: row.price,
+                    fee: row.fee,
+                    currency: row.currency,
+                })
+            })
+            .collect()
+    }
+
+    pub fn remove_transaction(&mut self, id: i32) -> Result<(), Error> {
+        let deleted = with_db!(self.db()?, |db| diesel::delete(
+            schema::transactions::table.find(id)
+        )
+        .execute(db))?;
+        if deleted == 0 {
+            return Err(Error::TransactionNotFound(id));
+        }
+        Ok(())
+    }
+
+    /// Computes the cost basis of an asset from its transactions
+    pub fn cost_basis(&self, asset: &str, method: CostBasisMethod) -> Result<CostBasis, Error> {
+        CostBasis::compute(
+            &self.list_transactions(Some(asset))?,
+            method,
+            self.tax_year_start,
+        )
+    }
+
+    /// Changes which day realized gains are split into years at
+    pub fn set_tax_year_start(&mut self, start: TaxYearStart) {
+        self.tax_year_start = start;
+    }
+
+    /// Changes the currency prices are quoted in, transactions in other currencies are rejected
+    /// by reports comparing them to prices. `None` only requires all of them to be in the same
+    /// currency.
+    pub fn set_base_currency(&mut self, currency: Option<String>) {
+        self.base_currency = currency;
+    }
+
+    /// Currency of all `transactions`, fails if they can't be compared to prices or each other
+    pub(crate) fn transactions_currency<'a>(
+        &self,
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+    ) -> Result<Option<String>, Error> {
+        let mut currency = self.base_currency.as_deref();
+        for tx in transactions {
+            match currency {
+                Some(currency) if currency != tx.currency => {
+                    return Err(Error::InvalidTransaction(format!(
+                        "transaction {} of '{}' is in {}, but {}",
+                        tx.id,
+                        tx.asset,
+                        tx.currency,
+                        match &self.base_currency {
+                            Some(_) => format!("prices are in {}", currency),
+                            None => format!("other transactions are in {}", currency),
+                        }
+                    )));
+                }
+                _ => currency = Some(&tx.currency),
+            }
+        }
+        Ok(currency.map(str::to_string))
+    }
+
+    pub(crate) fn asset_id(&self, asset: &str) -> Result<i32, Error> {
+        self.store()
+            .asset(asset)?
+            .map(|asset| asset.id)
+            .ok_or_else(|| Error::AssetNotFound(asset.to_string()))
+    }
+}
+
+impl CostBasis {
+    /// Replays the transactions of a single asset in order, removing units from the lots as
+    /// determined by `method`. Fails if more units are removed than were added before.
+    pub fn compute(
+        transactions: &[Transaction],
+        method: CostBasisMethod,
+        tax_year: TaxYearStart,
+    ) -> Result<CostBasis, Error> {
+        let mut lots = VecDeque::<Lot>::new();
+        let mut currency = None::<&str>;
+        let mut invested = Decimal::ZERO;
+        let mut realized = BTreeMap::<i32, Decimal>::new();
+
+        for tx in transactions {
+            match currency {
+                Some(currency) if currency != tx.currency => {
+                    return Err(Error::InvalidTransaction(format!(
+                        "transaction {} is in {}, not {}",
+                        tx.id, tx.currency, currency
+                    )))
+                }
+                _ => currency = Some(&tx.currency),
+            }
+
+            match tx.kind {
+                TransactionKind::Buy | TransactionKind::Deposit => {
+                    invested += tx.amount * tx.price + tx.fee;
+                    lots.push_back(Lot {
+                        date: tx.date,
+                        amount: tx.amount,
+                        price: tx.price + tx.fee / tx.amount,
+                    });
+                    if method == CostBasisMethod::Average {
+                        average(&mut lots);
+                    }
+                }
+                TransactionKind::Sell | TransactionKind::Withdrawal | TransactionKind::Fee => {
+                    let cost = remove(&mut lots, tx, method)?;
+                    let gain = match tx.kind {
+                        TransactionKind::Sell => tx.amount * tx.price - tx.fee - cost,
+                        TransactionKind::Fee => -cost - tx.fee,
+                        // the units keep their cost basis elsewhere, only the fee is lost
+                        _ if tx.fee.is_zero() => continue,
+                        _ => -tx.fee,
+                    };
+                    *realized
+                        .entry(tax_year.year(tx.date))
+                        .or_insert(Decimal::ZERO) += gain;
+                }
+            }
+        }
+
+        let lots = Vec::from(lots);
+        Ok(CostBasis {
+            method,
+            currency: currency.map(str::to_string),
+            amount: lots.iter().map(|lot| lot.amount).sum(),
+            cost: lots.iter().map(|lot| lot.amount * lot.price).sum(),
+            invested,
+            realized,
+            lots,
+        })
+    }
+
+    /// Average price paid per unit still held, `None` if nothing is held
+    pub fn average_price(&self) -> Option<Decimal> {
+        if self.amount.is_zero() {
+            None
+        } else {
+            Some(self.cost / self.amount)
+        }
+    }
+
+    /// Sum of all realized gains
+    pub fn realized_total(&self) -> Decimal {
+        self.realized.values().sum()
+    }
+}
+
+/// Merges all lots into a single one at the average price, dated like the oldest lot
+fn average(lots: &mut VecDeque<Lot>) {
+    let amount: Decimal = lots.iter().map(|lot| lot.amount).sum();
+    if lots.len() < 2 || amount.is_zero() {
+        return;
+    }
+    let cost: Decimal = lots.iter().map(|lot| lot.amount * lot.price).sum();
+    let date = lots[0].date;
+    lots.clear();
+    lots.push_back(Lot {
+        date,
+        amount,
+        price: cost / amount,
+    });
+}
+
+/// Removes the units of `tx` from the lots and returns what was paid for them
+fn remove(
+    lots: &mut VecDeque<Lot>,
+    tx: &Transaction,
+    method: CostBasisMethod,
+) -> Result<Decimal, Error> {
+    let mut remaining = tx.amount;
+    let mut cost = Decimal::ZERO;
+    while remaining > Decimal::ZERO {
+        let lot = match method {
+            CostBasisMethod::Fifo | CostBasisMethod::Average => lots.front_mut(),
+            CostBasisMethod::Lifo => lots.back_mut(),
+        };
+        let lot = lot.ok_or_else(|| {
+            Error::InvalidTransaction(format!(
+                "transaction {} removes {} more units of '{}' than were held on {}",
+                tx.id, remaining, tx.asset, tx.date
+            ))
+        })?;
+
+        if lot.amount > remaining {
+            lot.amount -= remaining;
+            cost += remaining * lot.price;
+            remaining = Decimal::ZERO;
+        } else {
+            remaining -= lot.amount;
+            cost += lot.amount * lot.price;
+            match method {
+                CostBasisMethod::Fifo | CostBasisMethod::Average => lots.pop_front(),
+                CostBasisMethod::Lifo => lots.pop_back(),
+            };
+        }
+    }
+    Ok(cost)
+}
+
+impl TaxYearStart {
+    /// Tax year `date` falls into
+    pub fn year(self, date: NaiveDate) -> i32 {
+        if (date.month(), date.day()) < (self.month, self.day) {
+            date.year() - 1
+        } else {
+            date.year()
+        }
+    }
+}
+
+impl Default for TaxYearStart {
+    /// Tax years are calendar years
+    fn default() -> Self {
+        TaxYearStart { month: 1, day: 1 }
+    }
+}
+
+impl Display for TaxYearStart {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}-{:02}", self.month, self.day)
+    }
+}
+
+/// Parses `MM-DD`, e.g. `04-06` for tax years starting on April 6th
+impl FromStr for TaxYearStart {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(2, '-');
+        let month = parts.next().and_then(|month| month.parse().ok());
+        let day = parts.next().and_then(|day| day.parse().ok());
+        match (month, day) {
+            // every tax year has to contain its start, so February 29th isn't allowed
+            (Some(month), Some(day)) if NaiveDate::from_ymd_opt(2021, month, day).is_some() => {
+                Ok(TaxYearStart { month, day })
+            }
+            _ => Err(format!("'{}' is not a day of the year like 04-06", s)),
+        }
+    }
+}
+
+impl Display for TransactionKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            TransactionKind::Buy => "buy",
+            TransactionKind::Sell => "sell",
+            TransactionKind::Deposit => "deposit",
+            TransactionKind::Withdrawal => "withdrawal",
+            TransactionKind::Fee => "fee",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+impl FromStr for TransactionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "buy" => Ok(TransactionKind::Buy),
+            "sell" => Ok(TransactionKind::Sell),
+            "deposit" => Ok(TransactionKind::Deposit),
+            "withdrawal" => Ok(TransactionKind::Withdrawal),
+            "fee" => Ok(TransactionKind::Fee),
+            _ => Err(format!("unknown transaction kind '{}'", s)),
+        }
+    }
+}
+
+impl FromStr for CostBasisMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fifo" => Ok(CostBasisMethod::Fifo),
+            "lifo" => Ok(CostBasisMethod::Lifo),
+            "average" | "avg" => Ok(CostBasisMethod::Average),
+            _ => Err(format!("unknown cost basis method '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transactions::{
+        CostBasis, CostBasisMethod, TaxYearStart, Transaction, TransactionKind,
+    };
+    use crate::Error;
+    use assetman_api::Decimal;
+    use chrono::NaiveDate;
+
+    fn tx(day: u32, kind: TransactionKind, amount: &str, price: &str) -> Transaction {
+        Transaction {
+            id: day as i32,
+            asset: "Bitcoin".to_string(),
+            kind,
+            date: NaiveDate::from_ymd(2020, 1, day),
+            amount: amount.parse().unwrap(),
+            price: price.parse().unwrap(),
+            fee: Decimal::ZERO,
+            currency: "EUR".to_string(),
+        }
+    }
+
+    fn with_fee(tx: Transaction, fee: &str) -> Transaction {
+        Transaction {
+            fee: fee.parse().unwrap(),
+            ..tx
+        }
+    }
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cost_basis_fees() {
+        let transactions = vec![
+            with_fee(tx(1, TransactionKind::Buy, "2", "100"), "2"),
+            with_fee(tx(2, TransactionKind::Sell, "1", "150"), "1"),
+            with_fee(tx(3, TransactionKind::Withdrawal, "0.5", "150"), "0.5"),
+            with_fee(tx(4, TransactionKind::Fee, "0.1", "150"), "0.2"),
+        ];
+
+        let basis = CostBasis::compute(
+            &transactions,
+            CostBasisMethod::Fifo,
+            TaxYearStart::default(),
+        )
+        .unwrap();
+        assert_eq!(basis.invested, dec("202"));
+        assert_eq!(basis.amount, dec("0.4"));
+        assert_eq!(basis.cost, dec("40.4"));
+        // 150 - 1 - 101 for the sale, the withdrawal's fee and 10.1 + 0.2 for the fee
+        assert_eq!(basis.realized_total(), dec("48") - dec("0.5") - dec("10.3"));
+    }
+
+    #[test]
+    fn test_cost_basis() {
+        let transactions = vec![
+            tx(1, TransactionKind::Buy, "1", "100"),
+            tx(2, TransactionKind::Deposit, "1", "200"),
+            tx(3, TransactionKind::Buy, "2", "400"),
+            tx(4, TransactionKind::Sell, "1.5", "500"),
+            tx(5, TransactionKind::Fee, "0.5", "500"),
+        ];
+
+        let fifo = CostBasis::compute(
+            &transactions,
+            CostBasisMethod::Fifo,
+            TaxYearStart::default(),
+        )
+        .unwrap();
+        assert_eq!(fifo.currency.as_deref(), Some("EUR"));
+        assert_eq!(fifo.amount, dec("2"));
+        assert_eq!(fifo.cost, dec("800"));
+        assert_eq!(fifo.lots.len(), 1);
+        assert_eq!(fifo.invested, dec("1100"));
+        // sold 1 @ 100 and 0.5 @ 200 for 750, then paid 0.5 @ 200 as fee
+        assert_eq!(fifo.realized_total(), dec("450"));
+
+        let lifo = CostBasis::compute(
+            &transactions,
+            CostBasisMethod::Lifo,
+            TaxYearStart::default(),
+        )
+        .unwrap();
+        assert_eq!(lifo.amount, dec("2"));
+        assert_eq!(lifo.cost, dec("300"));
+        assert_eq!(lifo.lots.len(), 2);
+        // sold 1.5 @ 400 for 750, then paid 0.5 @ 400 as fee
+        assert_eq!(lifo.realized_total(), dec("-50"));
+
+        let average = CostBasis::compute(
+            &transactions,
+            CostBasisMethod::Average,
+            TaxYearStart::default(),
+        )
+        .unwrap();
+        assert_eq!(average.amount, dec("2"));
+        assert_eq!(average.cost, dec("550"));
+        assert_eq!(average.average_price(), Some(dec("275")));
+    }
+
+    #[test]
+    fn test_overselling() {
+        let transactions = vec![
+            tx(1, TransactionKind::Buy, "1", "100"),
+            tx(2, TransactionKind::Withdrawal, "1.1", "100"),
+        ];
+        match CostBasis::compute(
+            &transactions,
+            CostBasisMethod::Fifo,
+            TaxYearStart::default(),
+        ) {
+            Err(Error::InvalidTransaction(_)) => {}
+            res => panic!("expected invalid transaction, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_tax_years() {
+        let start = "04-06".parse::<TaxYearStart>().unwrap();
+        assert_eq!(start.to_string(), "04-06");
+        assert_eq!(start.year(NaiveDate::from_ymd(2021, 4, 5)), 2020);
+        assert_eq!(start.year(NaiveDate::from_ymd(2021, 4, 6)), 2021);
+        assert!("02-29".parse::<TaxYearStart>().is_err());
+        assert!("13-01".parse::<TaxYearStart>().is_err());
+        assert!("april".parse::<TaxYearStart>().is_err());
+
+        let mut transactions = vec![
+            tx(1, TransactionKind::Buy, "2", "100"),
+            tx(2, TransactionKind::Sell, "1", "150"),
+        ];
+        transactions[1].date = NaiveDate::from_ymd(2020, 5, 1);
+        let basis = CostBasis::compute(&transactions, CostBasisMethod::Fifo, start).unwrap();
+        assert_eq!(
+            basis.realized.into_iter().collect::<Vec<_>>(),
+            vec![(2020, dec("50"))]
+        );
+        transactions[1].date = NaiveDate::from_ymd(2020, 4, 1);
+        let basis = CostBasis::compute(&transactions, CostBasisMethod::Fifo, start).unwrap();
+        assert_eq!(
+            basis.realized.into_iter().collect::<Vec<_>>(),
+            vec![(2019, dec("50"))]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Alert rules checked after fetching
+
+mod common;
+
+use assetman::alerts::{Alert, Condition};
+use assetman::allocation::{Target, TargetKind};
+use assetman::notifiers::{Notifier, NotifyError};
+use assetman::Error;
+use assetman_api::PluginType::Price;
+use assetman_mock::MockPlugin;
+use common::{assets, dec, holdings_plugin, price_plugin};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+struct CollectingNotifier(Rc<RefCell<Vec<String>>>);
+
+impl Notifier for CollectingNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        self.0.borrow_mut().push(alert.message.clone());
+        Ok(())
+    }
+}
+
+#[test]
+fn alerts_after_fetch() {
+    // the first answer is used to validate the query when adding the asset
+    let mover =
+        MockPlugin::new("mover", Price).answers("X", vec![dec("100"), dec("100"), dec("80")]);
+    let mut assets = assets(vec![price_plugin(), holdings_plugin(), mover]);
+    assets.set_cache(None);
+    let notified = Rc::new(RefCell::new(Vec::new()));
+    assets.add_notifier(Box::new(CollectingNotifier(notified.clone())));
+    assets
+        .add_asset("Bitcoin", "crypto", "price(BTC)", "holdings(cold)")
+        .unwrap();
+    assets
+        .add_asset("Mover", "other", "mover(X)", "holdings(hot)")
+        .unwrap();
+    assets
+        .set_target(&Target {
+            kind: TargetKind::Category,
+            name: "other".to_string(),
+            weight: dec("0.1"),
+            tolerance: dec("0.05"),
+        })
+        .unwrap();
+
+    match assets.add_alert_rule(&Condition::TotalDrop(dec("-0.1"))) {
+        Err(Error::InvalidAlertRule(_)) => {}
+        res => panic!("expected invalid alert rule, got {:?}", res),
+    }
+    let rules = vec![
+        Condition::TotalDrop(dec("0.005")),
+        Condition::PriceMove {
+            asset: "Mover".to_string(),
+            threshold: dec("0.15"),
+        },
+        Condition::PriceAbove {
+            asset: "Mover".to_string(),
+            level: dec("90"),
+        },
+        Condition::PriceBelow {
+            asset: "Mover".to_string(),
+            level: dec("90"),
+        },
+        Condition::OutOfBand(TargetKind::Category),
+    ];
+    for rule in &rules {
+        assets.add_alert_rule(rule).unwrap();
+    }
+    let stored = assets.list_alert_rules().unwrap();
+    assert_eq!(
+        stored
+            .into_iter()
+            .map(|rule| rule.condition)
+            .collect::<Vec<_>>(),
+        rules
+    );
+    match assets.remove_alert_rule(42) {
+        Err(Error::AlertRuleNotFound(42)) => {}
+        res => panic!("expected alert rule not found, got {:?}", res),
+    }
+
+    // without a previous update only level and band rules can match
+    assets.fetch_data().unwrap();
+    assert_eq!(
+        *notified.borrow(),
+        vec![
+            "price of Mover rose above 90 to 100",
+            "category 'other' is at 3.8% instead of 10.0% ±5.0%",
+        ]
+    );
+
+    // the category is still out of band, which isn't news
+    notified.borrow_mut().clear();
+    assets.fetch_data().unwrap();
+    assert_eq!(
+        *notified.borrow(),
+        vec![
+            "net worth dropped by 0.8% from 5200 to 5160",
+            "price of Mover fell by 20.0% from 100 to 80",
+            "price of Mover fell below 90 to 80",
+        ]
+    );
+
+    // checking again replaces the alerts of the latest update
+    assert_eq!(assets.check_alerts().unwrap().len(), 3);
+    let history = assets.list_alerts(None).unwrap();
+    assert_eq!(history.len(), 5);
+    assert_eq!(history[0].rule, "price of Mover falls below 90");
+}
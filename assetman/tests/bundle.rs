@@ -0,0 +1,152 @@
+//! Exporting and importing bundles
+
+mod common;
+
+use assetman::alerts::Condition;
+use assetman::bundle::{Bundle, OnConflict};
+use assetman::transactions::TransactionKind;
+use assetman::Error;
+use chrono::NaiveDate;
+use common::{assets, bitcoin_assets, dec, holdings_plugin, new_tx, price_plugin};
+
+#[test]
+fn export_and_import_bundle() {
+    let mut source = bitcoin_assets();
+    source
+        .add_asset("Ether", "crypto", "price(ETH)", "holdings(hot)")
+        .unwrap();
+    source.set_tag("Bitcoin", "owner", "alice").unwrap();
+    source.set_ownership("Ether", "alice", dec("0.5")).unwrap();
+    let mut tx = new_tx(
+        TransactionKind::Buy,
+        NaiveDate::from_ymd(2020, 1, 1),
+        "0.5",
+        "8000.123",
+    );
+    tx.fee = dec("1.50");
+    source.add_transaction("Bitcoin", &tx).unwrap();
+    source
+        .add_alert_rule(&Condition::TotalDrop(dec("0.1")))
+        .unwrap();
+    source.fetch_data().unwrap();
+    let bundle = source.export().unwrap();
+
+    // JSON and CSV both keep every record exactly
+    let mut json = Vec::new();
+    bundle.write_json(&mut json).unwrap();
+    assert_eq!(Bundle::read_json(json.as_slice()).unwrap(), bundle);
+    let dir = std::env::temp_dir().join(format!("assetman-bundle-{}", std::process::id()));
+    bundle.write_csv(&dir).unwrap();
+    let from_csv = Bundle::read_csv(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(from_csv, bundle);
+
+    let mut target = assets(vec![price_plugin(), holdings_plugin()]);
+    let summary = target.import(&bundle, OnConflict::Abort).unwrap();
+    assert_eq!(summary.unchanged, 0);
+    assert!(summary.conflicts.is_empty());
+    let exported = target.export().unwrap();
+    assert_eq!(exported.assets, bundle.assets);
+    assert_eq!(exported.prices, bundle.prices);
+    assert_eq!(exported.transactions, bundle.transactions);
+    assert_eq!(exported.ownership, bundle.ownership);
+    assert_eq!(
+        target.list_assets().unwrap().net_worth(),
+        source.list_assets().unwrap().net_worth()
+    );
+
+    // importing again changes nothing
+    let summary = target.import(&bundle, OnConflict::Abort).unwrap();
+    assert_eq!(summary.added, 0);
+    assert_eq!(summary.unchanged, record_count(&bundle));
+
+    // conflicting records abort the whole import unless told otherwise
+    let mut changed = bundle.clone();
+    changed.assets[0].category = "savings".to_string();
+    changed.tags[0].value = "bob".to_string();
+    match target.import(&changed, OnConflict::Abort) {
+        Err(Error::ImportConflict(record)) => assert_eq!(record, "asset 'Bitcoin'"),
+        res => panic!("expected conflict, got {:?}", res),
+    }
+    let summary = target.import(&changed, OnConflict::Keep).unwrap();
+    assert_eq!(summary.conflicts.len(), 2);
+    assert_eq!(target.list_tags(Some("Bitcoin")).unwrap()[0].value, "alice");
+    target.import(&changed, OnConflict::Replace).unwrap();
+    assert_eq!(target.list_tags(Some("Bitcoin")).unwrap()[0].value, "bob");
+
+    let mut newer = bundle;
+    newer.version += 1;
+    match target.import(&newer, OnConflict::Abort) {
+        Err(Error::InvalidBundle(_)) => {}
+        res => panic!("expected unsupported version, got {:?}", res),
+    }
+}
+
+#[test]
+fn bundle_round_trip_keeps_duplicates() {
+    let mut source = bitcoin_assets();
+    let tx = new_tx(
+        TransactionKind::Buy,
+        NaiveDate::from_ymd(2020, 1, 1),
+        "0.5",
+        "8000",
+    );
+    source.add_transaction("Bitcoin", &tx).unwrap();
+    source.add_transaction("Bitcoin", &tx).unwrap();
+    source.fetch_data().unwrap();
+    let mut bundle = source.export().unwrap();
+    assert_eq!(bundle.transactions.len(), 2);
+    // a second update within the same second
+    let mut update = bundle.updates[0].clone();
+    update.id += 1;
+    bundle.updates.push(update.clone());
+    let mut price = bundle.prices[0].clone();
+    price.update = update.id;
+    price.value = dec("9000");
+    bundle.prices.push(price);
+
+    let mut target = assets(vec![price_plugin(), holdings_plugin()]);
+    let summary = target.import(&bundle, OnConflict::Abort).unwrap();
+    assert_eq!(summary.added, record_count(&bundle));
+    let exported = target.export().unwrap();
+    assert_eq!(exported.transactions, bundle.transactions);
+    assert_eq!(exported.updates.len(), 2);
+    assert_eq!(exported.prices.len(), 2);
+
+    // re-importing the own export matches every record once
+    let summary = target.import(&exported, OnConflict::Abort).unwrap();
+    assert_eq!(summary.added, 0);
+    assert!(summary.conflicts.is_empty());
+    assert_eq!(summary.unchanged, record_count(&exported));
+    assert_eq!(target.export().unwrap().transactions.len(), 2);
+}
+
+#[test]
+fn bundle_ownership_is_validated() {
+    let mut target = assets(vec![price_plugin(), holdings_plugin()]);
+    target
+        .add_asset("Ether", "crypto", "price(ETH)", "holdings(hot)")
+        .unwrap();
+    target.set_ownership("Ether", "bob", dec("0.7")).unwrap();
+    let mut bundle = target.export().unwrap();
+    bundle.ownership[0].portfolio = "alice".to_string();
+    bundle.ownership[0].share = dec("0.5");
+    match target.import(&bundle, OnConflict::Abort) {
+        Err(Error::InvalidOwnership(_)) => {}
+        res => panic!("expected invalid ownership, got {:?}", res),
+    }
+    assert_eq!(target.list_ownership().unwrap().len(), 1);
+}
+
+fn record_count(bundle: &Bundle) -> usize {
+    bundle.assets.len()
+        + bundle.tags.len()
+        + bundle.ownership.len()
+        + bundle.updates.len()
+        + bundle.prices.len()
+        + bundle.holdings.len()
+        + bundle.transactions.len()
+        + bundle.targets.len()
+        + bundle.alert_rules.len()
+        + bundle.alerts.len()
+}
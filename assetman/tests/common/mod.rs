@@ -0,0 +1,81 @@
+//! Fixtures shared by the integration tests: mock plugins answering a fixed set of queries and
+//! databases using them. Not every test file uses all of them.
+#![allow(dead_code)]
+
+use assetman::transactions::{NewTransaction, TransactionKind};
+use assetman::{Assets, Plugins};
+use assetman_api::Decimal;
+use assetman_api::PluginType::{Holdings, Price};
+use assetman_mock::MockPlugin;
+use chrono::NaiveDate;
+use std::time::Duration;
+
+pub fn dec(value: &str) -> Decimal {
+    value.parse().unwrap()
+}
+
+pub fn price_plugin() -> MockPlugin {
+    MockPlugin::new("price", Price)
+        .answer("BTC", dec("10000"))
+        .answer("ETH", dec("300"))
+        .answer("EUR", dec("1"))
+        .error("XXX", 3, "unknown ticker")
+        .malformed("garbage", "{\"answer\": ")
+        .delay("slow", Duration::from_millis(50), dec("2"))
+}
+
+pub fn holdings_plugin() -> MockPlugin {
+    MockPlugin::new("holdings", Holdings)
+        .answer("cold", dec("0.5"))
+        .answer("hot", dec("2"))
+        .answer("empty", dec("0"))
+}
+
+/// Connects to the mock plugins via in-process streams
+pub fn plugins(plugins: Vec<MockPlugin>) -> Plugins {
+    let streams = plugins
+        .into_iter()
+        .map(|plugin| plugin.spawn().unwrap())
+        .collect::<Vec<_>>();
+    Plugins::from_streams(streams.into_iter()).unwrap()
+}
+
+/// In-memory SQLite database using the mock plugins
+pub fn assets(plugins: Vec<MockPlugin>) -> Assets {
+    Assets::with_plugins(":memory:", self::plugins(plugins)).unwrap()
+}
+
+/// In-memory database with the price and holdings plugins and half a Bitcoin, which is worth
+/// 5000 once fetched
+pub fn bitcoin_assets() -> Assets {
+    let mut assets = assets(vec![price_plugin(), holdings_plugin()]);
+    assets
+        .add_asset("Bitcoin", "crypto", "price(BTC)", "holdings(cold)")
+        .unwrap();
+    assets
+}
+
+/// Arguments of all requests the plugin received so far, oldest first
+pub fn logged_arguments(plugin: &MockPlugin) -> impl Fn() -> Vec<String> {
+    let requests = plugin.requests();
+    move || {
+        requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|req| req.arguments.clone())
+            .collect()
+    }
+}
+
+/// Transaction in EUR without fee
+pub fn new_tx(kind: TransactionKind, date: NaiveDate, amount: &str, price: &str) -> NewTransaction {
+    NewTransaction {
+        kind,
+        date,
+        amount: dec(amount),
+        price: dec(price),
+        fee: Decimal::ZERO,
+        currency: "EUR".to_string(),
+    }
+}
@@ -0,0 +1,383 @@
+//! Fetching values from plugins and listing them
+
+mod common;
+
+use assetman::anomalies::AnomalyKind;
+use assetman::history::ColumnMapping;
+use assetman::store::MemoryStore;
+use assetman::transactions::TransactionKind;
+use assetman::{Assets, Error, PluginError};
+use assetman_api::PluginType::{Any, Holdings, Liabilities, Price};
+use assetman_api::{DataPoint, Value};
+use assetman_mock::{Behavior, MockPlugin};
+use chrono::NaiveDate;
+use common::{assets, dec, holdings_plugin, new_tx, plugins, price_plugin};
+
+#[test]
+fn list_without_data() {
+    let assets = assets(vec![price_plugin(), holdings_plugin()]);
+    match assets.list_assets() {
+        Err(Error::NoData) => {}
+        res => panic!("expected no data error, got {:?}", res.err()),
+    }
+}
+
+#[test]
+fn fetch_and_list() {
+    let mut assets = assets(vec![price_plugin(), holdings_plugin()]);
+    assets
+        .add_asset("Ether", "crypto", "price(ETH)", "holdings(hot)")
+        .unwrap();
+    assets
+        .add_asset("Bitcoin", "crypto", "price(BTC)", "holdings(cold)")
+        .unwrap();
+    assets
+        .add_asset("Sold", "crypto", "price(BTC)", "holdings(empty)")
+        .unwrap();
+    assets
+        .add_asset("Slow", "other", "price(slow)", "holdings(hot)")
+        .unwrap();
+
+    assets.fetch_data().unwrap();
+    let snapshot = assets.list_assets().unwrap();
+
+    // sorted by name, empty holdings are hidden
+    let listed = snapshot
+        .assets
+        .iter()
+        .map(|asset| {
+            (
+                asset.name.as_str(),
+                asset.category.as_str(),
+                asset.price,
+                asset.holdings,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        listed,
+        vec![
+            ("Bitcoin", "crypto", dec("10000"), dec("0.5")),
+            ("Ether", "crypto", dec("300"), dec("2")),
+            ("Slow", "other", dec("2"), dec("2")),
+        ]
+    );
+}
+
+#[test]
+fn add_asset_validates_queries() {
+    let mut assets = assets(vec![price_plugin(), holdings_plugin()]);
+
+    match assets.add_asset("X", "crypto", "price(XXX)", "holdings(hot)") {
+        Err(Error::QueryError {
+            asset,
+            query,
+            error: PluginError::PluginError(plugin, e),
+        }) => {
+            assert_eq!(asset, "X");
+            assert_eq!(query, "price(XXX)");
+            assert_eq!(plugin, "price");
+            assert_eq!(e.code, 3);
+        }
+        res => panic!("expected plugin error, got {:?}", res.err()),
+    }
+    match assets.add_asset("X", "crypto", "price(garbage)", "holdings(hot)") {
+        Err(Error::QueryError {
+            error: PluginError::BadAnswer(_),
+            ..
+        }) => {}
+        res => panic!("expected bad answer, got {:?}", res.err()),
+    }
+    match assets.add_asset("X", "crypto", "holdings(hot)", "holdings(hot)") {
+        Err(Error::QueryError {
+            error: PluginError::WrongType { .. },
+            ..
+        }) => {}
+        res => panic!("expected wrong type, got {:?}", res.err()),
+    }
+    match assets.add_asset("X", "crypto", "missing(BTC)", "holdings(hot)") {
+        Err(Error::QueryError {
+            error: PluginError::UnknownPlugin(_),
+            ..
+        }) => {}
+        res => panic!("expected unknown plugin, got {:?}", res.err()),
+    }
+    match assets.add_asset("X", "crypto", "BTC", "holdings(hot)") {
+        Err(Error::QueryError {
+            error: PluginError::QueryParseError(_),
+            ..
+        }) => {}
+        res => panic!("expected parse error, got {:?}", res.err()),
+    }
+
+    // nothing was stored
+    assets.fetch_data().unwrap();
+    assert!(assets.list_assets().unwrap().assets.is_empty());
+}
+
+#[test]
+fn failed_fetch_is_rolled_back() {
+    let flaky = MockPlugin::new("flaky", Any).behave(
+        "1",
+        Behavior::Sequence(vec![
+            Behavior::Answer(dec("1").into()),
+            Behavior::Answer(dec("1").into()),
+            Behavior::Crash,
+        ]),
+    );
+    let mut assets = assets(vec![price_plugin(), holdings_plugin(), flaky]);
+    assets.set_cache(None);
+    assets
+        .add_asset("Bitcoin", "crypto", "price(BTC)", "holdings(cold)")
+        .unwrap();
+    assets
+        .add_asset("Flaky", "other", "flaky(1)", "holdings(hot)")
+        .unwrap();
+
+    assets.fetch_data().unwrap();
+    let first = assets.list_assets().unwrap();
+    assert_eq!(first.assets.len(), 2);
+
+    // the plugin crashes during the second fetch, no partial update may be visible
+    assert!(assets.fetch_data().is_err());
+    let second = assets.list_assets().unwrap();
+    assert_eq!(second.time, first.time);
+    assert_eq!(second.assets.len(), 2);
+
+    // the crashed plugin stays unusable
+    match assets.fetch_data() {
+        Err(Error::QueryError {
+            asset,
+            error: PluginError::ConnectionError(..),
+            ..
+        }) => assert_eq!(asset, "Flaky"),
+        res => panic!("expected connection error, got {:?}", res.err()),
+    }
+}
+
+#[test]
+fn amounts_are_exact() {
+    let mut assets = assets(vec![
+        MockPlugin::new("price", Price)
+            .answer("dime", dec("0.1"))
+            // plugins sending plain JSON numbers are read via their shortest representation
+            .malformed("float", "{\"Ok\":{\"answer\":0.2}}"),
+        MockPlugin::new("holdings", Holdings)
+            .answer("dimes", dec("0.3"))
+            .answer("exponent", dec("0.0000001"))
+            .answer("none", dec("0.00")),
+    ]);
+    assets
+        .add_asset("Dimes", "cash", "price(dime)", "holdings(dimes)")
+        .unwrap();
+    assets
+        .add_asset("Floats", "cash", "price(float)", "holdings(exponent)")
+        .unwrap();
+    assets
+        .add_asset("Nothing", "cash", "price(dime)", "holdings(none)")
+        .unwrap();
+    assets.fetch_data().unwrap();
+
+    let listed = assets.list_assets().unwrap().assets;
+    assert_eq!(listed.len(), 2);
+    assert_eq!(listed[0].price * listed[0].holdings, dec("0.03"));
+    assert_eq!(listed[1].price, dec("0.2"));
+    assert_eq!(listed[1].holdings, dec("0.0000001"));
+}
+
+#[test]
+fn liabilities_and_net_worth() {
+    let loans = MockPlugin::new("loans", Liabilities).answer("mortgage", dec("2000"));
+    let mut assets = assets(vec![price_plugin(), holdings_plugin(), loans]);
+    assets
+        .add_asset("Bitcoin", "crypto", "price(BTC)", "holdings(cold)")
+        .unwrap();
+    // holdings plugins don't answer liability queries and vice versa
+    match assets.add_asset("Mortgage", "house", "price(EUR)", "loans(mortgage)") {
+        Err(Error::QueryError {
+            error: PluginError::WrongType { .. },
+            ..
+        }) => {}
+        res => panic!("expected wrong type error, got {:?}", res),
+    }
+    assets
+        .add_liability("Mortgage", "house", "price(EUR)", "loans(mortgage)")
+        .unwrap();
+
+    assets.fetch_data().unwrap();
+    let snapshot = assets.list_assets().unwrap();
+    let mortgage = &snapshot.assets[1];
+    assert!(mortgage.liability);
+    assert_eq!(mortgage.value(), dec("-2000"));
+    assert_eq!(snapshot.gross_assets(), dec("5000"));
+    assert_eq!(snapshot.liabilities(), dec("-2000"));
+    assert_eq!(snapshot.net_worth(), dec("3000"));
+
+    // the holdings query has to answer as the new kind
+    match assets.set_liability("Mortgage", false) {
+        Err(Error::QueryError {
+            error: PluginError::WrongType { .. },
+            ..
+        }) => {}
+        res => panic!("expected wrong type error, got {:?}", res),
+    }
+    assert!(assets.list_assets().unwrap().assets[1].liability);
+    assets.set_liability("Mortgage", true).unwrap();
+    match assets.set_liability("Unknown", true) {
+        Err(Error::AssetNotFound(_)) => {}
+        res => panic!("expected asset not found, got {:?}", res),
+    }
+}
+
+#[test]
+fn suspicious_fetches_are_held_back() {
+    // the first answers are used to validate the queries when adding the assets
+    let changing = MockPlugin::new("changing", Any)
+        .answers("price", vec![dec("100"), dec("100"), dec("200")])
+        .answers("broken", vec![dec("1"), dec("1"), dec("0")])
+        .answers("wallet", vec![dec("1"), dec("1"), dec("2")])
+        .answers("bought", vec![dec("1"), dec("1"), dec("2")]);
+    let mut assets = assets(vec![price_plugin(), changing]);
+    assets.set_cache(None);
+    assets
+        .add_asset("Jumpy", "other", "changing(price)", "changing(broken)")
+        .unwrap();
+    assets
+        .add_asset("Wallet", "crypto", "price(BTC)", "changing(wallet)")
+        .unwrap();
+    assets
+        .add_asset("Bought", "crypto", "price(ETH)", "changing(bought)")
+        .unwrap();
+    let mut add_tx = |asset, date| {
+        let tx = new_tx(TransactionKind::Buy, date, "1", "1");
+        assets.add_transaction(asset, &tx).unwrap();
+    };
+    add_tx("Wallet", NaiveDate::from_ymd(2020, 1, 1));
+    add_tx("Bought", chrono::Utc::today().naive_utc());
+
+    assert!(assets.fetch_data().unwrap().is_empty());
+    let first = assets.list_assets().unwrap();
+
+    let anomalies = match assets.fetch_data() {
+        Err(Error::Anomalies(anomalies)) => anomalies,
+        res => panic!("expected anomalies, got {:?}", res),
+    };
+    // nothing was stored
+    assert_eq!(assets.list_assets().unwrap().time, first.time);
+    let found = anomalies
+        .iter()
+        .map(|anomaly| (anomaly.asset.as_str(), anomaly.kind.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        vec![
+            ("Jumpy", AnomalyKind::HoldingsVanished { before: dec("1") }),
+            (
+                "Jumpy",
+                AnomalyKind::PriceJump {
+                    before: dec("100"),
+                    after: dec("200")
+                }
+            ),
+            (
+                "Wallet",
+                AnomalyKind::UnexplainedHoldingsChange {
+                    before: dec("1"),
+                    after: dec("2")
+                }
+            ),
+        ]
+    );
+
+    assets.accept_anomalies(true);
+    assert_eq!(assets.fetch_data().unwrap().len(), 3);
+    let snapshot = assets.list_assets().unwrap();
+    assert_eq!(snapshot.assets.len(), 2);
+    assert_eq!(snapshot.assets[1].holdings, dec("2"));
+}
+
+#[test]
+fn backfill_adds_missing_points_only() {
+    let day = 24 * 60 * 60;
+    let series = Value::Series(vec![
+        DataPoint {
+            timestamp: 1_577_836_800,
+            value: dec("7000"),
+        },
+        DataPoint {
+            timestamp: 1_577_836_800 + day,
+            value: dec("7100"),
+        },
+    ]);
+    // the first request checks the query when adding the asset
+    let history = MockPlugin::new("history", Price).behave(
+        "BTC",
+        Behavior::Sequence(vec![
+            Behavior::Answer(dec("10000").into()),
+            Behavior::Answer(series),
+        ]),
+    );
+    let mut assets = assets(vec![history, holdings_plugin()]);
+    assets
+        .add_asset("Bitcoin", "crypto", "history(BTC)", "holdings(cold)")
+        .unwrap();
+
+    let since = NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0);
+    assert_eq!(
+        assets.backfill("Bitcoin", since, Some(dec("0.5"))).unwrap(),
+        2
+    );
+    assert_eq!(
+        assets.backfill("Bitcoin", since, Some(dec("0.5"))).unwrap(),
+        0
+    );
+    let bundle = assets.export().unwrap();
+    assert_eq!(bundle.updates.len(), 2);
+    assert!(bundle.updates.iter().all(|update| update.synthetic));
+    assert_eq!(
+        bundle
+            .prices
+            .iter()
+            .map(|price| price.value)
+            .collect::<Vec<_>>(),
+        vec![dec("7000"), dec("7100")]
+    );
+}
+
+#[test]
+fn in_memory_store() {
+    let plugins = plugins(vec![price_plugin(), holdings_plugin()]);
+    let mut assets = Assets::with_store(MemoryStore::new(), plugins);
+    assets
+        .add_asset("Bitcoin", "crypto", "price(BTC)", "holdings(cold)")
+        .unwrap();
+    assets
+        .add_asset("Ether", "crypto", "price(ETH)", "holdings(hot)")
+        .unwrap();
+
+    let spreadsheet = "date,asset,value\n2019-01-01,Savings,1000\n2019-02-01,Savings,1200\n";
+    let summary = assets
+        .import_history(spreadsheet.as_bytes(), &ColumnMapping::default())
+        .unwrap();
+    assert_eq!(summary.updates, 2);
+    assert_eq!(summary.new_assets, vec!["Savings"]);
+
+    assets.accept_anomalies(true);
+    assets.fetch_data().unwrap();
+    let snapshot = assets.list_assets().unwrap();
+    let listed = snapshot
+        .assets
+        .iter()
+        .map(|asset| (asset.name.as_str(), asset.value()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        listed,
+        vec![("Bitcoin", dec("5000")), ("Ether", dec("600"))]
+    );
+
+    // everything beyond assets and their values needs a database
+    match assets.list_tags(None) {
+        Err(Error::NoDatabase) => {}
+        res => panic!("expected no database error, got {:?}", res),
+    }
+}
@@ -0,0 +1,83 @@
+//! History of updates and importing it from spreadsheets
+
+mod common;
+
+use assetman::history::ColumnMapping;
+use assetman::Error;
+use chrono::NaiveDate;
+use common::{assets, bitcoin_assets, dec, holdings_plugin, price_plugin};
+
+#[test]
+fn history_of_updates() {
+    let mut assets = assets(vec![price_plugin(), holdings_plugin()]);
+    assert!(assets.history(None, None).unwrap().is_empty());
+
+    assets
+        .add_asset("Bitcoin", "crypto", "price(BTC)", "holdings(cold)")
+        .unwrap();
+    assets.fetch_data().unwrap();
+    assets.fetch_data().unwrap();
+
+    let history = assets.history(None, None).unwrap();
+    assert_eq!(history.len(), 2);
+    assert!(history[0].time <= history[1].time);
+    assert_eq!(history[1].net_worth(), dec("5000"));
+
+    let future = NaiveDate::from_ymd(2100, 1, 1).and_hms(0, 0, 0);
+    assert!(assets.history(Some(future), None).unwrap().is_empty());
+    assert_eq!(assets.history(None, Some(future)).unwrap().len(), 2);
+}
+
+#[test]
+fn history_from_spreadsheet() {
+    let mut assets = bitcoin_assets();
+    let spreadsheet = "\
+date;asset;holdings;price;value;category
+01.01.2019;Bitcoin;0,5;3.000,00;;
+01.01.2019;Savings;;;10.000,50;cash
+01.01.2019;House;1;;250.000;real estate
+01.02.2019;Bitcoin;0,5;;1.500;
+01.02.2019;Savings;;;9.000;
+yesterday;Savings;;;9.000;
+01.03.2019;Savings;;;;
+01.02.2019;Bitcoin;1;1;;
+01.03.2019;Gold;;0,0000000000000000000000000001;70.000.000.000.000.000.000.000.000.000;
+";
+    let mapping = ColumnMapping {
+        date_format: "%d.%m.%Y".to_string(),
+        delimiter: b';',
+        decimal_comma: true,
+        ..ColumnMapping::default()
+    };
+    let summary = assets
+        .import_history(spreadsheet.as_bytes(), &mapping)
+        .unwrap();
+    assert_eq!(summary.updates, 2);
+    assert_eq!(summary.rows, 5);
+    assert_eq!(summary.new_assets, vec!["Savings", "House"]);
+    let skipped = summary
+        .skipped
+        .iter()
+        .map(|skipped| skipped.line)
+        .collect::<Vec<_>>();
+    // an unparsable date, a row without numbers, a second row for the same asset and date and
+    // holdings too large for a decimal
+    assert_eq!(skipped, vec![7, 8, 9, 10]);
+    assert!(summary.skipped[3].reason.contains("out of range"));
+
+    // imported updates are regular history, new assets are archived and not fetched
+    let history = assets.returns(None, None).unwrap();
+    assert_eq!(history.portfolio.start_value, dec("261500.50"));
+    assert_eq!(history.portfolio.end_value, dec("10500"));
+    // the first fetch is compared to the imported history, which is years old
+    assets.accept_anomalies(true);
+    assets.fetch_data().unwrap();
+    let snapshot = assets.list_assets().unwrap();
+    assert_eq!(snapshot.assets.len(), 1);
+    assert_eq!(snapshot.assets[0].name, "Bitcoin");
+
+    match assets.import_history("day,asset,price\n".as_bytes(), &ColumnMapping::default()) {
+        Err(Error::InvalidSpreadsheet(_)) => {}
+        res => panic!("expected missing date column, got {:?}", res),
+    }
+}
@@ -0,0 +1,120 @@
+//! Backups, checking the database and compacting the history
+
+mod common;
+
+use assetman::history::ColumnMapping;
+use assetman::maintenance::{Defect, Problem, Row};
+use assetman::retention::RetentionPolicy;
+use assetman::sync::{AssetDecl, SyncChange};
+use assetman::{Assets, Error};
+use common::{assets, bitcoin_assets, dec, holdings_plugin, plugins, price_plugin};
+
+#[test]
+fn backup_database() {
+    let dir = std::env::temp_dir().join(format!("assetman-backup-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let backup = dir.join("backup.sqlite");
+
+    let mut source = bitcoin_assets();
+    source.fetch_data().unwrap();
+    source.backup_database(&backup).unwrap();
+    // an existing backup is never overwritten
+    match source.backup_database(&backup) {
+        Err(Error::Io(path, _)) => assert_eq!(path, backup),
+        res => panic!("expected existing file error, got {:?}", res),
+    }
+
+    let plugins = plugins(vec![price_plugin()]);
+    let restored = Assets::with_plugins(backup.to_str().unwrap(), plugins).unwrap();
+    let snapshot = restored.list_assets().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(snapshot.net_worth(), dec("5000"));
+    assert!(restored.check_database().unwrap().is_empty());
+}
+
+#[test]
+fn archived_assets_are_not_repaired() {
+    let mut assets = assets(vec![price_plugin(), holdings_plugin()]);
+    assets.accept_anomalies(true);
+    let decl = |name: &str, holdings: &str| AssetDecl {
+        name: name.to_string(),
+        category: "crypto".to_string(),
+        price_query: "price(BTC)".to_string(),
+        holdings_query: holdings.to_string(),
+        liability: false,
+        tags: Default::default(),
+    };
+    let (cold, hot) = (decl("Cold", "holdings(cold)"), decl("Hot", "holdings(hot)"));
+    let declared = vec![cold.clone(), hot];
+    let changes = assets.plan_sync(&declared).unwrap();
+    assets.apply_sync(&changes).unwrap();
+    assets.fetch_data().unwrap();
+
+    // Hot is archived for one fetch and restored afterwards
+    let changes = assets.plan_sync(&[cold]).unwrap();
+    assert_eq!(changes, vec![SyncChange::Archive("Hot".to_string())]);
+    assets.apply_sync(&changes).unwrap();
+    assets.fetch_data().unwrap();
+    let changes = assets.plan_sync(&declared).unwrap();
+    assert_eq!(changes, vec![SyncChange::Restore("Hot".to_string())]);
+    assets.apply_sync(&changes).unwrap();
+    assets.fetch_data().unwrap();
+
+    let problems = assets.check_database().unwrap();
+    assert_eq!(
+        problems,
+        vec![Problem {
+            row: Row::Update(2),
+            defect: Defect::MissingValues(2),
+        }]
+    );
+    assert!(!problems[0].is_repairable());
+
+    // the update with only Cold's values is kept
+    assets.repair_database(&problems).unwrap();
+    assert_eq!(assets.check_database().unwrap(), problems);
+    let history = assets.history(None, None).unwrap();
+    let values = history
+        .iter()
+        .map(|snapshot| snapshot.net_worth())
+        .collect::<Vec<_>>();
+    assert_eq!(values, vec![dec("25000"), dec("5000"), dec("25000")]);
+}
+
+#[test]
+fn compact_history() {
+    let mut assets = bitcoin_assets();
+    let spreadsheet = "\
+date,asset,holdings,price
+2019-01-01,Bitcoin,1,3000
+2019-02-01,Bitcoin,1,3500
+2019-02-10,Bitcoin,1,3600
+2019-02-20,Bitcoin,1,3700
+";
+    assets
+        .import_history(spreadsheet.as_bytes(), &ColumnMapping::default())
+        .unwrap();
+    assets.accept_anomalies(true);
+    assets.fetch_data().unwrap();
+    let before = assets.returns(None, None).unwrap();
+
+    let policy = "30=all,monthly".parse::<RetentionPolicy>().unwrap();
+    let planned = assets.compact(&policy, true).unwrap();
+    assert_eq!(planned.updates, 2);
+    assert_eq!(planned.values, 2);
+    assert_eq!(planned.kept, 3);
+    assert_eq!(assets.compact(&policy, false).unwrap(), planned);
+    assert_eq!(assets.compact(&policy, false).unwrap().updates, 0);
+
+    // the first update and the last one of February are kept, so returns stay the same
+    let after = assets.returns(None, None).unwrap();
+    assert_eq!(after.portfolio.start_value, before.portfolio.start_value);
+    assert_eq!(after.portfolio.end_value, before.portfolio.end_value);
+    assert!(assets.check_database().unwrap().is_empty());
+
+    // with a policy set every fetch compacts, replacing the earlier fetch of the same day
+    assets.set_retention(Some("daily".parse().unwrap()));
+    assets.fetch_data().unwrap();
+    let all = "all".parse::<RetentionPolicy>().unwrap();
+    assert_eq!(assets.compact(&all, true).unwrap().kept, 3);
+}
@@ -0,0 +1,139 @@
+//! Plugin handshakes, caching of answers and checking queries
+
+mod common;
+
+use assetman::{CacheCfg, Error, PluginError, Plugins};
+use assetman_api::PluginType::{Any, Holdings, Price};
+use assetman_mock::{Behavior, MockPlugin};
+use common::{assets, dec, holdings_plugin, logged_arguments, price_plugin};
+use std::time::Duration;
+
+#[test]
+fn broken_handshake() {
+    let broken = vec![
+        MockPlugin::new("crash", Price)
+            .handshake(Behavior::Crash)
+            .spawn()
+            .unwrap(),
+        MockPlugin::new("garbage", Price)
+            .handshake(Behavior::Malformed("{\"name\": 1}".to_string()))
+            .spawn()
+            .unwrap(),
+    ];
+
+    for plugin in broken {
+        match Plugins::from_streams(std::iter::once(plugin)) {
+            Err(PluginError::BadHandshake(_)) => {}
+            res => panic!("expected bad answer, got {:?}", res),
+        }
+    }
+}
+
+#[test]
+fn answers_are_cached() {
+    let price = price_plugin();
+    let holdings = holdings_plugin();
+    let price_requests = logged_arguments(&price);
+    let holdings_requests = logged_arguments(&holdings);
+    let mut assets = assets(vec![price, holdings]);
+    let mut cache = CacheCfg::default();
+    cache
+        .plugin_ttl
+        .insert("holdings".to_string(), Duration::from_secs(0));
+    assets.set_cache(Some(cache));
+
+    assets
+        .add_asset("Bitcoin", "crypto", "price(BTC)", "holdings(cold)")
+        .unwrap();
+    assets.fetch_data().unwrap();
+    assert_eq!(price_requests(), vec!["BTC"]);
+    assert_eq!(holdings_requests(), vec!["cold", "cold"]);
+
+    // a cached price answer can't be used as holdings
+    match assets.query("price(BTC)", Holdings) {
+        Err(Error::PluginError(PluginError::WrongType { .. })) => {}
+        res => panic!("expected wrong type, got {:?}", res),
+    }
+
+    assets.set_cache(None);
+    assert_eq!(
+        assets.query("price(BTC)", Price).unwrap(),
+        dec("10000").into()
+    );
+    assert_eq!(price_requests(), vec!["BTC", "BTC"]);
+}
+
+#[test]
+fn answers_are_cached_per_type() {
+    let mut assets = assets(vec![
+        MockPlugin::new("any", Any).answers("BTC", vec![dec("10000"), dec("2")])
+    ]);
+    assets.set_cache(Some(CacheCfg::default()));
+
+    assert_eq!(
+        assets.query("any(BTC)", Price).unwrap(),
+        dec("10000").into()
+    );
+    // the cached price must not be mistaken for holdings of the same arguments
+    assert_eq!(assets.query("any(BTC)", Holdings).unwrap(), dec("2").into());
+    assert_eq!(
+        assets.query("any(BTC)", Price).unwrap(),
+        dec("10000").into()
+    );
+    assert_eq!(assets.query("any(BTC)", Holdings).unwrap(), dec("2").into());
+}
+
+#[test]
+fn cache_ttl_parsing() {
+    let cache = "600, bitstamp=60,csv_scan=0".parse::<CacheCfg>().unwrap();
+    assert_eq!(cache.ttl("static"), Duration::from_secs(600));
+    assert_eq!(cache.ttl("bitstamp"), Duration::from_secs(60));
+    assert_eq!(cache.ttl("csv_scan"), Duration::from_secs(0));
+    assert!("bitstamp=soon".parse::<CacheCfg>().is_err());
+}
+
+#[test]
+fn check_queries_reports_every_query() {
+    let mut assets = assets(vec![
+        MockPlugin::new("price", Price).behave(
+            "BTC",
+            Behavior::Sequence(vec![
+                Behavior::Answer(dec("10000").into()),
+                Behavior::Error {
+                    code: 7,
+                    description: "rate limited".to_string(),
+                },
+            ]),
+        ),
+        holdings_plugin(),
+    ]);
+    assets
+        .add_asset("Bitcoin", "crypto", "price(BTC)", "holdings(cold)")
+        .unwrap();
+    assets
+        .add_asset("Altcoin", "crypto", "holdings(hot)", "holdings(hot)")
+        .unwrap_err();
+
+    let checks = assets.check_queries().unwrap();
+    assert_eq!(checks.len(), 2);
+    assert_eq!(checks[0].query, "price(BTC)");
+    match &checks[0].result {
+        Err(PluginError::PluginError(_, e)) => assert_eq!(e.code, 7),
+        res => panic!("expected plugin error, got {:?}", res),
+    }
+    assert_eq!(checks[1].query, "holdings(cold)");
+    assert_eq!(checks[1].result.as_ref().unwrap(), &dec("0.5"));
+}
+
+#[test]
+fn duplicate_plugin_names() {
+    let plugins = vec![
+        MockPlugin::new("price", Price).spawn().unwrap(),
+        MockPlugin::new("price", Holdings).spawn().unwrap(),
+    ];
+    // like before, the plugin loaded last replaces the earlier ones
+    let plugins = Plugins::from_streams(plugins.into_iter()).unwrap();
+    let infos = plugins.infos().collect::<Vec<_>>();
+    assert_eq!(infos.len(), 1);
+    assert_eq!(infos[0].plugin_type, Holdings);
+}
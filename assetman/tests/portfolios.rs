@@ -0,0 +1,119 @@
+//! Tags, groups and portfolios owning shares of assets
+
+mod common;
+
+use assetman::transactions::{CostBasisMethod, TransactionKind};
+use assetman::Error;
+use assetman_api::Decimal;
+use chrono::NaiveDate;
+use common::{bitcoin_assets, dec, new_tx};
+
+#[test]
+fn tags_and_nested_groups() {
+    let mut assets = bitcoin_assets();
+    assets
+        .add_asset("Ether", "crypto", "price(ETH)", "holdings(hot)")
+        .unwrap();
+    assets
+        .add_asset("Slow", "other", "price(slow)", "holdings(hot)")
+        .unwrap();
+    assets.set_tag("Bitcoin", "owner", "alice").unwrap();
+    assets.set_tag("Ether", "owner", "bob").unwrap();
+    assets.set_tag("Ether", "owner", "alice").unwrap();
+    assets.set_tag("Ether", "custodian", "exchange").unwrap();
+    match assets.set_tag("Ether", "category", "defi") {
+        Err(Error::InvalidTag(_)) => {}
+        res => panic!("expected invalid tag, got {:?}", res),
+    }
+    match assets.set_tag("Unknown", "owner", "alice") {
+        Err(Error::AssetNotFound(_)) => {}
+        res => panic!("expected asset not found, got {:?}", res),
+    }
+
+    let tags = assets.list_tags(Some("Ether")).unwrap();
+    assert_eq!(
+        tags.iter()
+            .map(|tag| (tag.key.as_str(), tag.value.as_str()))
+            .collect::<Vec<_>>(),
+        vec![("custodian", "exchange"), ("owner", "alice")]
+    );
+    assets.remove_tag("Ether", "custodian").unwrap();
+    match assets.remove_tag("Ether", "custodian") {
+        Err(Error::InvalidTag(_)) => {}
+        res => panic!("expected invalid tag, got {:?}", res),
+    }
+    assert_eq!(assets.list_tags(None).unwrap().len(), 2);
+
+    assets.fetch_data().unwrap();
+    let snapshot = assets.list_assets().unwrap();
+    let groups = assets
+        .group_assets(
+            snapshot.assets,
+            &["owner".to_string(), "category".to_string()],
+        )
+        .unwrap();
+    // untagged assets are grouped too, "(untagged)" sorts before letters
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].value, "(untagged)");
+    assert_eq!(groups[0].value(), dec("4"));
+    let alice = &groups[1];
+    assert_eq!(alice.value, "alice");
+    assert!(alice.assets.is_empty());
+    assert_eq!(alice.groups.len(), 1);
+    assert_eq!(alice.groups[0].key, "category");
+    assert_eq!(alice.groups[0].value, "crypto");
+    assert_eq!(alice.groups[0].assets.len(), 2);
+    assert_eq!(alice.value(), dec("5600"));
+}
+
+#[test]
+fn portfolios_with_shared_assets() {
+    let mut assets = bitcoin_assets();
+    assets
+        .add_asset("Ether", "crypto", "price(ETH)", "holdings(hot)")
+        .unwrap();
+    assets
+        .set_ownership("Bitcoin", "alice", Decimal::ONE)
+        .unwrap();
+    assets.set_ownership("Ether", "alice", dec("0.5")).unwrap();
+    assets.set_ownership("Ether", "bob", dec("0.5")).unwrap();
+    match assets.set_ownership("Ether", "bob", dec("0.6")) {
+        Err(Error::InvalidOwnership(_)) => {}
+        res => panic!("expected invalid ownership, got {:?}", res),
+    }
+    match assets.select_portfolio(Some("carol")) {
+        Err(Error::PortfolioNotFound(_)) => {}
+        res => panic!("expected portfolio not found, got {:?}", res),
+    }
+    assert_eq!(assets.list_portfolios().unwrap(), vec!["alice", "bob"]);
+
+    let tx = new_tx(
+        TransactionKind::Buy,
+        NaiveDate::from_ymd(2020, 6, 1),
+        "2",
+        "200",
+    );
+    assets.add_transaction("Ether", &tx).unwrap();
+    assets.fetch_data().unwrap();
+
+    let snapshots = assets.portfolio_snapshots().unwrap();
+    assert_eq!(snapshots["alice"].net_worth(), dec("5300"));
+    assert_eq!(snapshots["bob"].net_worth(), dec("300"));
+
+    assets.select_portfolio(Some("bob")).unwrap();
+    let snapshot = assets.list_assets().unwrap();
+    assert_eq!(snapshot.assets.len(), 1);
+    assert_eq!(snapshot.assets[0].holdings, dec("1"));
+    let basis = assets.cost_basis("Ether", CostBasisMethod::Fifo).unwrap();
+    assert_eq!((basis.amount, basis.cost), (dec("1"), dec("200")));
+    assert!(assets
+        .list_transactions(Some("Bitcoin"))
+        .unwrap()
+        .is_empty());
+
+    // the combined view isn't scaled
+    assets.select_portfolio(None).unwrap();
+    assert_eq!(assets.list_assets().unwrap().net_worth(), dec("5600"));
+    assets.remove_ownership("Ether", "bob").unwrap();
+    assert_eq!(assets.list_portfolios().unwrap(), vec!["alice"]);
+}
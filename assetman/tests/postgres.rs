@@ -0,0 +1,166 @@
+//! Runs against the PostgreSQL server at `ASSETMAN_TEST_POSTGRES`, e.g.
+//! `postgres://postgres@localhost/postgres`, and is skipped if it isn't set. Every test creates a
+//! database of its own and drops it afterwards. CI runs them against a PostgreSQL service.
+#![cfg(feature = "postgres")]
+
+mod common;
+
+use assetman::alerts::Condition;
+use assetman::allocation::{Target, TargetKind};
+use assetman::bundle::OnConflict;
+use assetman::history::ColumnMapping;
+use assetman::retention::RetentionPolicy;
+use assetman::transactions::TransactionKind;
+use assetman::{Assets, Error};
+use chrono::NaiveDate;
+use common::{assets, dec, holdings_plugin, new_tx, plugins, price_plugin};
+use diesel::pg::PgConnection;
+use diesel::{Connection, RunQueryDsl};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static DATABASES: AtomicUsize = AtomicUsize::new(0);
+
+struct TestDb {
+    server: PgConnection,
+    name: String,
+    url: String,
+}
+
+impl TestDb {
+    fn create() -> Option<TestDb> {
+        let server_url = match std::env::var("ASSETMAN_TEST_POSTGRES") {
+            Ok(url) => url,
+            Err(_) => {
+                eprintln!("ASSETMAN_TEST_POSTGRES isn't set, skipping");
+                return None;
+            }
+        };
+        let server = PgConnection::establish(&server_url).unwrap();
+        let name = format!(
+            "assetman_test_{}_{}",
+            std::process::id(),
+            DATABASES.fetch_add(1, Ordering::SeqCst)
+        );
+        diesel::sql_query(format!("DROP DATABASE IF EXISTS {}", name))
+            .execute(&server)
+            .unwrap();
+        diesel::sql_query(format!("CREATE DATABASE {}", name))
+            .execute(&server)
+            .unwrap();
+        let base = &server_url[..server_url.rfind('/').unwrap()];
+        let url = format!("{}/{}", base, name);
+        Some(TestDb { server, name, url })
+    }
+
+    fn assets(&self) -> Assets {
+        let plugins = plugins(vec![price_plugin(), holdings_plugin()]);
+        Assets::with_plugins(&self.url, plugins).unwrap()
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = diesel::sql_query(format!("DROP DATABASE IF EXISTS {}", self.name))
+            .execute(&self.server);
+    }
+}
+
+#[test]
+fn fetch_and_edit_on_postgres() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut assets = db.assets();
+    assets
+        .add_asset("Bitcoin", "crypto", "price(BTC)", "holdings(cold)")
+        .unwrap();
+    assets
+        .add_asset("Ether", "crypto", "price(ETH)", "holdings(hot)")
+        .unwrap();
+    assets.fetch_data().unwrap();
+    // the second fetch is answered from the cache
+    assets.fetch_data().unwrap();
+    let snapshot = assets.list_assets().unwrap();
+    assert_eq!(snapshot.net_worth(), dec("5600"));
+
+    // updating existing rows works without SQLite's REPLACE
+    assets.set_tag("Ether", "owner", "bob").unwrap();
+    assets.set_tag("Ether", "owner", "alice").unwrap();
+    assert_eq!(assets.list_tags(Some("Ether")).unwrap()[0].value, "alice");
+    assets.set_ownership("Ether", "alice", dec("0.5")).unwrap();
+    assets.set_ownership("Ether", "alice", dec("0.25")).unwrap();
+    assert_eq!(assets.list_ownership().unwrap()[0].share, dec("0.25"));
+    let target = |weight| Target {
+        kind: TargetKind::Asset,
+        name: "Bitcoin".to_string(),
+        weight: dec(weight),
+        tolerance: dec("0.05"),
+    };
+    assets.set_target(&target("0.5")).unwrap();
+    assets.set_target(&target("0.8")).unwrap();
+    assert_eq!(assets.list_targets().unwrap()[0].weight, dec("0.8"));
+
+    let buy = |day| {
+        new_tx(
+            TransactionKind::Buy,
+            NaiveDate::from_ymd(2020, 1, day),
+            "0.25",
+            "8000",
+        )
+    };
+    let first = assets.add_transaction("Bitcoin", &buy(1)).unwrap();
+    let second = assets.add_transaction("Bitcoin", &buy(2)).unwrap();
+    let listed = assets.list_transactions(Some("Bitcoin")).unwrap();
+    assert_eq!(
+        listed.iter().map(|tx| tx.id).collect::<Vec<_>>(),
+        vec![first, second]
+    );
+
+    let rule = assets
+        .add_alert_rule(&Condition::TotalDrop(dec("0.1")))
+        .unwrap();
+    assert_eq!(assets.list_alert_rules().unwrap()[0].id, rule);
+    assets.check_alerts().unwrap();
+
+    assert!(assets.check_database().unwrap().is_empty());
+    // the first update is always kept, the second one of today goes with a daily policy
+    assets.fetch_data().unwrap();
+    let daily = "daily".parse::<RetentionPolicy>().unwrap();
+    assert_eq!(assets.compact(&daily, false).unwrap().updates, 1);
+    match assets.backup_database(std::path::Path::new("/nonexistent/backup")) {
+        Err(Error::InvalidConfig("database", _)) => {}
+        res => panic!("expected pg_dump hint, got {:?}", res),
+    }
+}
+
+#[test]
+fn bundle_from_sqlite_to_postgres() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut sqlite = assets(vec![price_plugin(), holdings_plugin()]);
+    sqlite
+        .add_asset("Bitcoin", "crypto", "price(BTC)", "holdings(cold)")
+        .unwrap();
+    sqlite.set_tag("Bitcoin", "owner", "alice").unwrap();
+    sqlite.fetch_data().unwrap();
+    let bundle = sqlite.export().unwrap();
+
+    let mut postgres = db.assets();
+    postgres.import(&bundle, OnConflict::Abort).unwrap();
+    let exported = postgres.export().unwrap();
+    assert_eq!(exported.assets, bundle.assets);
+    assert_eq!(exported.tags, bundle.tags);
+    assert_eq!(exported.prices, bundle.prices);
+    let summary = postgres.import(&bundle, OnConflict::Replace).unwrap();
+    assert_eq!(summary.added, 0);
+
+    let spreadsheet = "date,asset,value\n2019-01-01,Savings,1000\n2019-02-01,Savings,1200\n";
+    let summary = postgres
+        .import_history(spreadsheet.as_bytes(), &ColumnMapping::default())
+        .unwrap();
+    assert_eq!(summary.updates, 2);
+    assert_eq!(summary.new_assets, vec!["Savings"]);
+}
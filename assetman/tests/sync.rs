@@ -0,0 +1,87 @@
+//! Syncing assets declared in the config file
+
+mod common;
+
+use assetman::sync::{AssetDecl, AssetField, SyncChange};
+use assetman::Error;
+use common::{assets, holdings_plugin, price_plugin};
+
+#[test]
+fn declared_assets_are_synced() {
+    let mut assets = assets(vec![price_plugin(), holdings_plugin()]);
+    assets
+        .add_asset("Cold", "crypto", "price(BTC)", "holdings(cold)")
+        .unwrap();
+    assets
+        .add_asset("Old", "crypto", "price(ETH)", "holdings(hot)")
+        .unwrap();
+    assets.set_tag("Cold", "owner", "alice").unwrap();
+
+    let decl = |name: &str, category: &str, price: &str, holdings: &str| AssetDecl {
+        name: name.to_string(),
+        category: category.to_string(),
+        price_query: price.to_string(),
+        holdings_query: holdings.to_string(),
+        liability: false,
+        tags: Default::default(),
+    };
+    let mut cold = decl("Cold", "savings", "price(BTC)", "holdings(cold)");
+    cold.tags
+        .insert("custodian".to_string(), "self".to_string());
+    let hot = decl("Hot", "crypto", "price(BTC)", "holdings(hot)");
+    let declared = vec![hot.clone(), cold];
+
+    let changes = assets.plan_sync(&declared).unwrap();
+    let update = |field, before: Option<&str>, after: Option<&str>| SyncChange::Update {
+        asset: "Cold".to_string(),
+        field,
+        before: before.map(str::to_string),
+        after: after.map(str::to_string),
+    };
+    assert_eq!(
+        changes,
+        vec![
+            update(AssetField::Category, Some("crypto"), Some("savings")),
+            update(AssetField::Tag("custodian".to_string()), None, Some("self")),
+            update(AssetField::Tag("owner".to_string()), Some("alice"), None),
+            SyncChange::Add(hot),
+            SyncChange::Archive("Old".to_string()),
+        ]
+    );
+
+    assets.apply_sync(&changes).unwrap();
+    assert!(assets.plan_sync(&declared).unwrap().is_empty());
+    assets.fetch_data().unwrap();
+    let snapshot = assets.list_assets().unwrap();
+    let listed = snapshot
+        .assets
+        .iter()
+        .map(|asset| (asset.name.as_str(), asset.category.as_str()))
+        .collect::<Vec<_>>();
+    // archived assets aren't fetched anymore
+    assert_eq!(listed, vec![("Cold", "savings"), ("Hot", "crypto")]);
+    let tags = assets.list_tags(Some("Cold")).unwrap();
+    assert_eq!(tags.len(), 1);
+    assert_eq!(tags[0].key, "custodian");
+
+    // declaring an archived asset again restores it
+    let mut declared = declared;
+    declared.push(decl("Old", "crypto", "price(ETH)", "holdings(hot)"));
+    let changes = assets.plan_sync(&declared).unwrap();
+    assert_eq!(changes, vec![SyncChange::Restore("Old".to_string())]);
+
+    // nothing is changed if a query doesn't work
+    declared.push(decl("Broken", "crypto", "price(XXX)", "holdings(hot)"));
+    let changes = assets.plan_sync(&declared).unwrap();
+    match assets.apply_sync(&changes) {
+        Err(Error::QueryError { asset, .. }) => assert_eq!(asset, "Broken"),
+        res => panic!("expected query error, got {:?}", res),
+    }
+    assert_eq!(assets.plan_sync(&declared).unwrap(), changes);
+
+    declared.push(decl("Hot", "crypto", "price(BTC)", "holdings(hot)"));
+    match assets.plan_sync(&declared) {
+        Err(Error::InvalidConfig("assets", _)) => {}
+        res => panic!("expected duplicate declaration, got {:?}", res),
+    }
+}
@@ -0,0 +1,123 @@
+//! Transactions, cost basis and gains
+
+mod common;
+
+use assetman::transactions::{CostBasisMethod, TransactionKind};
+use assetman::Error;
+use chrono::NaiveDate;
+use common::{bitcoin_assets, dec, new_tx};
+
+#[test]
+fn transactions_add_list_rm() {
+    let mut assets = bitcoin_assets();
+    let tx = |kind, day, amount| new_tx(kind, NaiveDate::from_ymd(2020, 1, day), amount, "8000");
+
+    let sell = assets
+        .add_transaction("Bitcoin", &tx(TransactionKind::Sell, 2, "0.5"))
+        .unwrap();
+    let buy = assets
+        .add_transaction("Bitcoin", &tx(TransactionKind::Buy, 1, "1"))
+        .unwrap();
+    let listed = assets.list_transactions(Some("Bitcoin")).unwrap();
+    assert_eq!(
+        listed.iter().map(|tx| tx.id).collect::<Vec<_>>(),
+        vec![buy, sell]
+    );
+    let basis = assets
+        .cost_basis("Bitcoin", CostBasisMethod::Average)
+        .unwrap();
+    assert_eq!(basis.amount, dec("0.5"));
+    assert_eq!(basis.cost, dec("4000"));
+
+    let mut usd = tx(TransactionKind::Buy, 3, "1");
+    usd.currency = "USD".to_string();
+    match assets.add_transaction("Bitcoin", &usd) {
+        Err(Error::InvalidTransaction(_)) => {}
+        res => panic!("expected invalid transaction, got {:?}", res),
+    }
+    match assets.add_transaction("Ether", &tx(TransactionKind::Buy, 3, "1")) {
+        Err(Error::AssetNotFound(_)) => {}
+        res => panic!("expected unknown asset, got {:?}", res),
+    }
+
+    assets.remove_transaction(buy).unwrap();
+    match assets.remove_transaction(buy) {
+        Err(Error::TransactionNotFound(id)) => assert_eq!(id, buy),
+        res => panic!("expected unknown transaction, got {:?}", res),
+    }
+    // without the buy the sell removes units that were never held
+    match assets.cost_basis("Bitcoin", CostBasisMethod::Fifo) {
+        Err(Error::InvalidTransaction(_)) => {}
+        res => panic!("expected invalid transaction, got {:?}", res),
+    }
+}
+
+#[test]
+fn pnl_report() {
+    let mut assets = bitcoin_assets();
+    assets
+        .add_asset("Ether", "crypto", "price(ETH)", "holdings(hot)")
+        .unwrap();
+    let mut add_tx = |asset, kind, year, amount, price| {
+        let tx = new_tx(kind, NaiveDate::from_ymd(year, 6, 1), amount, price);
+        assets.add_transaction(asset, &tx).unwrap();
+    };
+    add_tx("Bitcoin", TransactionKind::Buy, 2019, "1", "4000");
+    add_tx("Bitcoin", TransactionKind::Sell, 2020, "0.5", "9000");
+    add_tx("Ether", TransactionKind::Buy, 2020, "2", "200");
+
+    // without prices everything counts at cost
+    let report = assets.pnl(CostBasisMethod::Fifo).unwrap();
+    assert_eq!(report.assets[0].market_value, None);
+    assert_eq!(report.total.unrealized, dec("0"));
+
+    assets.fetch_data().unwrap();
+    let report = assets.pnl(CostBasisMethod::Fifo).unwrap();
+    assert_eq!(report.assets.len(), 2);
+    let bitcoin = &report.assets[0];
+    assert_eq!(bitcoin.amount, dec("0.5"));
+    assert_eq!(bitcoin.market_value, Some(dec("5000")));
+    assert_eq!(bitcoin.unrealized(), Some(dec("3000")));
+    assert_eq!(bitcoin.realized_total(), dec("2500"));
+    assert_eq!(report.realized.get(&2020), Some(&dec("2500")));
+    assert_eq!(report.assets[1].unrealized(), Some(dec("200")));
+
+    let crypto = &report.categories["crypto"];
+    assert_eq!(crypto.market_value, dec("5600"));
+    assert_eq!(report.total.total_return(), dec("5700"));
+    assert_eq!(
+        report
+            .total
+            .total_return_rate()
+            .map(|rate| rate.round_dp(4)),
+        Some(dec("1.2955"))
+    );
+
+    // prices are in the base currency, so gains can't be computed for other currencies
+    assets.set_base_currency(Some("USD".to_string()));
+    match assets.pnl(CostBasisMethod::Fifo) {
+        Err(Error::InvalidTransaction(_)) => {}
+        res => panic!("expected currency mismatch, got {:?}", res),
+    }
+    assets.set_base_currency(None);
+    assets
+        .add_asset("Cash", "cash", "price(EUR)", "holdings(empty)")
+        .unwrap();
+    let mut tx = new_tx(
+        TransactionKind::Deposit,
+        NaiveDate::from_ymd(2020, 1, 1),
+        "100",
+        "1",
+    );
+    tx.currency = "USD".to_string();
+    assets.add_transaction("Cash", &tx).unwrap();
+    match assets.pnl(CostBasisMethod::Fifo) {
+        Err(Error::InvalidTransaction(_)) => {}
+        res => panic!("expected mixed currencies, got {:?}", res),
+    }
+    // cash flows are compared to prices as well
+    match assets.returns(None, None) {
+        Err(Error::InvalidTransaction(_)) => {}
+        res => panic!("expected mixed currencies, got {:?}", res),
+    }
+}
@@ -1,4 +1,4 @@
-use assetman_api::{Answer, PluginInfo, PluginType, Request};
+use assetman_api::{Answer, Decimal, PluginInfo, PluginType, Request};
 use electrum_client::{Descriptor, ElectrumApi};
 use log::debug;
 use miniscript::bitcoin::util::bip32::ChildNumber;
@@ -72,10 +72,10 @@ fn main() {
 
                         internal + external
                     })
-                    .sum::<u64>() as f64;
+                    .sum::<u64>();
 
                 Ok(Answer {
-                    answer: amount_sat / 100_000_000.0,
+                    answer: Decimal::new(amount_sat as i64, 8).into(),
                 })
             },
         )
@@ -1,4 +1,4 @@
-use assetman_api::{Answer, PluginInfo, PluginType, Request};
+use assetman_api::{Answer, DataPoint, Decimal, PluginInfo, PluginType, Request, TimeRange};
 use reqwest::blocking::get;
 use serde_json::{de::Deserializer, to_writer, Value};
 use std::collections::HashMap;
@@ -8,7 +8,7 @@ fn main() {
     let mut stdout = stdout();
     let mut stdin = stdin();
 
-    let mut cache = HashMap::<String, f64>::new();
+    let mut cache = HashMap::<String, Decimal>::new();
 
     let info = PluginInfo {
         name: "bitstamp".to_string(),
@@ -28,8 +28,16 @@ fn main() {
                     description: format!("Input parsing error: {:?}", e),
                 })?;
 
+                if let Some(range) = req.history {
+                    return Ok(Answer {
+                        answer: assetman_api::Value::Series(fetch_history(&req.arguments, range)?),
+                    });
+                }
+
                 if let Some(price) = cache.get(&req.arguments) {
-                    return Ok(Answer { answer: *price });
+                    return Ok(Answer {
+                        answer: (*price).into(),
+                    });
                 }
 
                 let biststamp_resp = get(&format!(
@@ -47,7 +55,7 @@ fn main() {
                         code: 4,
                         description: format!("Invalid API response, parse error: {:?}", e),
                     })
-                    .and_then(|json: Value| -> Result<f64, assetman_api::Error> {
+                    .and_then(|json: Value| -> Result<Decimal, assetman_api::Error> {
                         json.get("bid")
                             .ok_or(assetman_api::Error {
                                 code: 5,
@@ -64,7 +72,7 @@ fn main() {
                             .map_err(|e| assetman_api::Error {
                                 code: 7,
                                 description: format!(
-                                    "Invalid API response: 'bid' is not a valid float string: {:?}",
+                                    "Invalid API response: 'bid' is not a valid decimal string: {:?}",
                                     e
                                 ),
                             })
@@ -72,7 +80,9 @@ fn main() {
 
                 cache.insert(req.arguments, price);
 
-                Ok(Answer { answer: price })
+                Ok(Answer {
+                    answer: price.into(),
+                })
             },
         )
         .for_each(|resp| {
@@ -80,3 +90,68 @@ fn main() {
             stdout.flush().unwrap();
         });
 }
+
+/// Maximum number of candles the OHLC endpoint returns per request
+const OHLC_LIMIT: i64 = 1000;
+const DAY: i64 = 86400;
+
+/// Fetches daily closing prices from the OHLC endpoint, paging through the range if it's longer
+/// than `OHLC_LIMIT` days
+fn fetch_history(pair: &str, range: TimeRange) -> Result<Vec<DataPoint>, assetman_api::Error> {
+    let mut series = Vec::new();
+    let mut start = range.start;
+
+    while start <= range.end {
+        let end = std::cmp::min(range.end, start + (OHLC_LIMIT - 1) * DAY);
+        let bitstamp_resp = get(&format!(
+            "https://www.bitstamp.net/api/v2/ohlc/{}/?step={}&limit={}&start={}&end={}",
+            pair, DAY, OHLC_LIMIT, start, end
+        ))
+        .map_err(|e| assetman_api::Error {
+            code: 3,
+            description: format!("HTTP error: {:?}", e),
+        })?;
+
+        let json: Value = bitstamp_resp.json().map_err(|e| assetman_api::Error {
+            code: 4,
+            description: format!("Invalid API response, parse error: {:?}", e),
+        })?;
+
+        let candles = json
+            .get("data")
+            .and_then(|data| data.get("ohlc"))
+            .and_then(|ohlc| ohlc.as_array())
+            .ok_or(assetman_api::Error {
+                code: 8,
+                description: "Invalid API response, no 'data.ohlc' array.".to_string(),
+            })?;
+
+        for candle in candles {
+            let field = |name: &str| -> Result<&str, assetman_api::Error> {
+                candle
+                    .get(name)
+                    .and_then(|field| field.as_str())
+                    .ok_or(assetman_api::Error {
+                        code: 9,
+                        description: format!(
+                            "Invalid API response, no '{}' field in candle.",
+                            name
+                        ),
+                    })
+            };
+            let malformed = |e: &dyn std::fmt::Debug| assetman_api::Error {
+                code: 10,
+                description: format!("Invalid API response, malformed candle: {:?}", e),
+            };
+
+            series.push(DataPoint {
+                timestamp: field("timestamp")?.parse().map_err(|e| malformed(&e))?,
+                value: field("close")?.parse().map_err(|e| malformed(&e))?,
+            });
+        }
+
+        start = end + DAY;
+    }
+
+    Ok(series)
+}
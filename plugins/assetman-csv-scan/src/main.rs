@@ -1,4 +1,4 @@
-use assetman_api::{Answer, PluginInfo, PluginType, Request};
+use assetman_api::{Answer, Decimal, PluginInfo, PluginType, Request};
 use serde_json::{de::Deserializer, to_writer};
 use std::io::{stdin, stdout, BufRead, BufReader, Write};
 
@@ -55,7 +55,7 @@ fn main() {
                     let cells: Vec<&str> = line.split(';').collect();
 
                     if cells.get(search_col as usize) == Some(&search_term) {
-                        let value = cells
+                        let value: Decimal = cells
                             .get(return_col as usize)
                             .ok_or(assetman_api::Error {
                                 code: 7,
@@ -66,12 +66,14 @@ fn main() {
                             .map_err(|e| assetman_api::Error {
                                 code: 8,
                                 description: format!(
-                                    "CSV parsing error: return col isn't a float. {:?}",
+                                    "CSV parsing error: return col isn't a number. {:?}",
                                     e
                                 ),
                             })?;
 
-                        return Ok(Answer { answer: value });
+                        return Ok(Answer {
+                            answer: value.into(),
+                        });
                     }
                 }
                 Err(assetman_api::Error {
@@ -1,4 +1,7 @@
-use assetman_api::{Answer, PluginInfo, PluginType, Request};
+use assetman_api::{
+    from_float, Answer, DataPoint, Decimal, PluginInfo, PluginType, Request, TimeRange,
+};
+use chrono::NaiveDate;
 use serde_json::{de::Deserializer, to_writer};
 use std::io::{stdin, stdout, Write};
 
@@ -23,6 +26,12 @@ fn main() {
                     description: format!("Input parsing error: {:?}", e),
                 })?;
 
+                if let Some(range) = req.history {
+                    return Ok(Answer {
+                        answer: assetman_api::Value::Series(fetch_history(&req.arguments, range)?),
+                    });
+                }
+
                 let api_response: serde_json::Value = reqwest::blocking::get("http://data-asg.goldprice.org/dbXRates/EUR").map_err(|e| assetman_api::Error {
                     code: 2,
                     description: format!("Request error: {:?}", e),
@@ -48,7 +57,13 @@ fn main() {
                     }
                 };
 
-                Ok(Answer { answer: price.unwrap().as_f64().unwrap() })
+                Ok(Answer {
+                    answer: price
+                        .and_then(|price| price.as_f64())
+                        .and_then(from_float)
+                        .unwrap()
+                        .into(),
+                })
             }
         )
         .for_each(|resp| {
@@ -56,3 +71,71 @@ fn main() {
             stdout.flush().unwrap();
         });
 }
+
+/// Fetches the daily LBMA prices in EUR, the current price API doesn't offer any history
+fn fetch_history(metal: &str, range: TimeRange) -> Result<Vec<DataPoint>, assetman_api::Error> {
+    let url = match metal {
+        "gold" => "https://prices.lbma.org.uk/json/gold_pm.json",
+        "silver" => "https://prices.lbma.org.uk/json/silver.json",
+        _ => {
+            return Err(assetman_api::Error {
+                code: 4,
+                description: "invalid argument, only silver or gold are valid".into(),
+            });
+        }
+    };
+
+    let api_response: serde_json::Value = reqwest::blocking::get(url)
+        .map_err(|e| assetman_api::Error {
+            code: 2,
+            description: format!("Request error: {:?}", e),
+        })?
+        .json()
+        .map_err(|e| assetman_api::Error {
+            code: 3,
+            description: format!("Response parsing error: {:?}", e),
+        })?;
+
+    let days = api_response.as_array().ok_or(assetman_api::Error {
+        code: 5,
+        description: "Response parsing error: expected a list of days".into(),
+    })?;
+
+    let mut series = Vec::new();
+    for day in days {
+        // every entry looks like {"d": "2020-07-31", "v": [usd, gbp, eur]}, days before the euro
+        // existed have an EUR price of 0
+        let date = day.get("d").and_then(|d| d.as_str());
+        let price = day
+            .get("v")
+            .and_then(|v| v.get(2))
+            .and_then(|p| p.as_f64())
+            .and_then(from_float);
+        let (date, price) = match (date, price) {
+            (Some(date), Some(price)) => (date, price),
+            _ => {
+                return Err(assetman_api::Error {
+                    code: 5,
+                    description: format!("Response parsing error: malformed entry {}", day),
+                })
+            }
+        };
+
+        let timestamp = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|e| assetman_api::Error {
+                code: 5,
+                description: format!("Response parsing error: malformed date: {:?}", e),
+            })?
+            .and_hms(0, 0, 0)
+            .timestamp();
+
+        if price > Decimal::ZERO && timestamp >= range.start && timestamp <= range.end {
+            series.push(DataPoint {
+                timestamp,
+                value: price,
+            });
+        }
+    }
+
+    Ok(series)
+}
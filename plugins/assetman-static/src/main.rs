@@ -1,4 +1,4 @@
-use assetman_api::{Answer, PluginInfo, PluginType, Request};
+use assetman_api::{Answer, Decimal, PluginInfo, PluginType, Request};
 use serde_json::{de::Deserializer, to_writer};
 use std::io::{stdin, stdout, Write};
 
@@ -23,12 +23,12 @@ fn main() {
                     description: format!("Input parsing error: {:?}", e),
                 })?;
 
-                let amt = req.arguments.parse().map_err(|e| assetman_api::Error {
+                let amt: Decimal = req.arguments.parse().map_err(|e| assetman_api::Error {
                     code: 2,
                     description: format!("Amount parsing error: {:?}", e),
                 })?;
 
-                Ok(Answer { answer: amt })
+                Ok(Answer { answer: amt.into() })
             },
         )
         .for_each(|resp| {